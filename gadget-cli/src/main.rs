@@ -0,0 +1,419 @@
+//! Inspects, validates, and runs contraptions without a browser
+
+use fnv::FnvHashMap;
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+
+use gadget_core::bit_serde;
+use gadget_core::document;
+use gadget_core::gadget::Gadget;
+use gadget_core::grid::{Grid, XY};
+use gadget_core::math::Vec2;
+use gadget_core::preset_gadgets::preset_gadgets;
+use gadget_core::render::raster;
+use gadget_core::script::{self, Script};
+use gadget_core::share;
+
+const USAGE: &str = "\
+Usage: gadget-cli <command> <contraption> [<script> | <options>]
+
+Commands:
+    summary <contraption>          Prints what the contraption is made of
+    validate <contraption>         Checks that the contraption loads and survives sharing,
+                                   and that the replay in its link, if any, fits it
+    run <contraption> <script>     Runs a script of agent moves on the contraption,
+                                   failing at the first expectation that isn't met
+    png <contraption> [--size <width>x<height>] [--bounds <x0>,<y0>,<x1>,<y1>]
+                                   Draws the contraption as a PNG image to standard output.
+                                   The bounds are in cells and default to the whole
+                                   contraption, and the size defaults to 64 pixels per cell.
+                                   Images are at most 4096 pixels on a side.
+
+A contraption is a share link, the hash of one, or a file with either of those
+or a RON contraption in it.";
+
+/// Reads an argument that is either a file name or the contents themselves
+fn read_argument(arg: &str) -> Result<String, String> {
+    if Path::new(arg).is_file() {
+        fs::read_to_string(arg).map_err(|e| format!("Failed to read {}: {}", arg, e))
+    } else {
+        Ok(arg.to_string())
+    }
+}
+
+/// Loads a contraption from a share link, its hash, or a RON document
+fn load_contraption(text: &str) -> Result<Grid<Gadget>, String> {
+    let text = text.trim();
+
+    // RON documents are parenthesized, and links never are
+    if text.starts_with('(') {
+        document::grid_from_ron(text)
+    } else {
+        share::grid_from_url(text)
+    }
+}
+
+/// The number of pixels per cell in PNG images, when no size is given
+const PNG_CELL_SIZE: usize = 64;
+
+/// The maximum width and height of PNG images, in pixels,
+/// so sparse contraptions don't need huge images
+const PNG_MAX_SIZE: usize = 4096;
+
+/// Gets the corners of the smallest rectangle of cells around the gadgets in a contraption
+fn bounds(grid: &Grid<Gadget>) -> Option<(XY, XY)> {
+    if grid.is_empty() {
+        return None;
+    }
+
+    let min_x = grid.iter().map(|(_, xy, _)| xy.x).min().unwrap();
+    let min_y = grid.iter().map(|(_, xy, _)| xy.y).min().unwrap();
+    let max_x = grid
+        .iter()
+        .map(|(_, xy, (w, _))| xy.x + *w as isize)
+        .max()
+        .unwrap();
+    let max_y = grid
+        .iter()
+        .map(|(_, xy, (_, h))| xy.y + *h as isize)
+        .max()
+        .unwrap();
+
+    Some((XY::new(min_x, min_y), XY::new(max_x, max_y)))
+}
+
+/// Parses a list of numbers separated by `separator`, such as "3x2"
+fn parse_numbers<T: std::str::FromStr>(
+    text: &str,
+    separator: char,
+    count: usize,
+    what: &str,
+) -> Result<Vec<T>, String> {
+    let numbers = text
+        .split(separator)
+        .map(|n| n.trim().parse::<T>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("Invalid {}: {}", what, text))?;
+
+    if numbers.len() != count {
+        return Err(format!("Invalid {}: {}", what, text));
+    }
+    Ok(numbers)
+}
+
+/// Draws a contraption as a PNG image, with the options after the contraption
+/// in the `png` command
+fn png(grid: &Grid<Gadget>, options: &[String]) -> Result<Vec<u8>, String> {
+    let mut size = None;
+    let mut corners = None;
+
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let value = options
+            .next()
+            .ok_or_else(|| format!("Missing value for {}", option))?;
+
+        match option.as_str() {
+            "--size" => {
+                let wh = parse_numbers::<usize>(value, 'x', 2, "size")?;
+                if wh.contains(&0) {
+                    return Err(format!("Invalid size: {}", value));
+                }
+                size = Some((wh[0], wh[1]));
+            }
+
+            "--bounds" => {
+                let b = parse_numbers::<f64>(value, ',', 4, "bounds")?;
+                if !(b[0] < b[2] && b[1] < b[3]) || b.iter().any(|n| !n.is_finite()) {
+                    return Err(format!("Invalid bounds: {}", value));
+                }
+                corners = Some((Vec2::new(b[0], b[1]), Vec2::new(b[2], b[3])));
+            }
+
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    let (min, max) = corners.unwrap_or_else(|| {
+        let (min, max) = bounds(grid).unwrap_or((XY::new(0, 0), XY::new(1, 1)));
+        (min.cast::<f64>().unwrap(), max.cast::<f64>().unwrap())
+    });
+    let size = size.unwrap_or_else(|| {
+        (
+            ((max.x - min.x) * PNG_CELL_SIZE as f64).ceil() as usize,
+            ((max.y - min.y) * PNG_CELL_SIZE as f64).ceil() as usize,
+        )
+    });
+
+    if size.0 > PNG_MAX_SIZE || size.1 > PNG_MAX_SIZE {
+        return Err(format!(
+            "The image would be {}x{} pixels, but it can be at most {} pixels on a side",
+            size.0, size.1, PNG_MAX_SIZE
+        ));
+    }
+
+    Ok(raster::grid_to_png(grid, min, max, size))
+}
+
+/// Describes a number of things
+fn count(n: usize, thing: &str) -> String {
+    if n == 1 {
+        format!("1 {}", thing)
+    } else {
+        format!("{} {}s", n, thing)
+    }
+}
+
+/// Describes the gadgets in a contraption
+fn summary(grid: &Grid<Gadget>) -> String {
+    let mut lines = vec![count(grid.iter().count(), "gadget")];
+
+    if let Some((min, max)) = bounds(grid) {
+        lines.push(format!(
+            "{}x{} cells, from ({}, {}) to ({}, {})",
+            max.x - min.x,
+            max.y - min.y,
+            min.x,
+            min.y,
+            max.x - 1,
+            max.y - 1
+        ));
+    }
+
+    // Gadgets with the same name and behavior are the same kind
+    let mut kinds = FnvHashMap::default();
+    for (gadget, _, _) in grid.iter() {
+        let key = (gadget.name().to_string(), gadget.def().hash_string());
+        kinds.entry(key).or_insert((gadget, 0)).1 += 1;
+    }
+
+    let mut kinds = kinds.into_iter().collect::<Vec<_>>();
+    kinds.sort_by(|((name0, _), (_, n0)), ((name1, _), (_, n1))| {
+        n1.cmp(n0).then_with(|| name0.cmp(name1))
+    });
+
+    for ((name, _), (gadget, n)) in kinds {
+        let name = if name.is_empty() { "Gadget" } else { &name };
+
+        lines.push(format!(
+            "  {} x {} ({}, {})",
+            n,
+            name,
+            count(gadget.def().num_states(), "state"),
+            count(gadget.def().num_ports(), "port")
+        ));
+    }
+
+    lines.join("\n")
+}
+
+/// Checks that a contraption comes back the same after being shared, except for names
+fn check_round_trip(grid: &Grid<Gadget>) -> Result<(), String> {
+    let hash = bit_serde::to_versioned_base64(grid).map_err(|e| e.to_string())?;
+    let shared = share::grid_from_url(&hash)?;
+
+    // Links don't keep gadget names
+    let unnamed = |grid: &Grid<Gadget>| {
+        grid.iter()
+            .map(|(gadget, xy, wh)| (gadget.clone().name_this(""), *xy, *wh))
+            .collect::<Grid<_>>()
+    };
+
+    if document::grid_to_ron(&unnamed(&shared))? != document::grid_to_ron(&unnamed(grid))? {
+        return Err("The contraption changes when it is shared".to_string());
+    }
+    Ok(())
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let (command, contraption) = match args {
+        [command, contraption, ..] => (command.as_str(), read_argument(contraption)?),
+        _ => return Err(USAGE.to_string()),
+    };
+
+    let mut grid = load_contraption(&contraption)?;
+
+    match (command, &args[2..]) {
+        ("summary", []) => {
+            println!("{}", summary(&grid));
+
+            if let Some(palette) = share::palette_from_url(contraption.trim()) {
+                println!("{} in the palette", count(palette?.len(), "custom gadget"));
+            }
+        }
+
+        ("validate", []) => {
+            if let Some(palette) = share::palette_from_url(contraption.trim()) {
+                palette.map_err(|e| format!("Invalid palette: {}", e))?;
+            }
+            check_round_trip(&grid)?;
+
+            println!("Valid, with {}", count(grid.iter().count(), "gadget"));
+
+            if let Some(replay) = share::replay_from_url(contraption.trim()) {
+                let replay = replay.map_err(|e| format!("Invalid replay: {}", e))?;
+                replay
+                    .rewind(&mut grid)
+                    .map_err(|e| format!("Invalid replay: {}", e))?;
+
+                println!(
+                    "With a replay of {} by {}",
+                    count(replay.len(), "move"),
+                    count(replay.starts().len(), "agent")
+                );
+            }
+        }
+
+        ("run", [script]) => {
+            let script = Script::parse(&read_argument(script)?)?;
+            let mut step_index = 0;
+
+            let agent = script
+                .run(&mut grid, |step, agent, changed| {
+                    step_index += 1;
+                    let position = agent.position();
+
+                    let mut line = format!(
+                        "{}. {}: agent at ({}, {}) facing {}",
+                        step_index,
+                        script::step_string(step),
+                        position.x,
+                        position.y,
+                        script::direction_name(agent.direction())
+                    );
+                    if let Some((xy, previous)) = changed {
+                        line +=
+                            &format!(", gadget at ({}, {}) was in state {}", xy.x, xy.y, previous);
+                    }
+                    println!("{}", line);
+                })
+                .map_err(|failure| failure.to_string())?;
+
+            let position = agent.position();
+            println!(
+                "Agent ends at ({}, {}) facing {}",
+                position.x,
+                position.y,
+                script::direction_name(agent.direction())
+            );
+            if script.num_expectations() > 0 {
+                println!("{} met", count(script.num_expectations(), "expectation"));
+            }
+        }
+
+        ("png", options) => {
+            // Registers the renderers of the presets, so they are drawn like in the app
+            preset_gadgets();
+
+            io::stdout()
+                .write_all(&png(&grid, options)?)
+                .map_err(|e| format!("Failed to write the image: {}", e))?;
+        }
+
+        _ => return Err(USAGE.to_string()),
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+
+    if let Err(e) = run(&args) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gadget_core::gadget::{GadgetDef, State};
+    use gadget_core::spsp_multi;
+
+    use cgmath::vec2;
+    use std::rc::Rc;
+
+    fn toggle() -> Gadget {
+        let def = GadgetDef::from_traversals(2, 2, spsp_multi![((0, 0), (1, 1)), ((1, 1), (0, 0))]);
+        Gadget::new(&Rc::new(def), (1, 1), vec![0, 2], State(0)).name_this("Toggle")
+    }
+
+    #[test]
+    fn test_load_contraption() {
+        let mut grid = Grid::new();
+        grid.insert(toggle(), vec2(1, 2), (1, 1));
+
+        let ron = document::grid_to_ron(&grid).unwrap();
+        let hash = bit_serde::to_versioned_base64(&grid).unwrap();
+        let link = format!("https://example.com/#{}\n", hash);
+
+        for text in &[ron, hash, link] {
+            let loaded = load_contraption(text).unwrap();
+            assert!(loaded.get(vec2(1, 2)).is_some());
+            check_round_trip(&loaded).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut grid = Grid::new();
+        grid.insert(toggle(), vec2(0, 0), (1, 1));
+        grid.insert(toggle(), vec2(2, 1), (1, 1));
+
+        assert_eq!(
+            summary(&grid),
+            "2 gadgets\n3x2 cells, from (0, 0) to (2, 1)\n  2 x Toggle (2 states, 2 ports)"
+        );
+        assert_eq!(summary(&Grid::new()), "0 gadgets");
+    }
+
+    #[test]
+    fn test_png() {
+        preset_gadgets();
+
+        let mut grid = Grid::new();
+        grid.insert(toggle(), vec2(1, 2), (1, 1));
+        grid.insert(toggle(), vec2(2, 3), (1, 1));
+
+        let options = |options: &[&str]| options.iter().map(|o| o.to_string()).collect::<Vec<_>>();
+        // The width and height are right after the signature and the header's length and type
+        let size = |png: &[u8]| {
+            let int = |i: usize| u32::from_be_bytes([png[i], png[i + 1], png[i + 2], png[i + 3]]);
+            (int(16), int(20))
+        };
+
+        let whole = png(&grid, &[]).unwrap();
+        assert!(whole.starts_with(b"\x89PNG\r\n\x1a\n"));
+        assert_eq!(size(&whole), (128, 128));
+
+        let bounded = png(&grid, &options(&["--bounds", "1,2,2,3"])).unwrap();
+        assert_eq!(size(&bounded), (64, 64));
+
+        let sized = png(&grid, &options(&["--size", "30x20", "--bounds", "0,0,3,2"])).unwrap();
+        assert_eq!(size(&sized), (30, 20));
+
+        assert_eq!(size(&png(&Grid::new(), &[]).unwrap()), (64, 64));
+
+        // Gadgets far apart would need a huge image
+        let mut sparse = grid.clone();
+        sparse.insert(toggle(), vec2(100000, 3), (1, 1));
+        assert!(png(&sparse, &[]).is_err());
+
+        for bad in &[
+            &["--size"][..],
+            &["--size", "0x10"],
+            &["--size", "10"],
+            &["--bounds", "1,2,1,3"],
+            &["--bounds", "a,b,c,d"],
+            &["--bounds", "0,0,inf,inf"],
+            &["--size", "100000x10"],
+            &["--scale", "2"],
+        ] {
+            assert!(png(&grid, &options(bad)).is_err());
+        }
+    }
+}
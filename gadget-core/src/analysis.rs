@@ -0,0 +1,193 @@
+use fnv::FnvHashSet;
+
+use crate::gadget::{GadgetDef, Port, SPSP};
+
+/// Whether each (state, port) pair has at most 1 target
+pub fn is_deterministic(def: &GadgetDef) -> bool {
+    let mut sources = FnvHashSet::default();
+    def.traversals().all(|(sp0, _)| sources.insert(*sp0))
+}
+
+/// Whether every traversal can be undone by going back the way the agent came:
+/// (s0, p0) -> (s1, p1) implies (s1, p1) -> (s0, p0)
+pub fn is_reversible(def: &GadgetDef) -> bool {
+    has_all(def, |(sp0, sp1)| (sp1, sp0))
+}
+
+/// Whether the def looks the same when time runs backward:
+/// (s0, p0) -> (s1, p1) implies (s1, p0) -> (s0, p1)
+pub fn is_time_symmetric(def: &GadgetDef) -> bool {
+    has_all(def, |((s0, p0), (s1, p1))| ((s1, p0), (s0, p1)))
+}
+
+/// Whether every traversal can also be done in the opposite direction with the same state change:
+/// (s0, p0) -> (s1, p1) implies (s0, p1) -> (s1, p0)
+pub fn is_bidirectional(def: &GadgetDef) -> bool {
+    has_all(def, |((s0, p0), (s1, p1))| ((s0, p1), (s1, p0)))
+}
+
+/// Whether the def has the traversal `f` maps each of its traversals to
+fn has_all(def: &GadgetDef, f: impl Fn(SPSP) -> SPSP) -> bool {
+    let traversals = def.traversals().copied().collect::<FnvHashSet<_>>();
+    traversals.iter().all(|t| traversals.contains(&f(*t)))
+}
+
+/// Gets the locations where traversals change the state, sorted.
+/// A location is an unordered pair of ports, given with the smaller port first.
+pub fn state_changing_locations(def: &GadgetDef) -> Vec<(Port, Port)> {
+    let mut locations = def
+        .traversals()
+        .filter(|((s0, _), (s1, _))| s0 != s1)
+        .map(|((_, p0), (_, p1))| (*p0.min(p1), *p0.max(p1)))
+        .collect::<Vec<_>>();
+
+    locations.sort();
+    locations.dedup();
+    locations
+}
+
+/// Standard properties of a gadget def
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Properties {
+    pub deterministic: bool,
+    pub reversible: bool,
+    pub time_symmetric: bool,
+    pub bidirectional: bool,
+    pub state_changing_locations: Vec<(Port, Port)>,
+}
+
+impl Properties {
+    pub fn of(def: &GadgetDef) -> Self {
+        Self {
+            deterministic: is_deterministic(def),
+            reversible: is_reversible(def),
+            time_symmetric: is_time_symmetric(def),
+            bidirectional: is_bidirectional(def),
+            state_changing_locations: state_changing_locations(def),
+        }
+    }
+
+    /// Whether no traversal changes the state
+    pub fn is_stateless(&self) -> bool {
+        self.state_changing_locations.is_empty()
+    }
+
+    /// Whether all state changes happen at the same location
+    pub fn has_single_state_changing_location(&self) -> bool {
+        self.state_changing_locations.len() <= 1
+    }
+
+    /// Describes the properties, 1 per line
+    pub fn describe(&self) -> String {
+        let mut lines = vec![];
+
+        for (name, has) in &[
+            ("deterministic", self.deterministic),
+            ("reversible", self.reversible),
+            ("time-symmetric", self.time_symmetric),
+            ("bidirectional", self.bidirectional),
+        ] {
+            lines.push(if *has {
+                format!("Is {}", name)
+            } else {
+                format!("Not {}", name)
+            });
+        }
+
+        lines.push(match self.state_changing_locations.len() {
+            0 => "Never changes state".to_string(),
+            1 => "Changes state at 1 location".to_string(),
+            n => format!("Changes state at {} locations", n),
+        });
+
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::spsp_multi;
+
+    fn toggle() -> GadgetDef {
+        GadgetDef::from_traversals(2, 2, spsp_multi![((0, 0), (1, 1)), ((1, 1), (0, 0))])
+    }
+
+    fn locking_2_toggle() -> GadgetDef {
+        GadgetDef::from_traversals(
+            3,
+            4,
+            spsp_multi![
+                ((1, 0), (0, 1)),
+                ((0, 1), (1, 0)),
+                ((1, 2), (2, 3)),
+                ((2, 3), (1, 2)),
+            ],
+        )
+    }
+
+    fn wire() -> GadgetDef {
+        GadgetDef::from_traversals(1, 2, spsp_multi![((0, 0), (0, 1)), ((0, 1), (0, 0))])
+    }
+
+    #[test]
+    fn test_deterministic() {
+        assert!(is_deterministic(&toggle()));
+
+        let def = GadgetDef::from_traversals(2, 3, spsp_multi![((0, 0), (0, 1)), ((0, 0), (1, 2))]);
+        assert!(!is_deterministic(&def));
+    }
+
+    #[test]
+    fn test_reversible() {
+        assert!(is_reversible(&toggle()));
+        assert!(is_reversible(&locking_2_toggle()));
+
+        let def = GadgetDef::from_traversals(2, 2, spsp_multi![((0, 0), (1, 1))]);
+        assert!(!is_reversible(&def));
+    }
+
+    #[test]
+    fn test_time_symmetric() {
+        let def = GadgetDef::from_traversals(2, 2, spsp_multi![((0, 0), (1, 1)), ((1, 0), (0, 1))]);
+        assert!(is_time_symmetric(&def));
+        assert!(!is_time_symmetric(&toggle()));
+    }
+
+    #[test]
+    fn test_bidirectional() {
+        assert!(is_bidirectional(&wire()));
+        assert!(!is_bidirectional(&toggle()));
+    }
+
+    #[test]
+    fn test_state_changing_locations() {
+        assert_eq!(state_changing_locations(&wire()), vec![]);
+        assert_eq!(
+            state_changing_locations(&toggle()),
+            vec![(Port(0), Port(1))]
+        );
+        assert_eq!(
+            state_changing_locations(&locking_2_toggle()),
+            vec![(Port(0), Port(1)), (Port(2), Port(3))]
+        );
+    }
+
+    #[test]
+    fn test_properties() {
+        let properties = Properties::of(&toggle());
+        assert!(properties.deterministic);
+        assert!(!properties.is_stateless());
+        assert!(properties.has_single_state_changing_location());
+        assert!(!Properties::of(&locking_2_toggle()).has_single_state_changing_location());
+
+        assert_eq!(
+            properties.describe(),
+            "Is deterministic\n\
+             Is reversible\n\
+             Not time-symmetric\n\
+             Not bidirectional\n\
+             Changes state at 1 location"
+        );
+    }
+}
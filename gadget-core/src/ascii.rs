@@ -0,0 +1,628 @@
+//! Plain-text drawings of contraptions.
+//!
+//! Each cell of the grid is drawn as a box 5 characters wide and 3 lines tall,
+//! and bigger gadgets are drawn as bigger boxes:
+//!
+//! ```text
+//! +-0-++-0-+
+//! |A1 ||B0 1
+//! +-1-++---+
+//!
+//! A: Toggle (2 states)
+//! B: Turn (1 state)
+//! Origin: (0, 0)
+//! ```
+//!
+//! A box is labeled with its gadget's symbol and state,
+//! and each port is marked on the boundary with its number.
+//! The legend maps symbols to gadget names, and gives the coordinates of the bottom left cell.
+
+use cgmath::vec2;
+use fnv::FnvHashMap;
+
+use crate::gadget::{Gadget, State};
+use crate::grid::{Grid, XY};
+
+/// Width of a cell, in characters
+const CELL_W: usize = 5;
+/// Height of a cell, in lines
+const CELL_H: usize = 3;
+
+/// Symbols for kinds of gadgets, in order of use
+const SYMBOLS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Gets the character a port is marked with
+fn port_marker(port: usize) -> char {
+    std::char::from_digit(port as u32, 36).unwrap_or('*')
+}
+
+/// Gets the (row, column) of a position index along the perimeter of a box
+/// with top left corner (r0, c0) that is (w, h) cells big
+fn marker_location(
+    index: usize,
+    (r0, c0): (usize, usize),
+    (w, h): (usize, usize),
+) -> (usize, usize) {
+    let r1 = r0 + CELL_H * h - 1;
+    let c1 = c0 + CELL_W * w - 1;
+
+    if index < w {
+        // Bottom edge, left to right
+        (r1, c0 + CELL_W * index + CELL_W / 2)
+    } else if index < w + h {
+        // Right edge, bottom to top
+        (r1 - CELL_H * (index - w) - CELL_H / 2, c1)
+    } else if index < 2 * w + h {
+        // Top edge, right to left
+        (r0, c0 + CELL_W * (2 * w + h - 1 - index) + CELL_W / 2)
+    } else {
+        // Left edge, top to bottom
+        (r1 - CELL_H * (2 * w + 2 * h - 1 - index) - CELL_H / 2, c0)
+    }
+}
+
+/// Describes a number of states
+fn states_string(num_states: usize) -> String {
+    if num_states == 1 {
+        "1 state".to_string()
+    } else {
+        format!("{} states", num_states)
+    }
+}
+
+/// Draws a grid as text, followed by a legend
+pub fn grid_to_ascii(grid: &Grid<Gadget>) -> Result<String, String> {
+    if grid.is_empty() {
+        return Ok(String::new());
+    }
+
+    let min_x = grid.iter().map(|(_, xy, _)| xy.x).min().unwrap();
+    let min_y = grid.iter().map(|(_, xy, _)| xy.y).min().unwrap();
+    let max_x = grid
+        .iter()
+        .map(|(_, xy, (w, _))| xy.x + *w as isize)
+        .max()
+        .unwrap();
+    let max_y = grid
+        .iter()
+        .map(|(_, xy, (_, h))| xy.y + *h as isize)
+        .max()
+        .unwrap();
+
+    let rows = CELL_H * (max_y - min_y) as usize;
+    let cols = CELL_W * (max_x - min_x) as usize;
+    let mut canvas = vec![vec![' '; cols]; rows];
+
+    // Top to bottom, then left to right, so symbols are in reading order
+    let mut sorted = grid.iter().collect::<Vec<_>>();
+    sorted.sort_by_key(|(_, xy, _)| (-xy.y, xy.x));
+
+    let mut symbols = SYMBOLS.chars();
+    let mut kinds = FnvHashMap::default();
+    let mut legend = vec![];
+
+    for (gadget, xy, (w, h)) in sorted {
+        let key = (gadget.name().to_string(), gadget.def().hash_string());
+        let symbol = match kinds.get(&key) {
+            Some(symbol) => *symbol,
+            None => {
+                let symbol = symbols
+                    .next()
+                    .ok_or_else(|| "Too many kinds of gadgets to draw".to_string())?;
+
+                let name = if gadget.name().is_empty() {
+                    "Gadget"
+                } else {
+                    gadget.name()
+                };
+                legend.push(format!(
+                    "{}: {} ({})",
+                    symbol,
+                    name,
+                    states_string(gadget.def().num_states())
+                ));
+
+                kinds.insert(key, symbol);
+                symbol
+            }
+        };
+
+        let r0 = CELL_H * (max_y - xy.y) as usize - CELL_H * h;
+        let c0 = CELL_W * (xy.x - min_x) as usize;
+        let r1 = r0 + CELL_H * h - 1;
+        let c1 = c0 + CELL_W * w - 1;
+
+        for r in &[r0, r1] {
+            for cell in &mut canvas[*r][c0..=c1] {
+                *cell = '-';
+            }
+        }
+        for row in &mut canvas[r0..=r1] {
+            row[c0] = '|';
+            row[c1] = '|';
+        }
+        for (r, c) in &[(r0, c0), (r0, c1), (r1, c0), (r1, c1)] {
+            canvas[*r][*c] = '+';
+        }
+
+        for (port, index) in gadget.port_map().iter().enumerate() {
+            let (r, c) = marker_location(*index, (r0, c0), (*w, *h));
+            canvas[r][c] = port_marker(port);
+        }
+
+        let label = format!("{}{}", symbol, gadget.state().id());
+        if label.len() > CELL_W * w - 2 {
+            return Err(format!(
+                "Gadget at ({}, {}) has too many states to label",
+                xy.x, xy.y
+            ));
+        }
+        for (i, c) in label.chars().enumerate() {
+            canvas[r0 + 1][c0 + 1 + i] = c;
+        }
+    }
+
+    let mut lines = canvas
+        .into_iter()
+        .map(|row| row.into_iter().collect::<String>().trim_end().to_string())
+        .collect::<Vec<_>>();
+
+    lines.push(String::new());
+    lines.extend(legend);
+    lines.push(format!("Origin: ({}, {})", min_x, min_y));
+
+    Ok(lines.join("\n") + "\n")
+}
+
+/// Parses the legend line "Origin: (x, y)"
+fn parse_origin(string: &str) -> Option<XY> {
+    let string = string.trim();
+    if !string.starts_with('(') || !string.ends_with(')') || string.len() < 2 {
+        return None;
+    }
+    let string = &string[1..string.len() - 1];
+    let mut coords = string.split(',').map(|c| c.trim().parse::<isize>());
+
+    let x = coords.next()?.ok()?;
+    let y = coords.next()?.ok()?;
+    if coords.next().is_some() {
+        return None;
+    }
+    Some(vec2(x, y))
+}
+
+/// The symbol, the state, and the (port, position index) markers in a cell of a drawing
+type ParsedCell = (char, usize, Vec<(usize, usize)>);
+
+/// Reads the cell whose box has top left corner (r0, c0) in the drawing.
+/// Returns None if the cell is empty.
+/// Errors have the (row, column) of the problem.
+fn parse_cell(
+    drawing: &[Vec<char>],
+    (r0, c0): (usize, usize),
+) -> Result<Option<ParsedCell>, ((usize, usize), String)> {
+    let at = |r: usize, c: usize| {
+        drawing
+            .get(r0 + r)
+            .and_then(|row| row.get(c0 + c))
+            .copied()
+            .unwrap_or(' ')
+    };
+
+    let is_empty = (0..CELL_H).all(|r| (0..CELL_W).all(|c| at(r, c).is_whitespace()));
+    if is_empty {
+        return Ok(None);
+    }
+
+    let mut markers = vec![];
+
+    for r in 0..CELL_H {
+        for c in 0..CELL_W {
+            let on_row = r == 0 || r == CELL_H - 1;
+            let on_col = c == 0 || c == CELL_W - 1;
+            let ch = at(r, c);
+
+            let expected = match (on_row, on_col) {
+                (true, true) => '+',
+                (true, false) => '-',
+                (false, true) => '|',
+                (false, false) => continue,
+            };
+            if ch == expected {
+                continue;
+            }
+
+            let index = if r == CELL_H - 1 && c == CELL_W / 2 {
+                Some(0)
+            } else if c == CELL_W - 1 && r == CELL_H / 2 {
+                Some(1)
+            } else if r == 0 && c == CELL_W / 2 {
+                Some(2)
+            } else if c == 0 && r == CELL_H / 2 {
+                Some(3)
+            } else {
+                None
+            };
+
+            match (index, ch.to_digit(36)) {
+                (Some(index), Some(port)) => markers.push((port as usize, index)),
+                _ => {
+                    return Err((
+                        (r0 + r, c0 + c),
+                        format!("Expected {:?} but found {:?}", expected, ch),
+                    ))
+                }
+            }
+        }
+    }
+
+    let label = (1..CELL_W - 1)
+        .map(|c| at(CELL_H / 2, c))
+        .collect::<String>();
+    let label = label.trim();
+
+    let mut chars = label.chars();
+    let symbol = chars.next().ok_or_else(|| {
+        (
+            (r0 + CELL_H / 2, c0 + 1),
+            "Missing gadget symbol".to_string(),
+        )
+    })?;
+
+    let state = chars.as_str();
+    let state = if state.is_empty() {
+        0
+    } else {
+        state.parse().map_err(|_| {
+            (
+                (r0 + CELL_H / 2, c0 + 1),
+                format!("Label {:?} is not a symbol followed by a state", label),
+            )
+        })?
+    };
+
+    Ok(Some((symbol, state, markers)))
+}
+
+/// Builds a 1x1 gadget from a preset, a state, and (port, position index) markers.
+/// Without markers, the preset's port map is used.
+fn build_gadget(
+    preset: &Gadget,
+    state: usize,
+    markers: &[(usize, usize)],
+) -> Result<Gadget, String> {
+    let def = preset.def();
+
+    if state >= def.num_states() {
+        return Err(format!(
+            "{} has no state {}; it has {}",
+            preset.name(),
+            state,
+            states_string(def.num_states())
+        ));
+    }
+
+    let port_map = if markers.is_empty() {
+        preset.port_map().to_vec()
+    } else {
+        let mut port_map = vec![None; def.num_ports()];
+
+        for (port, index) in markers {
+            match port_map.get_mut(*port) {
+                Some(Some(_)) => return Err(format!("Port {} is marked twice", port)),
+                Some(slot) => *slot = Some(*index),
+                None => {
+                    return Err(format!(
+                        "{} has no port {}; it has {} ports",
+                        preset.name(),
+                        port,
+                        def.num_ports()
+                    ))
+                }
+            }
+        }
+
+        port_map
+            .into_iter()
+            .enumerate()
+            .map(|(port, index)| index.ok_or_else(|| format!("Port {} is not marked", port)))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    Ok(Gadget::new(def, (1, 1), port_map, State(state)).name_this(preset.name()))
+}
+
+/// Reads a grid from a drawing like the ones `grid_to_ascii` makes.
+/// Only 1x1 gadgets can be read, and the legend must name gadgets in `presets`.
+/// Errors start with the line and column of the problem.
+pub fn grid_from_ascii(text: &str, presets: &[Gadget]) -> Result<Grid<Gadget>, String> {
+    let lines = text.lines().enumerate().collect::<Vec<_>>();
+    let at = |(line, col): (usize, usize), e: String| format!("{}:{}: {}", line + 1, col + 1, e);
+
+    // The legend starts at the first line with a colon, and the drawing is before it
+    let legend_start = lines
+        .iter()
+        .position(|(_, line)| line.contains(':'))
+        .unwrap_or(lines.len());
+    let is_blank = |(_, line): &(usize, &str)| line.trim().is_empty();
+    let start = lines[..legend_start]
+        .iter()
+        .position(|line| !is_blank(line))
+        .unwrap_or(legend_start);
+    let end = lines[..legend_start]
+        .iter()
+        .rposition(|line| !is_blank(line))
+        .map_or(start, |i| i + 1);
+
+    let mut legend = FnvHashMap::default();
+    let mut origin = vec2(0, 0);
+
+    for (l, line) in &lines[legend_start..] {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (key, value) = match line.find(':') {
+            Some(i) => (line[..i].trim(), line[i + 1..].trim()),
+            None => return Err(at((*l, 0), "Expected a legend entry".to_string())),
+        };
+
+        if key == "Origin" {
+            origin = parse_origin(value).ok_or_else(|| {
+                at(
+                    (*l, 0),
+                    format!("Origin {:?} is not a pair of integers", value),
+                )
+            })?;
+            continue;
+        }
+
+        let mut symbol = key.chars();
+        let symbol = match (symbol.next(), symbol.next()) {
+            (Some(symbol), None) => symbol,
+            _ => {
+                return Err(at(
+                    (*l, 0),
+                    format!("Symbol {:?} is not a single character", key),
+                ))
+            }
+        };
+
+        // Drop the number of states
+        let name = match value.rfind(" (") {
+            Some(i) if value.ends_with(')') => &value[..i],
+            _ => value,
+        };
+
+        let preset = presets
+            .iter()
+            .find(|gadget| gadget.size() == (1, 1) && gadget.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| at((*l, 0), format!("There is no 1x1 gadget named {:?}", name)))?;
+
+        if legend.insert(symbol, preset).is_some() {
+            return Err(at(
+                (*l, 0),
+                format!("Symbol {:?} is defined more than once", symbol),
+            ));
+        }
+    }
+
+    let drawing = lines[start..end]
+        .iter()
+        .map(|(_, line)| line.chars().collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    if drawing.len() % CELL_H != 0 {
+        return Err(at(
+            (end, 0),
+            format!("The drawing must be a multiple of {} lines tall", CELL_H),
+        ));
+    }
+
+    let rows = drawing.len() / CELL_H;
+    let cols = drawing
+        .iter()
+        .map(|row| row.len().div_ceil(CELL_W))
+        .max()
+        .unwrap_or(0);
+
+    let mut grid = Grid::new();
+
+    for row in 0..rows {
+        for col in 0..cols {
+            let corner = (CELL_H * row, CELL_W * col);
+            let line_of = |(r, c): (usize, usize)| (start + r, c);
+
+            let (symbol, state, markers) = match parse_cell(&drawing, corner) {
+                Ok(Some(cell)) => cell,
+                Ok(None) => continue,
+                Err((location, e)) => return Err(at(line_of(location), e)),
+            };
+
+            let preset = legend.get(&symbol).ok_or_else(|| {
+                at(
+                    line_of(corner),
+                    format!("Symbol {:?} is not in the legend", symbol),
+                )
+            })?;
+
+            let gadget =
+                build_gadget(preset, state, &markers).map_err(|e| at(line_of(corner), e))?;
+
+            let position = origin + vec2(col as isize, (rows - 1 - row) as isize);
+            grid.insert(gadget, position, (1, 1));
+        }
+    }
+
+    Ok(grid)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gadget::GadgetDef;
+    use crate::spsp_multi;
+
+    use std::rc::Rc;
+
+    fn presets() -> Vec<Gadget> {
+        let toggle =
+            GadgetDef::from_traversals(2, 2, spsp_multi![((0, 0), (1, 1)), ((1, 1), (0, 0))]);
+        let turn =
+            GadgetDef::from_traversals(1, 2, spsp_multi![((0, 0), (0, 1)), ((0, 1), (0, 0))]);
+
+        vec![
+            Gadget::new(&Rc::new(toggle), (1, 1), vec![0, 2], State(0)).name_this("Toggle"),
+            Gadget::new(&Rc::new(turn), (1, 1), vec![0, 1], State(0)).name_this("Turn"),
+        ]
+    }
+
+    const DRAWING: &str = "\
++-0-++-0-+
+|A1 ||B0 1
++-1-++---+
+
+A: Toggle (2 states)
+B: Turn (1 state)
+Origin: (0, 0)
+";
+
+    fn test_grid() -> Grid<Gadget> {
+        let presets = presets();
+
+        let toggle = Gadget::new(presets[0].def(), (1, 1), vec![2, 0], State(1));
+        let turn = Gadget::new(presets[1].def(), (1, 1), vec![2, 1], State(0));
+
+        let mut grid = Grid::new();
+        grid.insert(toggle.name_this("Toggle"), vec2(0, 0), (1, 1));
+        grid.insert(turn.name_this("Turn"), vec2(1, 0), (1, 1));
+        grid
+    }
+
+    #[test]
+    fn test_to_ascii() {
+        let grid = test_grid();
+        assert_eq!(grid_to_ascii(&grid).unwrap(), DRAWING);
+    }
+
+    #[test]
+    fn test_to_ascii_big() {
+        let presets = presets();
+        let def = presets[0].def();
+
+        let mut grid = Grid::new();
+        grid.insert(
+            Gadget::new(def, (2, 2), vec![1, 5], State(0)).name_this("Big toggle"),
+            vec2(-1, 3),
+            (2, 2),
+        );
+        grid.insert(presets[1].clone(), vec2(1, 3), (1, 1));
+
+        assert_eq!(
+            grid_to_ascii(&grid).unwrap(),
+            "\
++-1------+
+|A0      |
+|        |
+|        |+---+
+|        ||B0 1
++------0-++-0-+
+
+A: Big toggle (2 states)
+B: Turn (1 state)
+Origin: (-1, 3)
+"
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(grid_to_ascii(&Grid::new()).unwrap(), "");
+        assert!(grid_from_ascii("", &presets()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let grid = grid_from_ascii(DRAWING, &presets()).unwrap();
+        assert_eq!(grid_to_ascii(&grid).unwrap(), DRAWING);
+
+        let toggle = &grid.get(vec2(0, 0)).unwrap().0;
+        assert_eq!(toggle.name(), "Toggle");
+        assert_eq!(toggle.state(), State(1));
+        assert_eq!(toggle.port_map(), &[2, 0]);
+    }
+
+    #[test]
+    fn test_round_trip_gap() {
+        let presets = presets();
+
+        let mut grid = Grid::new();
+        grid.insert(presets[0].clone(), vec2(0, 0), (1, 1));
+        grid.insert(presets[1].clone(), vec2(0, 2), (1, 1));
+
+        let text = grid_to_ascii(&grid).unwrap();
+        assert!(text.contains("\n\n\n\n"));
+
+        let loaded = grid_from_ascii(&text, &presets).unwrap();
+        assert_eq!(grid_to_ascii(&loaded).unwrap(), text);
+        assert_eq!(loaded.get(vec2(0, 2)).unwrap().0.name(), "Turn");
+    }
+
+    #[test]
+    fn test_sketch() {
+        // No port markers, no states, no origin, and no number of states in the legend
+        let sketch = "
++---+
+|A  |
++---+
+          +---+
+          |b  |
+          +---+
+
+A: Turn
+b: toggle
+";
+        let grid = grid_from_ascii(sketch, &presets()).unwrap();
+        assert_eq!(grid.iter().count(), 2);
+
+        let turn = &grid.get(vec2(0, 1)).unwrap().0;
+        assert_eq!(turn.name(), "Turn");
+        assert_eq!(turn.port_map(), &[0, 1]);
+
+        let toggle = &grid.get(vec2(2, 0)).unwrap().0;
+        assert_eq!(toggle.name(), "Toggle");
+        assert_eq!(toggle.state(), State(0));
+    }
+
+    #[test]
+    fn test_invalid() {
+        let presets = presets();
+        let error = |text: &str| grid_from_ascii(text, &presets).unwrap_err();
+
+        assert_eq!(
+            error(&DRAWING.replace("|A1 |", "|A1 #")),
+            "2:5: Expected '|' but found '#'"
+        );
+        assert_eq!(
+            error(&DRAWING.replace("A1", "A2")),
+            "1:1: Toggle has no state 2; it has 2 states"
+        );
+        assert_eq!(
+            error(&DRAWING.replace("+-1-+", "+-0-+")),
+            "1:1: Port 0 is marked twice"
+        );
+        assert_eq!(
+            error(&DRAWING.replace("B: Turn", "C: Turn")),
+            "1:6: Symbol 'B' is not in the legend"
+        );
+        assert_eq!(
+            error(&DRAWING.replace("Toggle", "Door")),
+            "5:1: There is no 1x1 gadget named \"Door\""
+        );
+        assert_eq!(
+            error(&DRAWING.replace("+-1-++---+\n", "")),
+            "3:1: The drawing must be a multiple of 3 lines tall"
+        );
+    }
+}
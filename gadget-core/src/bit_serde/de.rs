@@ -13,6 +13,11 @@ impl<'de> Deserializer<'de> {
     pub fn from_bits(input: &'de BitSlice) -> Self {
         Deserializer { input }
     }
+
+    /// Gets the bits that haven't been deserialized yet
+    pub fn remaining(&self) -> &'de BitSlice {
+        self.input
+    }
 }
 
 pub fn from_bits<'a, T>(bits: &'a BitSlice) -> Result<T>
@@ -60,6 +65,27 @@ impl<'de> Deserializer<'de> {
         Ok(res + ((1u128 << ones) - 1) as u64)
     }
 
+    /// Length first, then each byte in LSB-MSB order
+    fn parse_bytes(&mut self) -> Result<Vec<u8>> {
+        let len = self.parse_uint()? as usize;
+        let num_bits = len.checked_mul(8).ok_or(Error::NumberOverflow)?;
+        if self.input.len() < num_bits {
+            return Err(Error::Eof);
+        }
+
+        let bytes = self.input[..num_bits]
+            .chunks(8)
+            .map(|byte| byte.load_le::<u8>())
+            .collect();
+
+        self.input = &self.input[num_bits..];
+        Ok(bytes)
+    }
+
+    fn parse_string(&mut self) -> Result<String> {
+        String::from_utf8(self.parse_bytes()?).map_err(|e| Error::Message(e.to_string()))
+    }
+
     fn parse_int(&mut self) -> Result<i64> {
         let neg = self.parse_bool()?;
         let abs = self.parse_uint()?;
@@ -155,32 +181,32 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         Err(Error::Unsupported("char".to_string()))
     }
 
-    fn deserialize_str<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        Err(Error::Unsupported("str".to_string()))
+        visitor.visit_string(self.parse_string()?)
     }
 
-    fn deserialize_string<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        Err(Error::Unsupported("string".to_string()))
+        visitor.visit_string(self.parse_string()?)
     }
 
-    fn deserialize_bytes<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        Err(Error::Unsupported("bytes".to_string()))
+        visitor.visit_byte_buf(self.parse_bytes()?)
     }
 
-    fn deserialize_byte_buf<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        Err(Error::Unsupported("byte_buf".to_string()))
+        visitor.visit_byte_buf(self.parse_bytes()?)
     }
 
     fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
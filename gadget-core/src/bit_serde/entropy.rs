@@ -0,0 +1,259 @@
+//! An adaptive binary arithmetic coder for bitstreams.
+//! Each bit is predicted from the few bits before it,
+//! so streams with repeating patterns shrink.
+
+use bitvec::prelude::*;
+
+use super::de::Deserializer;
+use super::error::{Error, Result};
+use super::ser::to_bits;
+
+/// Number of previous bits used to predict the next bit
+const CONTEXT_BITS: usize = 6;
+/// Probabilities are out of 1 << PROB_BITS
+const PROB_BITS: u32 = 12;
+/// How fast probabilities adapt; higher is slower
+const ADAPT_SHIFT: u32 = 4;
+
+const TOP: u64 = (1 << 32) - 1;
+const HALF: u64 = 1 << 31;
+const QUARTER: u64 = 1 << 30;
+
+/// Probabilities that the next bit is 0, for each context
+struct Model {
+    probs: Vec<u64>,
+    context: usize,
+}
+
+impl Model {
+    fn new() -> Self {
+        Self {
+            probs: vec![1 << (PROB_BITS - 1); 1 << CONTEXT_BITS],
+            context: 0,
+        }
+    }
+
+    fn prob_zero(&self) -> u64 {
+        self.probs[self.context]
+    }
+
+    fn update(&mut self, bit: bool) {
+        let prob = &mut self.probs[self.context];
+        if bit {
+            *prob -= *prob >> ADAPT_SHIFT;
+        } else {
+            *prob += ((1 << PROB_BITS) - *prob) >> ADAPT_SHIFT;
+        }
+
+        self.context = ((self.context << 1) | bit as usize) & ((1 << CONTEXT_BITS) - 1);
+    }
+}
+
+/// Gets the last value in the range [low, high] that decodes to a 0 bit
+fn split(low: u64, high: u64, prob_zero: u64) -> u64 {
+    low + (((high - low + 1) * prob_zero) >> PROB_BITS) - 1
+}
+
+struct Encoder {
+    low: u64,
+    high: u64,
+    /// Number of opposite bits to output after the next bit
+    pending: usize,
+    buffer: BitVec,
+}
+
+impl Encoder {
+    fn output(&mut self, bit: bool) {
+        self.buffer.push(bit);
+        for _ in 0..self.pending {
+            self.buffer.push(!bit);
+        }
+        self.pending = 0;
+    }
+
+    fn encode(&mut self, bit: bool, prob_zero: u64) {
+        let split = split(self.low, self.high, prob_zero);
+        if bit {
+            self.low = split + 1;
+        } else {
+            self.high = split;
+        }
+
+        loop {
+            if self.high < HALF {
+                self.output(false);
+            } else if self.low >= HALF {
+                self.output(true);
+                self.low -= HALF;
+                self.high -= HALF;
+            } else if self.low >= QUARTER && self.high < 3 * QUARTER {
+                self.pending += 1;
+                self.low -= QUARTER;
+                self.high -= QUARTER;
+            } else {
+                break;
+            }
+
+            self.low *= 2;
+            self.high = self.high * 2 + 1;
+        }
+    }
+
+    /// Outputs enough bits to pin down a value in the final range,
+    /// no matter what bits come after them
+    fn finish(mut self) -> BitVec {
+        self.pending += 1;
+        self.output(self.low >= QUARTER);
+        self.buffer
+    }
+}
+
+/// Encodes a bitstream. The length of the bitstream comes first.
+pub fn encode(bits: &BitSlice) -> BitVec {
+    let mut encoder = Encoder {
+        low: 0,
+        high: TOP,
+        pending: 0,
+        buffer: to_bits(&bits.len()).expect("Lengths always serialize"),
+    };
+    let mut model = Model::new();
+
+    for bit in bits.iter().copied() {
+        encoder.encode(bit, model.prob_zero());
+        model.update(bit);
+    }
+
+    encoder.finish()
+}
+
+/// Decodes a bitstream from `encode`
+pub fn decode(bits: &BitSlice) -> Result<BitVec> {
+    let mut deserializer = Deserializer::from_bits(bits);
+    let len: usize = serde::Deserialize::deserialize(&mut deserializer)?;
+    let mut input = deserializer.remaining().iter().copied();
+
+    // Bits past the end of the input are 0, and the encoder never needs 32 of them.
+    // Running further past the end means the length is corrupt,
+    // and decoding it would take forever.
+    let mut past_end = 0;
+    let mut next = || match input.next() {
+        Some(bit) => Ok(bit as u64),
+        None if past_end < 32 => {
+            past_end += 1;
+            Ok(0)
+        }
+        None => Err(Error::Corrupt(
+            "entropy-coded bits end too early".to_string(),
+        )),
+    };
+
+    let mut low = 0;
+    let mut high = TOP;
+    let mut value = 0;
+    for _ in 0..32 {
+        value = value * 2 + next()?;
+    }
+
+    let mut model = Model::new();
+    let mut result = BitVec::new();
+
+    for _ in 0..len {
+        let split = split(low, high, model.prob_zero());
+        let bit = value > split;
+        if bit {
+            low = split + 1;
+        } else {
+            high = split;
+        }
+
+        result.push(bit);
+        model.update(bit);
+
+        loop {
+            if high < HALF {
+                // Nothing to subtract
+            } else if low >= HALF {
+                low -= HALF;
+                high -= HALF;
+                value -= HALF;
+            } else if low >= QUARTER && high < 3 * QUARTER {
+                low -= QUARTER;
+                high -= QUARTER;
+                value -= QUARTER;
+            } else {
+                break;
+            }
+
+            low *= 2;
+            high = high * 2 + 1;
+            value = value * 2 + next()?;
+        }
+
+        if value < low || value > high {
+            return Err(Error::Corrupt(
+                "entropy-coded bits are out of range".to_string(),
+            ));
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn round_trip(bits: &BitSlice) {
+        assert_eq!(decode(&encode(bits)).unwrap(), bits);
+    }
+
+    #[test]
+    fn test_empty() {
+        round_trip(&BitVec::new());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip(bits![1]);
+        round_trip(bits![0, 1, 1, 0, 1, 0, 0, 0, 1]);
+
+        // A pseudorandom stream
+        let mut state = 12345u32;
+        let bits = (0..1000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                state & (1 << 16) != 0
+            })
+            .collect::<BitVec>();
+        round_trip(&bits);
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let mut state = 12345u32;
+        let bits = (0..1000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                state & (1 << 16) != 0
+            })
+            .collect::<BitVec>();
+
+        let encoded = encode(&bits);
+        let truncated = &encoded[..encoded.len() / 2];
+        assert!(matches!(decode(truncated), Err(Error::Corrupt(_))));
+
+        // A huge length with nothing after it
+        let mut garbage = to_bits(&(1usize << 40)).unwrap();
+        garbage.extend_from_slice(bits![1, 0, 1]);
+        assert!(matches!(decode(&garbage), Err(Error::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_repetitive_shrinks() {
+        let bits = (0..1000).map(|i| i % 3 == 0).collect::<BitVec>();
+        let encoded = encode(&bits);
+
+        assert!(encoded.len() < bits.len() / 4);
+        assert_eq!(decode(&encoded).unwrap(), bits);
+    }
+}
@@ -11,6 +11,11 @@ pub enum Error {
     Unsupported(String),
     TrailingCharacters,
     NumberOverflow,
+    /// The payload was written by a newer version of the format
+    UnknownVersion(u64),
+    /// The payload isn't valid base64 with padding,
+    /// or its entropy-coded bits end too early or decode out of range
+    Corrupt(String),
 }
 
 impl ser::Error for Error {
@@ -33,6 +38,11 @@ impl Display for Error {
             Error::Unsupported(type_) => f.write_str(&format!("{} is unsupported", type_)),
             Error::TrailingCharacters => f.write_str("not all bits were consumed"),
             Error::NumberOverflow => f.write_str("number is too big or too big on the negative"),
+            Error::UnknownVersion(version) => f.write_str(&format!(
+                "format version {} is newer than this version of the app supports",
+                version
+            )),
+            Error::Corrupt(msg) => f.write_str(&format!("corrupt data: {}", msg)),
         }
     }
 }
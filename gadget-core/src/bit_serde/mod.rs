@@ -0,0 +1,525 @@
+//! This format packs fields really tight,
+//! expecting numbers to be very small,
+//! to keep URLs short
+//!
+//! The entire `GadgetDef` struct consists of small numbers,
+//! and the port map and current state of `Gadget`
+//! also contain small numbers.
+//!
+//! Payloads saved in URLs start with a format version,
+//! so that old URLs keep working when the layout of a struct changes.
+//! Since version 2, they may also be entropy-coded if that makes them shorter.
+
+mod de;
+mod entropy;
+mod error;
+mod ser;
+
+use bitvec::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+pub use de::{from_bits, Deserializer};
+pub use error::{Error, Result};
+pub use ser::{to_bits, Serializer};
+
+const fn base64_map_inv_() -> [u8; 128] {
+    /// Generates the 64 assignments, since for loops aren't allowed
+    macro_rules! assign {
+        ($map:expr, $inv:expr) => { assign!($map, $inv, 0 b b b b b b)};
+
+        ($map:expr, $inv:expr, $num:tt b $($b:tt)*) => {
+            {
+                assign!($map, $inv, ($num * 2) $($b)*);
+                assign!($map, $inv, ($num * 2 + 1) $($b)*);
+            }
+        };
+
+        ($map:expr, $inv:expr, $num:tt) => {
+            $inv[$map[$num] as usize] = $num;
+        }
+    }
+
+    let mut inv_map = [0; 128];
+    assign!(BASE64_MAP, inv_map);
+    inv_map
+}
+
+const BASE64_MAP: &'static [u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+const BASE64_INV: &'static [u8] = &base64_map_inv_();
+
+fn bits_to_base64(bitvec: &BitSlice) -> (String, usize) {
+    let padding = (-(bitvec.len() as isize)).rem_euclid(6) as usize;
+    let base64 = bitvec
+        .chunks(6)
+        .map(|slice| BASE64_MAP[slice.load_le::<usize>()] as char)
+        .collect();
+    (base64, padding)
+}
+
+fn base64_to_bits(s: &str, padding: usize) -> Result<BitVec> {
+    if let Some(c) = s
+        .chars()
+        .find(|c| !c.is_ascii() || (BASE64_INV[*c as usize] == 0 && *c != 'A'))
+    {
+        return Err(Error::Corrupt(format!("'{}' is not a base64 character", c)));
+    }
+
+    let mut bitvec = s
+        .chars()
+        .flat_map(|c| &BASE64_INV[c as usize].bits::<Lsb0>()[..6])
+        .copied()
+        .collect::<BitVec>();
+
+    if padding >= 6 || padding > bitvec.len() {
+        return Err(Error::Corrupt(format!(
+            "{} bits of padding is too many",
+            padding
+        )));
+    }
+    bitvec.truncate(bitvec.len() - padding);
+    Ok(bitvec)
+}
+
+/// Helper function to convert a serializable type to a base64 string,
+/// along with the number of bits added as padding
+pub fn to_base64<T: Serialize>(t: &T) -> Result<(String, usize)> {
+    let bitvec = to_bits(t)?;
+    Ok(bits_to_base64(&bitvec))
+}
+
+/// Helper function to convert a base64 string, along with number of bits of padding, to a deserializable type
+pub fn from_base64<T: DeserializeOwned>(s: &str, padding: usize) -> Result<T> {
+    let bitvec = base64_to_bits(s, padding)?;
+    from_bits(&bitvec)
+}
+
+/// The current format version.
+/// Bump this whenever the layout of a versioned type changes,
+/// and teach its `Versioned::migrate` to decode the old layout.
+pub const VERSION: u64 = 2;
+
+/// The first format version that says whether the payload is entropy-coded
+const FIRST_CODED_VERSION: u64 = 2;
+
+/// Starts a versioned string.
+/// Strings from before versioning don't have it, and are version 0.
+const VERSION_MARKER: char = '~';
+
+/// A type whose layout can change between format versions
+pub trait Versioned: DeserializeOwned {
+    /// Decodes bits in the layout of an older format version
+    fn migrate(version: u64, bits: &BitSlice) -> Result<Self>;
+}
+
+/// Helper function to convert a serializable type to a string with a format version,
+/// ending in the number of bits added as padding
+pub fn to_versioned_base64<T: Serialize>(t: &T) -> Result<String> {
+    let raw = to_bits(t)?;
+    let coded = entropy::encode(&raw);
+    let is_coded = coded.len() < raw.len();
+
+    let mut bitvec = to_bits(&(VERSION, is_coded))?;
+    bitvec.extend_from_slice(if is_coded { &coded } else { &raw });
+
+    let (base64, padding) = bits_to_base64(&bitvec);
+    Ok(format!("{}{}{}", VERSION_MARKER, base64, padding))
+}
+
+/// Helper function to convert a string from `to_versioned_base64`,
+/// or from before versioning, to a deserializable type,
+/// migrating it from an older format version if needed
+pub fn from_versioned_base64<T: Versioned>(s: &str) -> Result<T> {
+    let versioned = s.starts_with(VERSION_MARKER);
+    let s = if versioned {
+        &s[VERSION_MARKER.len_utf8()..]
+    } else {
+        s
+    };
+
+    let mut base64 = s.to_string();
+    let padding = base64
+        .pop()
+        .and_then(|c| c.to_digit(10))
+        .ok_or_else(|| Error::Corrupt("missing the number of bits of padding".to_string()))?;
+    let bitvec = base64_to_bits(&base64, padding as usize)?;
+
+    if !versioned {
+        return T::migrate(0, &bitvec);
+    }
+
+    let mut deserializer = Deserializer::from_bits(&bitvec);
+    let version: u64 = serde::Deserialize::deserialize(&mut deserializer)?;
+
+    if version > VERSION {
+        return Err(Error::UnknownVersion(version));
+    } else if version < FIRST_CODED_VERSION {
+        return T::migrate(version, deserializer.remaining());
+    }
+
+    let is_coded: bool = serde::Deserialize::deserialize(&mut deserializer)?;
+    let bits = if is_coded {
+        entropy::decode(deserializer.remaining())?
+    } else {
+        deserializer.remaining().to_owned()
+    };
+
+    if version == VERSION {
+        from_bits(&bits)
+    } else {
+        T::migrate(version, &bits)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::de::DeserializeOwned;
+    use serde::Deserialize;
+    use std::fmt::Debug;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    enum Enum {
+        Unit,
+        Newtype(u64),
+        Tuple(u64, i64),
+        Struct { a: Option<bool>, b: u64 },
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct UnitStruct;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct NewtypeStruct(u64);
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct TupleStruct(Vec<u64>, i64);
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Struct {
+        a: Option<()>,
+        b: i64,
+        c: (Option<Box<Struct>>, u64),
+    }
+
+    fn round_trip<T: Debug + Serialize + DeserializeOwned + PartialEq>(t: T) {
+        let bits = to_bits(&t).expect(&format!("Failed to serialize {:?}", t));
+        assert_eq!(
+            from_bits::<T>(&bits).unwrap(),
+            t,
+            "{:?} failed to deserialize to {:?}",
+            bits,
+            t
+        );
+
+        let (base64, padding) = to_base64(&t).unwrap();
+        assert_eq!(from_base64::<T>(&base64, padding).unwrap(), t);
+    }
+
+    fn round_trip_bitvec_base64(bits: &BitSlice) {
+        let (base64, padding) = bits_to_base64(&bits);
+        let result = base64_to_bits(&base64, padding).unwrap();
+        assert_eq!(
+            result,
+            bits,
+            "{:?} converted to {:?} instead of {:?}",
+            (base64, padding),
+            &result,
+            bits,
+        );
+    }
+
+    #[test]
+    fn test_base64_regression_1() {
+        // for Struct { a: None, b: 1u64 }
+        round_trip_bitvec_base64(bits![0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn test_bool() {
+        round_trip(false);
+        round_trip(true);
+    }
+
+    #[test]
+    fn test_uint() {
+        for i in 0u64..256u64 {
+            round_trip(i);
+        }
+        round_trip(std::u64::MAX);
+    }
+
+    #[test]
+    fn test_int() {
+        for i in -128i64..128i64 {
+            round_trip(i);
+        }
+        round_trip(std::i64::MIN);
+        round_trip(std::i64::MAX);
+    }
+
+    #[test]
+    fn test_string() {
+        round_trip(String::new());
+        round_trip("Toggle".to_string());
+        round_trip("Ünïcödé ✓".to_string());
+        round_trip(vec!["a".to_string(), "bc".to_string()]);
+    }
+
+    #[test]
+    fn test_string_eof() {
+        let mut bits = to_bits(&"Toggle".to_string()).unwrap();
+        bits.truncate(bits.len() - 1);
+        assert_eq!(from_bits::<String>(&bits), Err(Error::Eof));
+    }
+
+    #[test]
+    fn test_option() {
+        round_trip(None as Option<u64>);
+        round_trip(Some(5i64));
+        round_trip(Some(()));
+        round_trip(Some(None as Option<u64>));
+    }
+
+    #[test]
+    fn test_seq() {
+        round_trip(vec![] as Vec<u64>);
+        round_trip(vec![(), (), ()]);
+        round_trip(vec![1u64, 2, 8, 4]);
+    }
+
+    #[test]
+    fn test_seq_nest() {
+        round_trip(vec![
+            vec![false, true, false],
+            vec![true, true, true],
+            vec![false, true, false],
+        ]);
+    }
+
+    #[test]
+    fn test_tuple() {
+        round_trip(());
+        round_trip((1u64,));
+        round_trip((3u64, 4u64, 7i64, Some(false)));
+    }
+
+    #[test]
+    fn test_tuple_nest() {
+        round_trip(((5i64, 2i64), (None as Option<()>, (), false, true)));
+    }
+
+    #[test]
+    fn test_map() {
+        use fnv::FnvHashMap;
+        round_trip(FnvHashMap::<(), ()>::default());
+        round_trip(
+            [(1u64, false), (7u64, true), (11u64, true)]
+                .iter()
+                .copied()
+                .collect::<FnvHashMap<_, _>>(),
+        );
+    }
+
+    #[test]
+    fn test_enum_unit() {
+        round_trip(Enum::Unit);
+    }
+
+    #[test]
+    fn test_enum_newtype() {
+        round_trip(Enum::Newtype(0));
+        round_trip(Enum::Newtype(43));
+    }
+
+    #[test]
+    fn test_enum_tuple() {
+        round_trip(Enum::Tuple(0, 0));
+        round_trip(Enum::Tuple(8, -1));
+    }
+
+    #[test]
+    fn test_enum_struct() {
+        round_trip(Enum::Struct { a: None, b: 1 });
+        round_trip(Enum::Struct {
+            a: Some(true),
+            b: 8,
+        });
+    }
+
+    #[test]
+    fn test_struct_unit() {
+        round_trip(UnitStruct)
+    }
+
+    #[test]
+    fn test_struct_newtype() {
+        round_trip(NewtypeStruct(0));
+        round_trip(NewtypeStruct(2));
+    }
+
+    #[test]
+    fn test_struct_tuple() {
+        round_trip(TupleStruct(vec![8, 7, 99], 3));
+        round_trip(TupleStruct(vec![], -11));
+    }
+
+    #[test]
+    fn test_struct() {
+        round_trip(Struct {
+            a: None,
+            b: 69,
+            c: (None, 2),
+        });
+        round_trip(Struct {
+            a: Some(()),
+            b: -7,
+            c: (
+                Some(Box::new(Struct {
+                    a: Some(()),
+                    b: 0,
+                    c: (None, 10),
+                })),
+                0,
+            ),
+        });
+    }
+
+    mod golden {
+        use super::super::*;
+        use crate::gadget::{Gadget, GadgetDef, State};
+        use crate::grid::{Grid, WH, XY};
+        use crate::spsp_multi;
+        use cgmath::vec2;
+        use std::rc::Rc;
+
+        fn toggle_def() -> Rc<GadgetDef> {
+            Rc::new(GadgetDef::from_traversals(
+                2,
+                2,
+                spsp_multi![((0, 0), (1, 1)), ((1, 1), (0, 0))],
+            ))
+        }
+
+        fn summary(grid: &Grid<Gadget>) -> Vec<(GadgetDef, WH, Vec<usize>, State, XY)> {
+            grid.iter()
+                .map(|(gadget, xy, _)| {
+                    (
+                        (**gadget.def()).clone(),
+                        gadget.size(),
+                        gadget.port_map().to_vec(),
+                        gadget.state(),
+                        *xy,
+                    )
+                })
+                .collect()
+        }
+
+        fn sorted_summary(grid: &Grid<Gadget>) -> Vec<(GadgetDef, WH, Vec<usize>, State, XY)> {
+            let mut summary = summary(grid);
+            summary.sort_by_key(|(_, _, _, _, xy)| (xy.y, xy.x));
+            summary
+        }
+
+        /// Checks that the grid encodes to `current`,
+        /// and that `current` and the encodings from older versions decode to the grid
+        fn assert_golden(grid: &Grid<Gadget>, current: &str, older: &[&str]) {
+            assert_eq!(to_versioned_base64(grid).unwrap(), current);
+
+            for string in std::iter::once(&current).chain(older) {
+                let decoded = from_versioned_base64::<Grid<Gadget>>(string).unwrap();
+                assert_eq!(sorted_summary(&decoded), sorted_summary(grid));
+            }
+        }
+
+        /// A block of toggles in alternating states
+        fn toggle_block(w: isize, h: isize) -> Grid<Gadget> {
+            let def = toggle_def();
+            let mut grid = Grid::new();
+
+            for y in 0..h {
+                for x in 0..w {
+                    let state = State(((x + y) % 2) as usize);
+                    grid.insert(
+                        Gadget::new(&def, (1, 1), vec![0, 2], state),
+                        vec2(x, y),
+                        (1, 1),
+                    );
+                }
+            }
+            grid
+        }
+
+        #[test]
+        fn test_golden_empty() {
+            assert_golden(&Grid::new(), "~FA5", &["~B1", "A4"]);
+        }
+
+        #[test]
+        fn test_golden_toggle() {
+            let mut grid = Grid::new();
+            grid.insert(
+                Gadget::new(&toggle_def(), (1, 1), vec![0, 2], State(0)),
+                vec2(0, 0),
+                (1, 1),
+            );
+            assert_golden(&grid, "~l0WSSISqCA5", &["~JtlkEikqA1", "ptkkQkUFA4"]);
+        }
+
+        #[test]
+        fn test_golden_tall_toggle() {
+            let mut grid = Grid::new();
+            grid.insert(
+                Gadget::new(&toggle_def(), (1, 2), vec![0, 3], State(1)),
+                vec2(-2, 3),
+                (1, 2),
+            );
+            assert_golden(&grid, "~l0WSSISrRGD1", &["~JtlkEi0akxA3"]);
+        }
+
+        #[test]
+        fn test_golden_block() {
+            assert_golden(
+                &toggle_block(4, 3),
+                "~VSbJJhIpKcFIEICRCQECECCBiQA5",
+                &["ptkkwVJVJikqBRSVGikqRFJVjgkqAJVjBSqSRS1IqkqQlUNGqkqUF3"],
+            );
+        }
+
+        #[test]
+        fn test_entropy_coded() {
+            let grid = toggle_block(10, 10);
+            let string = to_versioned_base64(&grid).unwrap();
+            let (raw, _) = to_base64(&grid).unwrap();
+            assert!(string.len() < raw.len());
+
+            let decoded = from_versioned_base64::<Grid<Gadget>>(&string).unwrap();
+            assert_eq!(sorted_summary(&decoded), sorted_summary(&grid));
+        }
+
+        #[test]
+        fn test_unknown_version() {
+            let mut bits = to_bits(&(VERSION + 1)).unwrap();
+            bits.extend_from_slice(&to_bits(&Grid::<Gadget>::new()).unwrap());
+            let (base64, padding) = bits_to_base64(&bits);
+            let string = format!("{}{}{}", VERSION_MARKER, base64, padding);
+
+            assert_eq!(
+                from_versioned_base64::<Grid<Gadget>>(&string).err(),
+                Some(Error::UnknownVersion(VERSION + 1))
+            );
+        }
+
+        #[test]
+        fn test_corrupt() {
+            for string in &["~B!1", "~B", "~B9", ""] {
+                match from_versioned_base64::<Grid<Gadget>>(string) {
+                    Err(Error::Corrupt(_)) => {}
+                    result => panic!("{:?} decoded to {:?}", string, result.map(|g| summary(&g))),
+                }
+            }
+        }
+    }
+}
@@ -109,17 +109,19 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         Err(Error::Unsupported("char".to_string()))
     }
 
-    fn serialize_str(self, _v: &str) -> Result<()> {
-        Err(Error::Unsupported("str".to_string()))
+    /// Stored as its UTF-8 bytes
+    fn serialize_str(self, v: &str) -> Result<()> {
+        self.serialize_bytes(v.as_bytes())
     }
 
     /// First the length, then the elements are
     /// stored in order, each byte in LSB-MSB order
-    fn serialize_bytes(self, _v: &[u8]) -> Result<()> {
-        Err(Error::Unsupported("bytes".to_string()))
-        //v.len().serialize(&mut *self)?;
-        //self.buffer.extend(v.iter().flat_map(|b| b.bits::<Lsb0>().iter().copied()));
-        //Ok(())
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        v.len().serialize(&mut *self)?;
+        for byte in v {
+            self.buffer.extend_from_slice(byte.bits::<Lsb0>());
+        }
+        Ok(())
     }
 
     /// 0 to represent no value
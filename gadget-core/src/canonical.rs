@@ -0,0 +1,481 @@
+use crate::gadget::{GadgetDef, Port, State, SPSP};
+
+/// The default maximum number of nodes of the individualization tree to visit
+/// while searching for a canonical form
+pub const DEFAULT_BUDGET: usize = 1000;
+
+/// (number of states, number of ports, number of traversals) of a gadget def,
+/// which relabelling doesn't change
+pub type Invariant = (usize, usize, usize);
+
+/// Gets the invariant of a gadget def.
+/// Defs with different invariants can't have the same canonical form,
+/// and this is much cheaper to compute.
+pub fn invariant(def: &GadgetDef) -> Invariant {
+    (def.num_states(), def.num_ports(), def.traversals().count())
+}
+
+/// A relabelling of the states and ports of a gadget def,
+/// optionally along with a reversal of all its traversals
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Relabelling {
+    /// The new label of each state
+    pub states: Vec<State>,
+    /// The new label of each port
+    pub ports: Vec<Port>,
+    /// Whether traversals go the other way after relabelling
+    pub reversed: bool,
+}
+
+impl Relabelling {
+    pub fn identity(num_states: usize, num_ports: usize) -> Self {
+        Self {
+            states: (0..num_states).map(State).collect(),
+            ports: (0..num_ports).map(Port).collect(),
+            reversed: false,
+        }
+    }
+
+    /// Gets the relabelling that undoes this one
+    pub fn inverse(&self) -> Self {
+        let mut states = vec![State(0); self.states.len()];
+        for (i, state) in self.states.iter().enumerate() {
+            states[state.0] = State(i);
+        }
+
+        let mut ports = vec![Port(0); self.ports.len()];
+        for (i, port) in self.ports.iter().enumerate() {
+            ports[port.0] = Port(i);
+        }
+
+        Self {
+            states,
+            ports,
+            reversed: self.reversed,
+        }
+    }
+
+    /// Gets the relabelling that does this one, then `other`
+    pub fn then(&self, other: &Relabelling) -> Self {
+        Self {
+            states: self.states.iter().map(|s| other.states[s.0]).collect(),
+            ports: self.ports.iter().map(|p| other.ports[p.0]).collect(),
+            reversed: self.reversed != other.reversed,
+        }
+    }
+
+    /// Relabels a single traversal
+    pub fn traversal(&self, ((s0, p0), (s1, p1)): SPSP) -> SPSP {
+        let sp0 = (self.states[s0.0], self.ports[p0.0]);
+        let sp1 = (self.states[s1.0], self.ports[p1.0]);
+
+        if self.reversed {
+            (sp1, sp0)
+        } else {
+            (sp0, sp1)
+        }
+    }
+
+    /// Relabels a gadget def
+    pub fn apply(&self, def: &GadgetDef) -> GadgetDef {
+        GadgetDef::from_traversals(
+            def.num_states(),
+            def.num_ports(),
+            def.traversals()
+                .map(|t| self.traversal(*t))
+                .collect::<Vec<_>>(),
+        )
+    }
+}
+
+/// Ordered colors of states and ports.
+/// Colors are ranks, so the colors of states are 0..k for some k, and likewise for ports.
+#[derive(Clone, Debug)]
+struct Coloring {
+    states: Vec<usize>,
+    ports: Vec<usize>,
+}
+
+/// Replaces each key with its rank among the distinct keys
+fn ranks<K: Ord + Clone>(keys: &[K]) -> Vec<usize> {
+    let mut sorted = keys.to_vec();
+    sorted.sort();
+    sorted.dedup();
+
+    keys.iter()
+        .map(|k| sorted.binary_search(k).expect("Key disappeared"))
+        .collect()
+}
+
+fn num_colors(colors: &[usize]) -> usize {
+    colors.iter().max().map_or(0, |max| max + 1)
+}
+
+impl Coloring {
+    fn is_discrete(&self) -> bool {
+        num_colors(&self.states) == self.states.len() && num_colors(&self.ports) == self.ports.len()
+    }
+
+    /// Splits colors by how their states and ports take part in traversals,
+    /// until nothing more can be split.
+    /// Only depends on the structure of the def, not on its labels.
+    fn refine(&mut self, traversals: &[SPSP]) {
+        loop {
+            let mut state_sigs = self
+                .states
+                .iter()
+                .map(|c| (*c, vec![], vec![]))
+                .collect::<Vec<_>>();
+            let mut port_sigs = self
+                .ports
+                .iter()
+                .map(|c| (*c, vec![], vec![]))
+                .collect::<Vec<_>>();
+
+            for ((s0, p0), (s1, p1)) in traversals {
+                let colors = (
+                    self.states[s0.0],
+                    self.ports[p0.0],
+                    self.states[s1.0],
+                    self.ports[p1.0],
+                );
+                state_sigs[s0.0].1.push(colors);
+                state_sigs[s1.0].2.push(colors);
+                port_sigs[p0.0].1.push(colors);
+                port_sigs[p1.0].2.push(colors);
+            }
+
+            for (_, from, to) in state_sigs.iter_mut().chain(port_sigs.iter_mut()) {
+                from.sort();
+                to.sort();
+            }
+
+            let states = ranks(&state_sigs);
+            let ports = ranks(&port_sigs);
+
+            let stable = num_colors(&states) == num_colors(&self.states)
+                && num_colors(&ports) == num_colors(&self.ports);
+
+            self.states = states;
+            self.ports = ports;
+
+            if stable {
+                return;
+            }
+        }
+    }
+
+    /// Gives one element of a color class a color of its own, ordered before the rest of its class
+    fn individualize(&self, state: bool, index: usize) -> Self {
+        let split = |colors: &[usize]| {
+            let color = colors[index];
+            ranks(
+                &colors
+                    .iter()
+                    .enumerate()
+                    .map(|(i, c)| (*c, *c == color && i != index))
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        if state {
+            Self {
+                states: split(&self.states),
+                ports: self.ports.clone(),
+            }
+        } else {
+            Self {
+                states: self.states.clone(),
+                ports: split(&self.ports),
+            }
+        }
+    }
+
+    /// Gets the first color class with more than 1 element,
+    /// as (whether it's a class of states, its elements)
+    fn first_nontrivial_class(&self) -> Option<(bool, Vec<usize>)> {
+        for (state, colors) in &[(true, &self.states), (false, &self.ports)] {
+            let mut counts = vec![0; colors.len()];
+            for c in colors.iter() {
+                counts[*c] += 1;
+            }
+
+            if let Some(color) = counts.iter().position(|n| *n > 1) {
+                let class = colors
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, c)| **c == color)
+                    .map(|(i, _)| i)
+                    .collect();
+                return Some((*state, class));
+            }
+        }
+
+        None
+    }
+}
+
+/// Searches the tree of individualizations for the relabelling
+/// that gives the smallest sorted list of traversals,
+/// visiting at most `budget` more nodes.
+/// Returns false if it ran out of budget.
+fn search(
+    traversals: &[SPSP],
+    mut coloring: Coloring,
+    best: &mut Option<(Vec<SPSP>, Relabelling)>,
+    budget: &mut usize,
+) -> bool {
+    if *budget == 0 {
+        return false;
+    }
+    *budget -= 1;
+
+    coloring.refine(traversals);
+
+    if coloring.is_discrete() {
+        let relabelling = Relabelling {
+            states: coloring.states.into_iter().map(State).collect(),
+            ports: coloring.ports.into_iter().map(Port).collect(),
+            reversed: false,
+        };
+
+        let mut relabelled = traversals
+            .iter()
+            .map(|t| relabelling.traversal(*t))
+            .collect::<Vec<_>>();
+        relabelled.sort();
+
+        let better = match best {
+            Some((b, _)) => relabelled < *b,
+            None => true,
+        };
+        if better {
+            *best = Some((relabelled, relabelling));
+        }
+        return true;
+    }
+
+    let (state, class) = coloring
+        .first_nontrivial_class()
+        .expect("Non-discrete coloring has no class to split");
+
+    class.into_iter().all(|index| {
+        search(
+            traversals,
+            coloring.individualize(state, index),
+            best,
+            budget,
+        )
+    })
+}
+
+/// Gets the canonical form of a gadget def under relabelling of states and ports,
+/// along with a relabelling that turns the def into it.
+/// Isomorphic defs have equal canonical forms.
+/// The search can take factorial time on very symmetric defs,
+/// so this gives up and returns `None` after visiting `budget` nodes of it.
+pub fn canonical_form_within(def: &GadgetDef, budget: usize) -> Option<(GadgetDef, Relabelling)> {
+    let mut traversals = def.traversals().copied().collect::<Vec<_>>();
+    traversals.sort();
+
+    let mut best = None;
+    let mut budget = budget;
+    let finished = search(
+        &traversals,
+        Coloring {
+            states: vec![0; def.num_states()],
+            ports: vec![0; def.num_ports()],
+        },
+        &mut best,
+        &mut budget,
+    );
+
+    if !finished {
+        return None;
+    }
+
+    let (traversals, relabelling) = best.expect("Search found no labelling");
+    Some((
+        GadgetDef::from_traversals(def.num_states(), def.num_ports(), traversals),
+        relabelling,
+    ))
+}
+
+/// Gets the hash string of the canonical form of a gadget def,
+/// along with a relabelling that turns the def into the canonical form.
+/// Returns `None` if the canonical form can't be found within `budget` nodes of search.
+pub fn canonical_hash_string(def: &GadgetDef, budget: usize) -> Option<(String, Relabelling)> {
+    let (canonical, relabelling) = canonical_form_within(def, budget)?;
+    Some((canonical.hash_string(), relabelling))
+}
+
+/// The search for a canonical form visited more nodes than its budget allowed
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct OutOfBudget;
+
+/// Checks whether 2 gadget defs are the same up to relabelling of states and ports,
+/// and also reversal of all traversals if `allow_reversal` is true,
+/// visiting at most `budget` nodes in each search for a canonical form.
+/// Returns a relabelling that turns `a` into `b` if so.
+pub fn is_isomorphic(
+    a: &GadgetDef,
+    b: &GadgetDef,
+    allow_reversal: bool,
+    budget: usize,
+) -> Result<Option<Relabelling>, OutOfBudget> {
+    if invariant(a) != invariant(b) {
+        return Ok(None);
+    }
+
+    let (canon_a, to_canon_a) = canonical_form_within(a, budget).ok_or(OutOfBudget)?;
+    let (canon_b, to_canon_b) = canonical_form_within(b, budget).ok_or(OutOfBudget)?;
+
+    if canon_a == canon_b {
+        return Ok(Some(to_canon_a.then(&to_canon_b.inverse())));
+    }
+
+    if allow_reversal {
+        let mut reversal = Relabelling::identity(a.num_states(), a.num_ports());
+        reversal.reversed = true;
+
+        let (canon_a, to_canon_a) =
+            canonical_form_within(&reversal.apply(a), budget).ok_or(OutOfBudget)?;
+        if canon_a == canon_b {
+            return Ok(Some(reversal.then(&to_canon_a).then(&to_canon_b.inverse())));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::spsp_multi;
+
+    fn canonical_form(def: &GadgetDef) -> (GadgetDef, Relabelling) {
+        canonical_form_within(def, DEFAULT_BUDGET).unwrap()
+    }
+
+    fn toggle() -> GadgetDef {
+        GadgetDef::from_traversals(2, 2, spsp_multi![((0, 0), (1, 1)), ((1, 1), (0, 0))])
+    }
+
+    /// A door that opens when bounced off of port 0, and then lets the agent through port 1 to 2
+    fn door() -> GadgetDef {
+        GadgetDef::from_traversals(2, 3, spsp_multi![((0, 0), (1, 0)), ((1, 1), (1, 2))])
+    }
+
+    fn shuffled_door() -> GadgetDef {
+        GadgetDef::from_traversals(2, 3, spsp_multi![((1, 2), (0, 2)), ((0, 0), (0, 1))])
+    }
+
+    #[test]
+    fn test_relabelling_inverse() {
+        let relabelling = Relabelling {
+            states: vec![State(1), State(2), State(0)],
+            ports: vec![Port(1), Port(0)],
+            reversed: false,
+        };
+
+        assert_eq!(
+            relabelling.then(&relabelling.inverse()),
+            Relabelling::identity(3, 2)
+        );
+    }
+
+    #[test]
+    fn test_canonical_form_relabelled() {
+        let (canon_a, to_canon_a) = canonical_form(&door());
+        let (canon_b, _) = canonical_form(&shuffled_door());
+
+        assert_eq!(canon_a, canon_b);
+        assert_eq!(to_canon_a.apply(&door()), canon_a);
+        assert_eq!(canon_a.hash_string(), canon_b.hash_string());
+    }
+
+    #[test]
+    fn test_canonical_form_symmetric() {
+        // Every state and port looks the same until individualized
+        let def = GadgetDef::from_traversals(
+            2,
+            4,
+            spsp_multi![
+                ((0, 0), (1, 1)),
+                ((1, 1), (0, 0)),
+                ((0, 2), (1, 3)),
+                ((1, 3), (0, 2)),
+                ((1, 0), (0, 1)),
+                ((0, 1), (1, 0)),
+                ((1, 2), (0, 3)),
+                ((0, 3), (1, 2)),
+            ],
+        );
+        let relabelling = Relabelling {
+            states: vec![State(1), State(0)],
+            ports: vec![Port(2), Port(0), Port(3), Port(1)],
+            reversed: false,
+        };
+
+        assert_eq!(
+            canonical_form(&def).0,
+            canonical_form(&relabelling.apply(&def)).0
+        );
+    }
+
+    #[test]
+    fn test_canonical_form_budget() {
+        // Every port looks the same no matter how many get individualized
+        let def = GadgetDef::new(1, 12);
+
+        assert!(canonical_form_within(&def, DEFAULT_BUDGET).is_none());
+        assert!(canonical_hash_string(&def, DEFAULT_BUDGET).is_none());
+        assert!(canonical_form_within(&toggle(), DEFAULT_BUDGET).is_some());
+    }
+
+    #[test]
+    fn test_invariant() {
+        assert_eq!(invariant(&door()), invariant(&shuffled_door()));
+        assert_ne!(invariant(&door()), invariant(&toggle()));
+    }
+
+    #[test]
+    fn test_is_isomorphic() {
+        let relabelling = is_isomorphic(&door(), &shuffled_door(), false, DEFAULT_BUDGET)
+            .unwrap()
+            .unwrap();
+        assert_eq!(relabelling.apply(&door()), shuffled_door());
+
+        assert_eq!(
+            is_isomorphic(&door(), &toggle(), true, DEFAULT_BUDGET),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_is_isomorphic_budget() {
+        let def = GadgetDef::new(1, 12);
+
+        assert_eq!(
+            is_isomorphic(&def, &def, false, DEFAULT_BUDGET),
+            Err(OutOfBudget)
+        );
+    }
+
+    #[test]
+    fn test_is_isomorphic_reversal() {
+        let reversed_door =
+            GadgetDef::from_traversals(2, 3, spsp_multi![((1, 0), (0, 0)), ((1, 2), (1, 1))]);
+
+        assert_eq!(
+            is_isomorphic(&door(), &reversed_door, false, DEFAULT_BUDGET),
+            Ok(None)
+        );
+
+        let relabelling = is_isomorphic(&door(), &reversed_door, true, DEFAULT_BUDGET)
+            .unwrap()
+            .unwrap();
+        assert!(relabelling.reversed);
+        assert_eq!(relabelling.apply(&door()), reversed_door);
+    }
+}
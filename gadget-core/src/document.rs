@@ -0,0 +1,486 @@
+use cgmath::vec2;
+use fnv::{FnvHashMap, FnvHashSet};
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+
+use std::rc::Rc;
+
+use crate::gadget::{Gadget, GadgetDef, GadgetSerde, Port, State};
+use crate::grid::{Grid, WH};
+
+/// A gadget def, with a name that gadgets refer to it by
+#[derive(Serialize, Deserialize, Debug)]
+struct DefDocument {
+    name: String,
+    num_states: usize,
+    num_ports: usize,
+    /// ((from state, from port), (to state, to port)), sorted
+    traversals: Vec<((usize, usize), (usize, usize))>,
+}
+
+impl DefDocument {
+    fn new(name: String, def: &GadgetDef) -> Self {
+        let mut traversals = def
+            .traversals()
+            .map(|((s0, p0), (s1, p1))| ((s0.id(), p0.id()), (s1.id(), p1.id())))
+            .collect::<Vec<_>>();
+        traversals.sort();
+
+        Self {
+            name,
+            num_states: def.num_states(),
+            num_ports: def.num_ports(),
+            traversals,
+        }
+    }
+
+    fn def(&self) -> GadgetDef {
+        GadgetDef::from_traversals(
+            self.num_states,
+            self.num_ports,
+            self.traversals
+                .iter()
+                .map(|((s0, p0), (s1, p1))| ((State(*s0), Port(*p0)), (State(*s1), Port(*p1))))
+                .collect::<Vec<_>>(),
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GadgetDocument {
+    /// Name of the def
+    def: String,
+    position: (isize, isize),
+    size: WH,
+    port_map: Vec<usize>,
+    state: usize,
+}
+
+/// A part of a document that can be invalid
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Part {
+    /// The def at some index
+    Def(usize),
+    /// The gadget at some index
+    Gadget(usize),
+}
+
+/// A contraption as a human-readable document.
+/// Gadgets are in scan order, and defs are in order of first use,
+/// so small changes to the contraption make small changes to the document.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GridDocument {
+    defs: Vec<DefDocument>,
+    gadgets: Vec<GadgetDocument>,
+}
+
+/// Gets a name based on `name` that isn't taken
+fn unique_name(name: &str, taken: &FnvHashSet<String>) -> String {
+    let name = if name.is_empty() { "Gadget" } else { name };
+
+    if !taken.contains(name) {
+        return name.to_string();
+    }
+
+    (2..)
+        .map(|i| format!("{} {}", name, i))
+        .find(|name| !taken.contains(name))
+        .expect("Ran out of names")
+}
+
+impl GridDocument {
+    /// Creates a document of a grid.
+    /// Each def is named after the first gadget that uses it.
+    pub fn new(grid: &Grid<Gadget>) -> Self {
+        let mut sorted = grid.iter().collect::<Vec<_>>();
+        sorted.sort_by_key(|(_, xy, _)| (xy.y, xy.x));
+
+        let mut defs = vec![];
+        let mut defs_inv = FnvHashMap::default();
+        let mut names = FnvHashSet::default();
+        let mut gadgets = vec![];
+
+        for (gadget, xy, _) in sorted {
+            let index = *defs_inv
+                .entry(gadget.def().hash_string())
+                .or_insert_with(|| {
+                    let name = unique_name(gadget.name(), &names);
+                    names.insert(name.clone());
+                    defs.push(DefDocument::new(name, gadget.def()));
+                    defs.len() - 1
+                });
+
+            gadgets.push(GadgetDocument {
+                def: defs[index].name.clone(),
+                position: (xy.x, xy.y),
+                size: gadget.size(),
+                port_map: gadget.port_map().to_vec(),
+                state: gadget.state().id(),
+            });
+        }
+
+        Self { defs, gadgets }
+    }
+
+    /// Builds the grid, or says which part of the document is invalid and why.
+    /// Gadgets are named after their defs.
+    fn into_grid(self) -> Result<Grid<Gadget>, (Part, String)> {
+        let mut def_indexes = FnvHashMap::default();
+        let mut defs = vec![];
+
+        for (i, def) in self.defs.iter().enumerate() {
+            if def_indexes.insert(def.name.clone(), i).is_some() {
+                return Err((
+                    Part::Def(i),
+                    format!("Gadget def name {:?} is used more than once", def.name),
+                ));
+            }
+
+            let gadget_def = def.def();
+            if !gadget_def.is_valid() {
+                return Err((
+                    Part::Def(i),
+                    format!("Gadget def {:?} is not valid", gadget_def),
+                ));
+            }
+            defs.push(gadget_def);
+        }
+
+        let mut gadgets = vec![];
+
+        for (i, gadget) in self.gadgets.into_iter().enumerate() {
+            let def = *def_indexes.get(&gadget.def).ok_or_else(|| {
+                (
+                    Part::Gadget(i),
+                    format!(
+                        "Gadget at {:?} is not valid because its def {:?} does not exist",
+                        gadget.position, gadget.def
+                    ),
+                )
+            })?;
+
+            let serde = GadgetSerde::new(def, gadget.size, gadget.port_map, State(gadget.state));
+            serde.validate(&defs).map_err(|e| (Part::Gadget(i), e))?;
+
+            gadgets.push((serde, gadget.position, gadget.def));
+        }
+
+        let defs = defs.into_iter().map(Rc::new).collect();
+        let mut grid = Grid::new();
+
+        for (i, (serde, (x, y), name)) in gadgets.into_iter().enumerate() {
+            let gadget = Gadget::from_serializable(serde, &defs).name_this(&name);
+            let size = gadget.size();
+
+            if !grid.insert(gadget, vec2(x, y), size).is_empty() {
+                return Err((
+                    Part::Gadget(i),
+                    format!(
+                        "Gadget at {:?} is not valid because it overlaps another gadget",
+                        (x, y)
+                    ),
+                ));
+            }
+        }
+
+        Ok(grid)
+    }
+}
+
+/// Finds the line and column, both starting at 1,
+/// of the start of the `index`th element of the list under `key` in a document.
+/// Strings and comments are skipped.
+fn element_location(text: &str, key: &str, index: usize) -> Option<(usize, usize)> {
+    // (byte offset, token), where a token is a word, a string, or a single character
+    let mut tokens = vec![];
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+
+            '/' if chars.peek().map(|(_, c)| *c) == Some('/') => {
+                for (_, c) in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+
+            '/' if chars.peek().map(|(_, c)| *c) == Some('*') => {
+                chars.next();
+                let mut prev = ' ';
+                for (_, c) in chars.by_ref() {
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+            }
+
+            '"' => {
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+                tokens.push((i, "\""));
+            }
+
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut end = i + c.len_utf8();
+                while let Some((j, c)) = chars.peek().copied() {
+                    if !(c.is_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    end = j + c.len_utf8();
+                    chars.next();
+                }
+                tokens.push((i, &text[i..end]));
+            }
+
+            _ => tokens.push((i, &text[i..i + c.len_utf8()])),
+        }
+    }
+
+    // Find the list, which is 1 level deep in the document
+    let mut depth = 0;
+    let mut start = None;
+    for (t, (_, token)) in tokens.iter().enumerate() {
+        match *token {
+            "(" | "[" | "{" => depth += 1,
+            ")" | "]" | "}" => depth -= 1,
+            _ if depth == 1
+                && *token == key
+                && tokens.get(t + 1).map(|(_, t)| *t) == Some(":")
+                && tokens.get(t + 2).map(|(_, t)| *t) == Some("[") =>
+            {
+                start = Some(t + 3);
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    // Count the elements, which start right after the list starts or after a comma
+    let mut depth = 0;
+    let mut count = 0;
+    let mut element_start = true;
+    for (offset, token) in &tokens[start?..] {
+        match *token {
+            "]" | ")" | "}" if depth == 0 => return None,
+            "," if depth == 0 => element_start = true,
+            _ => {
+                if element_start {
+                    if count == index {
+                        let before = &text[..*offset];
+                        let line = before.matches('\n').count() + 1;
+                        let col = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+                        return Some((line, col));
+                    }
+                    count += 1;
+                    element_start = false;
+                }
+
+                match *token {
+                    "(" | "[" | "{" => depth += 1,
+                    ")" | "]" | "}" => depth -= 1,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    None
+}
+
+/// Writes a grid as a human-readable RON document
+pub fn grid_to_ron(grid: &Grid<Gadget>) -> Result<String, String> {
+    ron::ser::to_string_pretty(&GridDocument::new(grid), PrettyConfig::default())
+        .map_err(|e| e.to_string())
+}
+
+/// Reads a grid from a RON document.
+/// Errors start with the line and column of the problem.
+pub fn grid_from_ron(text: &str) -> Result<Grid<Gadget>, String> {
+    let document = ron::de::from_str::<GridDocument>(text).map_err(|e| e.to_string())?;
+
+    document.into_grid().map_err(|(part, e)| {
+        let location = match part {
+            Part::Def(i) => element_location(text, "defs", i),
+            Part::Gadget(i) => element_location(text, "gadgets", i),
+        };
+
+        match location {
+            Some((line, col)) => format!("{}:{}: {}", line, col, e),
+            None => e,
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::grid::XY;
+    use crate::spsp_multi;
+
+    fn test_grid() -> Grid<Gadget> {
+        let toggle = Rc::new(GadgetDef::from_traversals(
+            2,
+            2,
+            spsp_multi![((0, 0), (1, 1)), ((1, 1), (0, 0))],
+        ));
+        let wire = Rc::new(GadgetDef::from_traversals(
+            1,
+            2,
+            spsp_multi![((0, 0), (0, 1)), ((0, 1), (0, 0))],
+        ));
+
+        let mut grid = Grid::new();
+        grid.insert(
+            Gadget::new(&toggle, (1, 1), vec![0, 2], State(1)).name_this("Toggle"),
+            vec2(-1, 0),
+            (1, 1),
+        );
+        grid.insert(
+            Gadget::new(&toggle, (1, 1), vec![1, 3], State(0)).name_this("Toggle"),
+            vec2(0, 0),
+            (1, 1),
+        );
+        grid.insert(
+            Gadget::new(&wire, (1, 2), vec![0, 3], State(0)),
+            vec2(2, -3),
+            (1, 2),
+        );
+        grid
+    }
+
+    fn summary(grid: &Grid<Gadget>) -> Vec<(String, GadgetDef, WH, Vec<usize>, State, XY)> {
+        let mut summary = grid
+            .iter()
+            .map(|(g, xy, _)| {
+                (
+                    g.name().to_string(),
+                    (**g.def()).clone(),
+                    g.size(),
+                    g.port_map().to_vec(),
+                    g.state(),
+                    *xy,
+                )
+            })
+            .collect::<Vec<_>>();
+        summary.sort_by_key(|(_, _, _, _, _, xy)| (xy.y, xy.x));
+        summary
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let grid = test_grid();
+        let text = grid_to_ron(&grid).unwrap();
+        let loaded = grid_from_ron(&text).unwrap();
+
+        let mut expected = summary(&grid);
+        // The unnamed wire gets a name
+        expected[0].0 = "Gadget".to_string();
+        assert_eq!(summary(&loaded), expected);
+
+        assert_eq!(grid_to_ron(&loaded).unwrap(), text);
+    }
+
+    #[test]
+    fn test_document_order() {
+        let document = GridDocument::new(&test_grid());
+
+        let names = document
+            .defs
+            .iter()
+            .map(|def| def.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Gadget", "Toggle"]);
+
+        let positions = document
+            .gadgets
+            .iter()
+            .map(|gadget| gadget.position)
+            .collect::<Vec<_>>();
+        assert_eq!(positions, vec![(2, -3), (-1, 0), (0, 0)]);
+    }
+
+    #[test]
+    fn test_unique_name() {
+        let taken = vec!["Toggle".to_string(), "Toggle 2".to_string()]
+            .into_iter()
+            .collect();
+        assert_eq!(unique_name("Toggle", &taken), "Toggle 3");
+        assert_eq!(unique_name("", &taken), "Gadget");
+    }
+
+    const INVALID_STATE: &str = r#"(
+    defs: [
+        (
+            name: "Toggle", // the usual
+            num_states: 2,
+            num_ports: 2,
+            traversals: [((0, 0), (1, 1)), ((1, 1), (0, 0))],
+        ),
+    ],
+    gadgets: [
+        (def: "Toggle", position: (0, 0), size: (1, 1), port_map: [0, 2], state: 1),
+        (def: "Toggle", position: (1, 0), size: (1, 1), port_map: [0, 2], state: 2),
+    ],
+)"#;
+
+    #[test]
+    fn test_invalid_state() {
+        let error = grid_from_ron(INVALID_STATE).unwrap_err();
+        assert!(error.starts_with("12:9: Gadget "), "{}", error);
+        assert!(
+            error.ends_with("is not valid because its state is out of bounds"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_invalid_def_name() {
+        let text = INVALID_STATE.replace("state: 2", "state: 0").replace(
+            r#"(def: "Toggle", position: (1, 0)"#,
+            r#"(def: "Toggle\"", position: (1, 0)"#,
+        );
+
+        let error = grid_from_ron(&text).unwrap_err();
+        assert!(error.starts_with("12:9: Gadget at (1, 0) "), "{}", error);
+        assert!(error.contains("does not exist"), "{}", error);
+    }
+
+    #[test]
+    fn test_invalid_overlap() {
+        let text = INVALID_STATE
+            .replace("state: 2", "state: 0")
+            .replace("position: (1, 0)", "position: (0, 0)");
+
+        let error = grid_from_ron(&text).unwrap_err();
+        assert!(error.starts_with("12:9: "), "{}", error);
+        assert!(error.ends_with("overlaps another gadget"), "{}", error);
+    }
+
+    #[test]
+    fn test_invalid_def() {
+        let text = INVALID_STATE.replace("num_states: 2", "num_states: 1");
+
+        let error = grid_from_ron(&text).unwrap_err();
+        assert!(error.starts_with("3:9: Gadget def "), "{}", error);
+    }
+
+    #[test]
+    fn test_syntax_error() {
+        let text = INVALID_STATE.replace("size: (1, 1), port_map: [0, 2], state: 2", "size: 1");
+
+        let error = grid_from_ron(&text).unwrap_err();
+        assert!(error.starts_with("12:"), "{}", error);
+    }
+}
@@ -0,0 +1,181 @@
+use fnv::{FnvHashMap, FnvHashSet};
+
+use std::collections::VecDeque;
+
+use crate::gadget::{Agent, Gadget, State};
+use crate::grid::{Grid, XY};
+use crate::solver::{Configuration, Simulation};
+
+/// A report of the configurations reachable from some starting configuration
+pub struct Exploration {
+    /// Minimal xy coords of the gadgets, in the order their states are stored in
+    positions: Vec<XY>,
+    /// Reachable configurations, in the order they were found.
+    /// The first one is the starting configuration.
+    configurations: Vec<Configuration>,
+    /// Indexes of configurations the agent can't leave, even by turning around
+    dead_ends: Vec<usize>,
+    /// (gadget position, state) pairs that no reachable configuration has
+    unvisited_states: Vec<(XY, State)>,
+    /// Whether every reachable configuration was visited within the budget
+    complete: bool,
+}
+
+impl Exploration {
+    /// Explores the configurations reachable from the agent in the grid,
+    /// visiting at most `budget` configurations.
+    pub fn explore(grid: &Grid<Gadget>, agent: &Agent, budget: usize) -> Self {
+        let mut sim = Simulation::new(grid);
+        let start = sim.configuration(agent);
+
+        let mut configurations = vec![start.clone()];
+        let mut indexes = FnvHashMap::default();
+        indexes.insert(start, 0);
+
+        // (agent position, states) pairs that some move leaves
+        let mut escapes = FnvHashSet::default();
+        let mut complete = true;
+
+        let mut queue = VecDeque::new();
+        queue.push_back(0);
+
+        while let Some(index) = queue.pop_front() {
+            let config = configurations[index].clone();
+            let moves = sim.moves(&config);
+
+            // Moves that end up in the same place with the same states just turn the agent around
+            if moves.iter().any(|(_, next)| {
+                next.agent.double_xy() != config.agent.double_xy() || next.states != config.states
+            }) {
+                escapes.insert((config.agent.double_xy(), config.states.clone()));
+            }
+
+            for (_, next) in moves {
+                if indexes.contains_key(&next) {
+                    continue;
+                }
+
+                if configurations.len() >= budget {
+                    complete = false;
+                    continue;
+                }
+
+                indexes.insert(next.clone(), configurations.len());
+                queue.push_back(configurations.len());
+                configurations.push(next);
+            }
+        }
+
+        // Turning around leads to every configuration with the same place and states,
+        // so the agent is only stuck if none of them can leave
+        let dead_ends = (0..configurations.len())
+            .filter(|i| {
+                let config = &configurations[*i];
+                !escapes.contains(&(config.agent.double_xy(), config.states.clone()))
+            })
+            .collect();
+
+        let positions = sim.positions().to_vec();
+
+        let mut visited = vec![FnvHashSet::default(); positions.len()];
+        for config in configurations.iter() {
+            for (set, state) in visited.iter_mut().zip(config.states.iter()) {
+                set.insert(*state);
+            }
+        }
+
+        let unvisited_states = positions
+            .iter()
+            .zip(visited.iter())
+            .flat_map(|(xy, set)| {
+                let (gadget, _, _) = grid.get(*xy).expect("Gadget disappeared");
+                (0..gadget.def().num_states())
+                    .map(State)
+                    .filter(move |state| !set.contains(state))
+                    .map(move |state| (*xy, state))
+            })
+            .collect();
+
+        Self {
+            positions,
+            configurations,
+            dead_ends,
+            unvisited_states,
+            complete,
+        }
+    }
+
+    /// Gets the minimal xy coords of the gadgets, in the order their states are stored in
+    pub fn positions(&self) -> &[XY] {
+        &self.positions
+    }
+
+    pub fn configurations(&self) -> &[Configuration] {
+        &self.configurations
+    }
+
+    /// Gets the indexes of configurations the agent can't leave, even by turning around
+    pub fn dead_ends(&self) -> &[usize] {
+        &self.dead_ends
+    }
+
+    /// Gets the (gadget position, state) pairs that no reachable configuration has
+    pub fn unvisited_states(&self) -> &[(XY, State)] {
+        &self.unvisited_states
+    }
+
+    /// Whether every reachable configuration was visited within the budget
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gadget::GadgetDef;
+    use crate::solver::DEFAULT_BUDGET;
+    use crate::spsp_multi;
+    use cgmath::vec2;
+    use std::rc::Rc;
+
+    /// A door that only opens after the agent bounces off its bottom,
+    /// and a toggle far away from it
+    fn test_grid() -> Grid<Gadget> {
+        let def = GadgetDef::from_traversals(2, 2, spsp_multi![((0, 0), (1, 0)), ((1, 0), (1, 1))]);
+        let door = Gadget::new(&Rc::new(def), (1, 1), vec![0, 2], State(0));
+
+        let def = GadgetDef::from_traversals(2, 2, spsp_multi![((0, 0), (1, 1)), ((1, 1), (0, 0))]);
+        let toggle = Gadget::new(&Rc::new(def), (1, 1), vec![0, 2], State(0));
+
+        let mut grid = Grid::new();
+        grid.insert(door, vec2(0, 0), (1, 1));
+        grid.insert(toggle, vec2(5, 5), (1, 1));
+        grid
+    }
+
+    #[test]
+    fn test_explore() {
+        let grid = test_grid();
+        let agent = Agent::new(vec2(0.5, 0.0), vec2(0, 1));
+
+        let exploration = Exploration::explore(&grid, &agent, DEFAULT_BUDGET);
+        assert!(exploration.is_complete());
+        assert_eq!(exploration.configurations().len(), 6);
+        assert_eq!(exploration.configurations()[0].agent, agent);
+        // Only the configurations past the door are stuck,
+        // since facing away from it at the bottom can still turn around toward it
+        assert_eq!(exploration.dead_ends().len(), 2);
+        assert_eq!(exploration.unvisited_states(), &[(vec2(5, 5), State(1))]);
+    }
+
+    #[test]
+    fn test_explore_budget() {
+        let grid = test_grid();
+        let agent = Agent::new(vec2(0.5, 0.0), vec2(0, 1));
+
+        let exploration = Exploration::explore(&grid, &agent, 2);
+        assert!(!exploration.is_complete());
+        assert_eq!(exploration.configurations().len(), 2);
+    }
+}
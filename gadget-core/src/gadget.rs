@@ -1,8 +1,10 @@
+use bitvec::slice::BitSlice;
 use cgmath::vec2;
 use fnv::{FnvHashMap, FnvHashSet};
 
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::cell::{Cell, Ref, RefCell};
+use std::any::Any;
+use std::cell::{Ref, RefCell};
 use std::fmt::{self, Debug, Formatter};
 use std::rc::Rc;
 
@@ -10,8 +12,6 @@ use crate::bit_serde;
 use crate::grid::{Grid, GridItem, WH, XY};
 use crate::math::{Vec2, Vec2i, Vector2Ex};
 
-use crate::render::GadgetRenderInfo;
-
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct Port(pub usize);
@@ -72,7 +72,7 @@ macro_rules! spsp_multi {
 }
 
 /// Definition of a gadget, including ports, states, and transitions
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct GadgetDef {
     num_states: usize,
     num_ports: usize,
@@ -93,7 +93,7 @@ impl GadgetDef {
     ///
     /// * At least 1 state exists.
     /// * The states and ports of the traversals are not out of bounds.
-    fn is_valid(&self) -> bool {
+    pub fn is_valid(&self) -> bool {
         self.num_states > 0
             && self.traversals.iter().all(|((s0, p0), (s1, p1))| {
                 s0.0 < self.num_states
@@ -165,6 +165,72 @@ pub struct GadgetSerde {
     state: State,
 }
 
+impl GadgetSerde {
+    pub fn new(def: usize, size: WH, port_map: Vec<usize>, state: State) -> Self {
+        Self {
+            def,
+            size,
+            port_map,
+            state,
+        }
+    }
+
+    /// Is a no-op if this is valid with respect to a list of gadget defs,
+    /// but returns an error message otherwise.
+    pub fn validate(&self, defs: &[GadgetDef]) -> Result<(), String> {
+        let gadget = self;
+
+        // index must be in bounds
+        let def = defs.get(gadget.def).ok_or_else(|| {
+            format!(
+                "Gadget {:?} is not valid because its def is out of bounds",
+                gadget
+            )
+        })?;
+
+        // size must be positive
+        if gadget.size.0 == 0 || gadget.size.1 == 0 {
+            return Err(format!(
+                "Gadget {:?} with def {:?} is not valid because its size is not positive",
+                gadget, def
+            ));
+        }
+
+        // state must be in bounds
+        if gadget.state.0 >= def.num_states() {
+            return Err(format!(
+                "Gadget {:?} with def {:?} is not valid because its state is out of bounds",
+                gadget, def
+            ));
+        }
+
+        // port map must be the right size and in bounds, and also be a 1-to-1 map
+        if gadget.port_map.len() != def.num_ports() {
+            return Err(format!(
+                "Gadget {:?} with def {:?} is not valid because its port map is not the right size",
+                gadget, def
+            ));
+        }
+
+        if gadget
+            .port_map
+            .iter()
+            .any(|i| *i >= 2 * gadget.size.0 + 2 * gadget.size.1)
+        {
+            return Err(format!("Gadget {:?} with def {:?} is not valid because a port position index is out of bounds", gadget, def));
+        }
+
+        if gadget.port_map.iter().collect::<FnvHashSet<_>>().len() != gadget.port_map.len() {
+            return Err(format!(
+                "Gadget {:?} with def {:?} is not valid because 2 ports map to the same position",
+                gadget, def
+            ));
+        }
+
+        Ok(())
+    }
+}
+
 pub struct Gadget {
     name: String,
     def: Rc<GadgetDef>,
@@ -174,8 +240,9 @@ pub struct Gadget {
     /// This map maps each port to its position index along the perimeter.
     port_map: Vec<usize>,
     state: State,
-    render: RefCell<GadgetRenderInfo>,
-    dirty: Cell<bool>,
+    /// Whatever a frontend needs to draw this gadget, shared between clones.
+    /// It is cleared whenever the gadget changes.
+    render_cache: RefCell<Option<Rc<dyn Any>>>,
 }
 
 impl Gadget {
@@ -192,8 +259,7 @@ impl Gadget {
             size,
             port_map,
             state,
-            render: RefCell::new(GadgetRenderInfo::new()),
-            dirty: Cell::new(true),
+            render_cache: RefCell::new(None),
         };
         res
     }
@@ -253,6 +319,10 @@ impl Gadget {
         self.size
     }
 
+    pub fn port_map(&self) -> &[usize] {
+        &self.port_map
+    }
+
     pub fn perimeter(&self) -> usize {
         2 * self.size.0 + 2 * self.size.1
     }
@@ -270,7 +340,7 @@ impl Gadget {
 
     pub fn set_state(&mut self, state: State) {
         self.state = state;
-        self.dirty.set(true);
+        self.clear_render_cache();
     }
 
     /// Gets the traversals allowed in the current state, at some port
@@ -334,7 +404,7 @@ impl Gadget {
     /// A positive number means counterclockwise,
     /// a negative number means clockwise.
     pub fn rotate_ports(&mut self, num_spaces: i32) {
-        self.dirty.set(true);
+        self.clear_render_cache();
         let perimeter = self.perimeter();
         let rem = (num_spaces).rem_euclid(perimeter as i32);
 
@@ -347,7 +417,7 @@ impl Gadget {
     /// A positive number means counterclockwise,
     /// a negative number means clockwise.
     pub fn rotate(&mut self, num_turns: i32) {
-        self.dirty.set(true);
+        self.clear_render_cache();
 
         for _ in 0..num_turns.rem_euclid(4) {
             self.rotate_ports(self.size.1 as i32);
@@ -357,7 +427,7 @@ impl Gadget {
 
     /// Flips ports across the x axis
     pub fn flip_ports_x(&mut self) {
-        self.dirty.set(true);
+        self.clear_render_cache();
         let perimeter = self.perimeter() as isize;
 
         for idx in self.port_map.iter_mut() {
@@ -367,7 +437,7 @@ impl Gadget {
 
     /// Flips ports across the y axis
     pub fn flip_ports_y(&mut self) {
-        self.dirty.set(true);
+        self.clear_render_cache();
         let perimeter = self.perimeter() as isize;
 
         for idx in self.port_map.iter_mut() {
@@ -378,7 +448,7 @@ impl Gadget {
 
     /// Twists the bottom-right ports
     pub fn twist_bottom_right(&mut self) {
-        self.dirty.set(true);
+        self.clear_render_cache();
 
         let t0 = self.size.0 - 1;
         let t1 = self.size.0;
@@ -394,7 +464,7 @@ impl Gadget {
 
     /// Adds 1 to the state; resetting it to 0 in case of overflow
     pub fn cycle_state(&mut self) {
-        self.dirty.set(true);
+        self.clear_render_cache();
         self.set_state(State((self.state.0 + 1) % self.def.num_states()));
     }
 
@@ -406,17 +476,36 @@ impl Gadget {
         self.port_map.iter().map(|n| potential[*n]).collect()
     }
 
-    /// Updates the rendering information
-    pub fn update_render(&self) {
-        self.render.borrow_mut().update(self);
+    /// Gets the port at `double_xy`, which is double a position relative to the bottom-left corner,
+    /// if there is one there
+    pub fn port_at(&self, double_xy: XY) -> Option<Port> {
+        self.port_positions()
+            .iter()
+            .position(|pos| (pos * 2.0).cast::<isize>().unwrap() == double_xy)
+            .map(Port)
+    }
+
+    fn clear_render_cache(&mut self) {
+        *self.render_cache.get_mut() = None;
     }
 
-    pub fn renderer(&self) -> Ref<GadgetRenderInfo> {
-        if self.dirty.get() {
-            self.dirty.set(false);
-            self.update_render();
+    /// Gets the cached render info of type `R`,
+    /// computing it with `compute` first if the gadget changed since it was cached
+    pub fn render_cache<R: Any>(&self, compute: impl FnOnce(&Self) -> R) -> Ref<'_, R> {
+        let cached = self
+            .render_cache
+            .borrow()
+            .as_ref()
+            .is_some_and(|cache| cache.is::<R>());
+
+        if !cached {
+            let cache = compute(self);
+            *self.render_cache.borrow_mut() = Some(Rc::new(cache));
         }
-        self.render.borrow()
+
+        Ref::map(self.render_cache.borrow(), |cache| {
+            cache.as_ref().unwrap().downcast_ref().unwrap()
+        })
     }
 }
 
@@ -445,8 +534,7 @@ impl Clone for Gadget {
             size: self.size,
             port_map: self.port_map.clone(),
             state: self.state,
-            render: self.render.clone(),
-            dirty: self.dirty.clone(),
+            render_cache: self.render_cache.clone(),
         }
     }
 }
@@ -470,88 +558,203 @@ pub struct GadgetGridSerde {
 }
 
 impl GadgetGridSerde {
-    /// Is a no-op if this is valid,
-    /// but returns an error otherwise.
-    fn validate<'de, D: Deserializer<'de>>(self) -> Result<Self, D::Error> {
-        use serde::de::Error;
+    fn new(grid: &Grid<Gadget>) -> Self {
+        let mut defs = vec![];
+        let mut defs_inv = FnvHashMap::default();
+        let mut gadgets = vec![];
+
+        for (gadget, xy, _wh) in grid.iter() {
+            let gadget = gadget.get_serializable(&mut defs, &mut defs_inv);
+            gadgets.push((gadget, (xy.x, xy.y)));
+        }
+
+        Self {
+            defs: defs.into_iter().map(|def| (*def).clone()).collect(),
+            gadgets,
+        }
+    }
 
+    /// Is a no-op if this is valid,
+    /// but returns an error message otherwise.
+    fn check(&self) -> Result<(), String> {
         for def in &self.defs {
             if !def.is_valid() {
-                return Err(D::Error::custom(&format!(
-                    "Gadget def {:?} is not valid",
-                    def
-                )));
+                return Err(format!("Gadget def {:?} is not valid", def));
             }
         }
 
         // Now validate the gadgets
         for (gadget, _) in &self.gadgets {
-            // index must be in bounds
-            let def = self.defs.get(gadget.def).ok_or_else(|| {
-                D::Error::custom(&format!(
-                    "Gadget {:?} is not valid because its def is out of bounds",
-                    gadget
-                ))
-            })?;
-
-            // size must be positive
-            if gadget.size.0 == 0 || gadget.size.1 == 0 {
-                return Err(D::Error::custom(&format!(
-                    "Gadget {:?} with def {:?} is not valid because its size is not positive",
-                    gadget, def
-                )));
-            }
+            gadget.validate(&self.defs)?;
+        }
 
-            // state must be in bounds
-            if gadget.state.0 >= def.num_states() {
-                return Err(D::Error::custom(&format!(
-                    "Gadget {:?} with def {:?} is not valid because its state is out of bounds",
-                    gadget, def
-                )));
-            }
+        Ok(())
+    }
 
-            // port map must be the right size and in bounds, and also be a 1-to-1 map
-            if gadget.port_map.len() != def.num_ports() {
-                return Err(D::Error::custom(&format!("Gadget {:?} with def {:?} is not valid because its port map is not the right size", gadget, def)));
-            }
+    /// Is a no-op if this is valid,
+    /// but returns an error otherwise.
+    fn validate<'de, D: Deserializer<'de>>(self) -> Result<Self, D::Error> {
+        use serde::de::Error;
 
-            if gadget
-                .port_map
-                .iter()
-                .any(|i| *i >= 2 * gadget.size.0 + 2 * gadget.size.1)
-            {
-                return Err(D::Error::custom(&format!("Gadget {:?} with def {:?} is not valid because a port position index is out of bounds", gadget, def)));
-            }
+        self.check().map_err(|e| D::Error::custom(&e))?;
+        Ok(self)
+    }
+
+    /// Builds the grid. This must be valid.
+    fn into_grid(self) -> Grid<Gadget> {
+        let defs = self.defs.into_iter().map(Rc::new).collect();
+
+        let mut grid = Grid::new();
+
+        for (gadget, (x, y)) in self.gadgets.into_iter() {
+            let gadget = Gadget::from_serializable(gadget, &defs);
+            let size = gadget.size;
+            grid.insert(gadget, vec2(x, y), size);
+        }
+
+        grid
+    }
+}
+
+/// A more compact layout of a grid of gadgets.
+/// Gadgets with the same def, size, and port map share a template,
+/// and gadgets are stored in scan order, with positions relative to the gadget before.
+#[derive(Serialize, Deserialize, Debug)]
+struct CompactGridSerde {
+    defs: Vec<GadgetDef>,
+    /// (def index, size, port map)
+    templates: Vec<(usize, WH, Vec<usize>)>,
+    /// Minimum x and y coordinates of the gadgets
+    origin: (isize, isize),
+    /// (template index, state, y offset, x offset).
+    /// The y offset is from the previous gadget.
+    /// The x offset is from the previous gadget if they're in the same row,
+    /// and from the origin otherwise.
+    gadgets: Vec<(usize, State, usize, isize)>,
+}
+
+impl CompactGridSerde {
+    fn new(plain: &GadgetGridSerde) -> Self {
+        let mut sorted = plain.gadgets.iter().collect::<Vec<_>>();
+        sorted.sort_by_key(|(_, (x, y))| (*y, *x));
+
+        let origin = (
+            sorted.iter().map(|(_, (x, _))| *x).min().unwrap_or(0),
+            sorted.iter().map(|(_, (_, y))| *y).min().unwrap_or(0),
+        );
+
+        let mut templates = vec![];
+        let mut templates_inv = FnvHashMap::default();
+        let mut gadgets = vec![];
+        let mut prev = None;
+
+        for (gadget, (x, y)) in sorted {
+            let template = (gadget.def, gadget.size, gadget.port_map.clone());
+            let index = *templates_inv.entry(template.clone()).or_insert_with(|| {
+                templates.push(template);
+                templates.len() - 1
+            });
+
+            let (dy, dx) = match prev {
+                Some((px, py)) if py == *y => (0, x - px),
+                Some((_, py)) => ((y - py) as usize, x - origin.0),
+                None => ((y - origin.1) as usize, x - origin.0),
+            };
+            prev = Some((*x, *y));
+
+            gadgets.push((index, gadget.state, dy, dx));
+        }
+
+        Self {
+            defs: plain.defs.clone(),
+            templates,
+            origin,
+            gadgets,
+        }
+    }
+
+    /// Expands the templates and positions,
+    /// or returns an error message if a template or position is out of bounds
+    fn into_plain(self) -> Result<GadgetGridSerde, String> {
+        let CompactGridSerde {
+            defs,
+            templates,
+            origin,
+            gadgets,
+        } = self;
 
-            if gadget.port_map.iter().collect::<FnvHashSet<_>>().len() != gadget.port_map.len() {
-                return Err(D::Error::custom(&format!("Gadget {:?} with def {:?} is not valid because 2 ports map to the same position", gadget, def)));
+        let mut plain = vec![];
+        let mut prev = None;
+
+        for (index, state, dy, dx) in gadgets {
+            let (def, size, port_map) = templates
+                .get(index)
+                .cloned()
+                .ok_or_else(|| format!("Gadget template {} is out of bounds", index))?;
+
+            let out_of_bounds =
+                || format!("Gadget position offset {:?} is out of bounds", (dx, dy));
+
+            let y = match prev {
+                Some((_, py)) => py,
+                None => origin.1,
+            }
+            .checked_add(dy as isize)
+            .filter(|_| dy <= isize::MAX as usize)
+            .ok_or_else(out_of_bounds)?;
+            let x = match prev {
+                Some((px, py)) if py == y => px,
+                _ => origin.0,
             }
+            .checked_add(dx)
+            .ok_or_else(out_of_bounds)?;
+            prev = Some((x, y));
+
+            plain.push((
+                GadgetSerde {
+                    def,
+                    size,
+                    port_map,
+                    state,
+                },
+                (x, y),
+            ));
         }
 
-        Ok(self)
+        Ok(GadgetGridSerde {
+            defs,
+            gadgets: plain,
+        })
     }
 }
 
+/// The ways a grid of gadgets can be laid out when serialized
+#[derive(Serialize, Deserialize, Debug)]
+enum GadgetGridLayout {
+    Plain(GadgetGridSerde),
+    Compact(CompactGridSerde),
+}
+
 impl Serialize for Grid<Gadget> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        let mut defs = vec![];
-        let mut defs_inv = FnvHashMap::default();
-        let mut gadgets = vec![];
+        let plain = GadgetGridSerde::new(self);
+        let compact = CompactGridSerde::new(&plain);
 
-        for (gadget, xy, _wh) in self.iter() {
-            let gadget = gadget.get_serializable(&mut defs, &mut defs_inv);
-            gadgets.push((gadget, (xy.x, xy.y)));
-        }
+        // Use whichever layout is shorter in the format URLs use
+        let plain_len = bit_serde::to_bits(&plain).map(|bits| bits.len());
+        let compact_len = bit_serde::to_bits(&compact).map(|bits| bits.len());
 
-        let grid_serde = GadgetGridSerde {
-            defs: defs.into_iter().map(|def| (*def).clone()).collect(),
-            gadgets,
+        let layout = match (plain_len, compact_len) {
+            (Ok(plain_len), Ok(compact_len)) if compact_len < plain_len => {
+                GadgetGridLayout::Compact(compact)
+            }
+            _ => GadgetGridLayout::Plain(plain),
         };
 
-        grid_serde.serialize(serializer)
+        layout.serialize(serializer)
     }
 }
 
@@ -560,24 +763,35 @@ impl<'de> Deserialize<'de> for Grid<Gadget> {
     where
         D: Deserializer<'de>,
     {
-        let GadgetGridSerde { defs, gadgets } =
-            GadgetGridSerde::deserialize(deserializer)?.validate::<D>()?;
+        use serde::de::Error;
 
-        let defs = defs.into_iter().map(|def| Rc::new(def)).collect();
+        let plain = match GadgetGridLayout::deserialize(deserializer)? {
+            GadgetGridLayout::Plain(plain) => plain,
+            GadgetGridLayout::Compact(compact) => {
+                compact.into_plain().map_err(|e| D::Error::custom(&e))?
+            }
+        };
 
-        let mut grid = Self::new();
+        Ok(plain.validate::<D>()?.into_grid())
+    }
+}
 
-        for (gadget, (x, y)) in gadgets.into_iter() {
-            let gadget = Gadget::from_serializable(gadget, &defs);
-            let size = gadget.size;
-            grid.insert(gadget, vec2(x, y), size);
+impl bit_serde::Versioned for Grid<Gadget> {
+    fn migrate(version: u64, bits: &BitSlice) -> bit_serde::Result<Self> {
+        match version {
+            // Before version 2, grids were always in the plain layout, without saying so
+            0 | 1 => {
+                let plain: GadgetGridSerde = bit_serde::from_bits(bits)?;
+                plain.check().map_err(bit_serde::Error::Message)?;
+                Ok(plain.into_grid())
+            }
+            _ => Err(bit_serde::Error::UnknownVersion(version)),
         }
-
-        Ok(grid)
     }
 }
 
 /// Walks around in a maze of gadgets
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct Agent {
     /// Double the position, because then it's integers
     double_xy: XY,
@@ -602,6 +816,11 @@ impl Agent {
         self.double_xy.cast::<f64>().unwrap() * 0.5
     }
 
+    /// Gets twice the position, which is always made of integers
+    pub fn double_xy(&self) -> XY {
+        self.double_xy
+    }
+
     pub fn direction(&self) -> Vec2i {
         self.direction
     }
@@ -635,86 +854,177 @@ impl Agent {
         self.direction = -self.direction
     }
 
-    /// Advances the agent according to internal rules.
+    /// Gets the traversals the agent can choose between for some input,
+    /// as (resulting state, exit port) pairs sorted by port,
+    /// along with the minimal xy coords of the gadget being traversed.
+    /// An input opposite the agent's direction moves the agent backward
+    /// through the gadget if `back` is true, and turns it around otherwise.
+    /// Returns `None` if there is no gadget in front of the agent,
+    /// or if the input turns the agent around instead.
+    pub fn traversals(
+        &self,
+        grid: &Grid<Gadget>,
+        input: Vec2i,
+        back: bool,
+    ) -> Option<(XY, Vec<SP>)> {
+        if input.dot_ex(self.direction) == -1 && !back {
+            return None;
+        }
+
+        let (gadget, xy, (_w, _h), idx) =
+            grid.get_item_touching_edge(self.double_xy, self.direction)?;
+        let port = gadget.port(idx)?;
+        let [backward, right, front, left] =
+            gadget.targets_from_state_port_brfl(port, self.direction);
+
+        let mut sps = if input.dot_ex(self.direction) == 1 {
+            // Forward
+            if !front.is_empty() {
+                front
+            } else if left.is_empty() != right.is_empty() {
+                if left.is_empty() {
+                    right
+                } else {
+                    left
+                }
+            } else {
+                backward
+            }
+        } else if self.direction.right_ccw() == input {
+            // Left
+            left
+        } else if input.dot_ex(self.direction) == -1 {
+            // Back
+            backward
+        } else {
+            // Right
+            right
+        };
+
+        sps.sort_by_key(|(s, p)| (*p, *s));
+        Some((xy, sps))
+    }
+
+    /// Moves the agent through the gadget with minimal xy coords `xy`,
+    /// exiting through the port of `target` and setting the gadget to the state of `target`.
+    /// Returns a reference to the gadget, its position, and its previous state.
+    pub fn traverse<'a>(
+        &mut self,
+        grid: &'a mut Grid<Gadget>,
+        xy: XY,
+        target: SP,
+    ) -> Option<(&'a Gadget, XY, State)> {
+        let (gadget, xy, _) = grid.get_mut(xy)?;
+        let (s1, p1) = target;
+
+        self.exit(gadget, xy, p1);
+        let state = gadget.state();
+        gadget.set_state(s1);
+
+        Some((gadget, xy, state))
+    }
+
+    /// Puts the agent on port `port` of the gadget with minimal xy coords `xy`, facing out
+    fn exit(&mut self, gadget: &Gadget, xy: XY, port: Port) {
+        // No floor necessary because this becomes an integer
+        // when multiplied by 2
+        let pos2 = (gadget.port_positions()[port.0] * 2.0)
+            .cast::<isize>()
+            .unwrap();
+        self.direction = if pos2.x.rem_euclid(2) != 0 {
+            if pos2.y == 0 {
+                // Bottom
+                vec2(0, -1)
+            } else {
+                // Top
+                vec2(0, 1)
+            }
+        } else {
+            if pos2.x == 0 {
+                // Left
+                vec2(-1, 0)
+            } else {
+                // Right
+                vec2(1, 0)
+            }
+        };
+
+        self.double_xy = xy * 2 + pos2;
+    }
+
+    /// Advances the agent according to internal rules,
+    /// taking the traversal at index `choice` of the ones returned by `traversals`.
     /// Returns a reference to the gadget, its position, and its previous state
     /// if a gadget changed state as a result.
     pub fn advance<'a>(
         &mut self,
         grid: &'a mut Grid<Gadget>,
         input: Vec2i,
+        back: bool,
+        choice: usize,
     ) -> Option<(&'a Gadget, XY, State)> {
-        if input.dot_ex(self.direction) == -1 {
+        if input.dot_ex(self.direction) == -1 && !back {
             // Turn around, that's it
             self.direction *= -1;
             return None;
         }
 
-        if let Some((gadget, xy, (_w, _h), idx)) =
-            grid.get_item_touching_edge_mut(self.double_xy, self.direction)
-        {
-            if let Some(port) = gadget.port(idx) {
-                let [back, right, front, left] =
-                    gadget.targets_from_state_port_brfl(port, self.direction);
-
-                // TODO: Make this more sophisticated; don't just take the first traversal
-
-                let sp;
-
-                if input.dot_ex(self.direction) == 1 {
-                    // Forward
-                    sp = front
-                        .first()
-                        .or_else(|| left.first().xor(right.first()))
-                        .or_else(|| back.first());
-                } else if self.direction.right_ccw() == input {
-                    // Left
-                    sp = left.first();
-                } else if input.dot_ex(self.direction) == -1 {
-                    // Back
-                    // TODO: Unreachable right now
-                    sp = None;
-                } else {
-                    // Right
-                    sp = right.first();
-                }
+        let (xy, sps) = self.traversals(grid, input, back)?;
+        let target = *sps.get(choice)?;
+        self.traverse(grid, xy, target)
+    }
 
-                if let Some((s1, p1)) = sp {
-                    // No floor necessary because this becomes an integer
-                    // when multiplied by 2
-                    let pos2 = (gadget.port_positions()[p1.0] * 2.0)
-                        .cast::<isize>()
-                        .unwrap();
-                    self.direction = if pos2.x.rem_euclid(2) != 0 {
-                        if pos2.y == 0 {
-                            // Bottom
-                            vec2(0, -1)
-                        } else {
-                            // Top
-                            vec2(0, 1)
-                        }
-                    } else {
-                        if pos2.x == 0 {
-                            // Left
-                            vec2(-1, 0)
-                        } else {
-                            // Right
-                            vec2(1, 0)
-                        }
-                    };
+    /// Gets what `advance` would do with the same arguments, without changing anything
+    pub fn preview_advance(
+        &self,
+        grid: &Grid<Gadget>,
+        input: Vec2i,
+        back: bool,
+        choice: usize,
+    ) -> AdvancePreview {
+        if input.dot_ex(self.direction) == -1 && !back {
+            return AdvancePreview::TurnAround;
+        }
 
-                    self.double_xy = xy * 2 + pos2;
-                    let state = gadget.state();
-                    gadget.set_state(*s1);
+        let preview = || {
+            let (xy, sps) = self.traversals(grid, input, back)?;
+            let (s1, p1) = *sps.get(choice)?;
+            let (gadget, _, _) = grid.get(xy)?;
+            let p0 = gadget.port_at(self.double_xy - xy * 2)?;
 
-                    return Some((gadget, xy, state));
-                }
-            }
-        }
+            let mut agent = self.clone();
+            agent.exit(gadget, xy, p1);
+
+            Some(AdvancePreview::Traverse {
+                xy,
+                ports: (p0, p1),
+                states: (gadget.state(), s1),
+                agent,
+            })
+        };
 
-        None
+        preview().unwrap_or(AdvancePreview::Nothing)
     }
 }
 
+/// What advancing an agent would do
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AdvancePreview {
+    /// Nothing happens
+    Nothing,
+    /// The agent turns around
+    TurnAround,
+    /// The agent goes through the gadget with minimal xy coords `xy`
+    /// from the first port in `ports` to the second,
+    /// ending up as `agent`, and changing the gadget from the first state in `states` to the second
+    Traverse {
+        xy: XY,
+        ports: PP,
+        states: (State, State),
+        agent: Agent,
+    },
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -1036,4 +1346,236 @@ mod test {
         };
         assert_gadget_grid_serde_valid(grid);
     }
+
+    /// A serialized gadget's (def, size, port map, state, position offset)
+    type GadgetSerdeSummary = (usize, WH, Vec<usize>, State, (isize, isize));
+
+    /// The gadgets of a serialized grid in scan order, as comparable tuples
+    fn gadget_grid_serde_summary(grid: &GadgetGridSerde) -> Vec<GadgetSerdeSummary> {
+        let mut summary = grid
+            .gadgets
+            .iter()
+            .map(|(g, xy)| (g.def, g.size, g.port_map.clone(), g.state, *xy))
+            .collect::<Vec<_>>();
+        summary.sort_by_key(|(_, _, _, _, (x, y))| (*y, *x));
+        summary
+    }
+
+    fn scattered_grid() -> Grid<Gadget> {
+        let toggle = Rc::new(GadgetDef::from_traversals(
+            2,
+            2,
+            spsp_multi![((0, 0), (1, 1)), ((1, 1), (0, 0))],
+        ));
+        let wire = Rc::new(GadgetDef::new(1, 3));
+
+        let mut grid = Grid::new();
+        for (def, size, port_map, state, xy) in [
+            (&toggle, (1, 1), vec![0, 2], State(1), vec2(-3, -2)),
+            (&toggle, (1, 1), vec![0, 2], State(0), vec2(2, -2)),
+            (&toggle, (1, 2), vec![0, 3], State(0), vec2(-1, -2)),
+            (&wire, (2, 1), vec![0, 1, 5], State(0), vec2(5, 4)),
+            (&toggle, (1, 1), vec![0, 2], State(1), vec2(-5, 4)),
+        ] {
+            grid.insert(Gadget::new(def, size, port_map, state), xy, size);
+        }
+        grid
+    }
+
+    #[test]
+    fn test_compact_grid_serde_round_trip() {
+        let plain = GadgetGridSerde::new(&scattered_grid());
+        let compact = CompactGridSerde::new(&plain);
+
+        assert_eq!(compact.origin, (-5, -2));
+        assert_eq!(compact.templates.len(), 3);
+
+        let expanded = compact.into_plain().unwrap();
+        assert_eq!(
+            gadget_grid_serde_summary(&expanded),
+            gadget_grid_serde_summary(&plain)
+        );
+    }
+
+    #[test]
+    fn test_compact_grid_serde_invalid_template() {
+        let compact = CompactGridSerde {
+            defs: vec![GadgetDef::new(2, 3)],
+            templates: vec![(0, (1, 1), vec![0, 1, 2])],
+            origin: (0, 0),
+            gadgets: vec![(0, State(0), 0, 0), (1, State(0), 0, 1)],
+        };
+        assert!(compact.into_plain().is_err());
+    }
+
+    #[test]
+    fn test_grid_serde_picks_shorter_layout() {
+        let def = Rc::new(GadgetDef::new(2, 2));
+        let mut grid = Grid::new();
+        for y in 0..5 {
+            for x in 0..5 {
+                grid.insert(
+                    Gadget::new(&def, (1, 1), vec![0, 2], State(0)),
+                    vec2(x, y),
+                    (1, 1),
+                );
+            }
+        }
+
+        let plain = GadgetGridSerde::new(&grid);
+        let bits = bit_serde::to_bits(&grid).unwrap();
+        assert!(bits.len() < bit_serde::to_bits(&plain).unwrap().len());
+
+        let decoded = bit_serde::from_bits::<Grid<Gadget>>(&bits).unwrap();
+        assert_eq!(
+            gadget_grid_serde_summary(&GadgetGridSerde::new(&decoded)),
+            gadget_grid_serde_summary(&plain)
+        );
+    }
+
+    fn agent_test_grid() -> Grid<Gadget> {
+        let def = GadgetDef::from_traversals(
+            2,
+            4,
+            spsp_multi![
+                ((0, 0), (0, 2)),
+                ((0, 0), (1, 2)),
+                ((0, 0), (0, 1)),
+                ((0, 0), (1, 0)),
+            ],
+        );
+        let gadget = Gadget::new(&Rc::new(def), (1, 1), vec![0, 1, 2, 3], State(0));
+
+        let mut grid = Grid::new();
+        grid.insert(gadget, vec2(0, 0), (1, 1));
+        grid
+    }
+
+    #[test]
+    fn test_agent_traversals_multiple() {
+        let grid = agent_test_grid();
+        let agent = Agent::new(vec2(0.5, 0.0), vec2(0, 1));
+
+        let expected = Some((vec2(0, 0), sp_multi![(0, 2), (1, 2)].collect::<Vec<_>>()));
+        assert_eq!(agent.traversals(&grid, vec2(0, 1), false), expected);
+    }
+
+    #[test]
+    fn test_agent_traversals_none() {
+        let grid = agent_test_grid();
+        let agent = Agent::new(vec2(0.5, 0.0), vec2(0, 1));
+
+        let expected = Some((vec2(0, 0), vec![]));
+        assert_eq!(agent.traversals(&grid, vec2(-1, 0), false), expected);
+        assert_eq!(agent.traversals(&grid, vec2(0, -1), false), None);
+    }
+
+    #[test]
+    fn test_agent_advance_choice() {
+        let mut grid = agent_test_grid();
+        let mut agent = Agent::new(vec2(0.5, 0.0), vec2(0, 1));
+
+        let result = agent
+            .advance(&mut grid, vec2(0, 1), false, 1)
+            .map(|(_, xy, state)| (xy, state));
+        assert_eq!(result, Some((vec2(0, 0), State(0))));
+        assert_eq!(agent.position(), vec2(0.5, 1.0));
+        assert_eq!(agent.direction(), vec2(0, 1));
+        assert_eq!(grid.get(vec2(0, 0)).unwrap().0.state(), State(1));
+    }
+
+    #[test]
+    fn test_agent_advance_choice_out_of_bounds() {
+        let mut grid = agent_test_grid();
+        let mut agent = Agent::new(vec2(0.5, 0.0), vec2(0, 1));
+
+        assert!(agent.advance(&mut grid, vec2(0, 1), false, 2).is_none());
+        assert_eq!(agent.position(), vec2(0.5, 0.0));
+        assert_eq!(grid.get(vec2(0, 0)).unwrap().0.state(), State(0));
+    }
+
+    #[test]
+    fn test_agent_advance_turn_around() {
+        let mut grid = agent_test_grid();
+        let mut agent = Agent::new(vec2(0.5, 0.0), vec2(0, 1));
+
+        assert!(agent.advance(&mut grid, vec2(0, -1), false, 0).is_none());
+        assert_eq!(agent.position(), vec2(0.5, 0.0));
+        assert_eq!(agent.direction(), vec2(0, -1));
+        assert_eq!(grid.get(vec2(0, 0)).unwrap().0.state(), State(0));
+    }
+
+    #[test]
+    fn test_agent_preview_advance() {
+        let grid = agent_test_grid();
+        let agent = Agent::new(vec2(0.5, 0.0), vec2(0, 1));
+
+        for &input in &[vec2(0, 1), vec2(-1, 0), vec2(0, -1), vec2(1, 0)] {
+            for &back in &[false, true] {
+                for choice in 0..3 {
+                    let mut advanced_grid = grid.clone();
+                    let mut advanced = agent.clone();
+                    let result = advanced
+                        .advance(&mut advanced_grid, input, back, choice)
+                        .map(|(gadget, xy, state)| (xy, state, gadget.state()));
+
+                    let preview = agent.preview_advance(&grid, input, back, choice);
+                    match preview {
+                        AdvancePreview::Traverse {
+                            xy,
+                            states,
+                            agent: previewed,
+                            ..
+                        } => {
+                            assert_eq!(result, Some((xy, states.0, states.1)));
+                            assert_eq!(previewed, advanced);
+                        }
+                        AdvancePreview::TurnAround => {
+                            assert_eq!(result, None);
+                            assert_eq!(advanced.direction(), -agent.direction());
+                        }
+                        AdvancePreview::Nothing => {
+                            assert_eq!(result, None);
+                            assert_eq!(advanced, agent);
+                        }
+                    }
+                }
+            }
+        }
+
+        assert_eq!(
+            agent.preview_advance(&grid, vec2(0, 1), false, 1),
+            AdvancePreview::Traverse {
+                xy: vec2(0, 0),
+                ports: (Port(0), Port(2)),
+                states: (State(0), State(1)),
+                agent: Agent::new(vec2(0.5, 1.0), vec2(0, 1)),
+            }
+        );
+        assert_eq!(
+            agent.preview_advance(&grid, vec2(-1, 0), false, 0),
+            AdvancePreview::Nothing
+        );
+        assert_eq!(
+            agent.preview_advance(&grid, vec2(0, -1), false, 0),
+            AdvancePreview::TurnAround
+        );
+    }
+
+    #[test]
+    fn test_agent_advance_back() {
+        let mut grid = agent_test_grid();
+        let mut agent = Agent::new(vec2(0.5, 0.0), vec2(0, 1));
+
+        let expected = Some((vec2(0, 0), sp_multi![(1, 0)].collect::<Vec<_>>()));
+        assert_eq!(agent.traversals(&grid, vec2(0, -1), true), expected);
+
+        let result = agent
+            .advance(&mut grid, vec2(0, -1), true, 0)
+            .map(|(_, xy, state)| (xy, state));
+        assert_eq!(result, Some((vec2(0, 0), State(0))));
+        assert_eq!(agent.position(), vec2(0.5, 0.0));
+        assert_eq!(agent.direction(), vec2(0, -1));
+        assert_eq!(grid.get(vec2(0, 0)).unwrap().0.state(), State(1));
+    }
 }
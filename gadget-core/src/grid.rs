@@ -116,13 +116,9 @@ impl<T: GridItem> Grid<T> {
             .collect::<Vec<_>>()
     }
 
-    /// Gets the item touching an edge centered at double_xy / 2, in the direction `direction`,
-    /// and also gets the minimal xy coords, the size, and the index along the perimeter.
-    pub fn get_item_touching_edge_mut(
-        &mut self,
-        double_xy: XY,
-        direction: XY,
-    ) -> Option<(&mut T, XY, WH, usize)> {
+    /// Gets the position of the cell in front of an edge centered at double_xy / 2,
+    /// when facing the direction `direction`
+    fn cell_touching_edge(double_xy: XY, direction: XY) -> XY {
         let x_mis = double_xy.x.rem_euclid(2);
         let y_mis = double_xy.y.rem_euclid(2);
 
@@ -135,40 +131,62 @@ impl<T: GridItem> Grid<T> {
         if direction.y < 0 {
             xy.y -= 1;
         }
+        xy
+    }
 
-        if let Some((t, min_xy, (w, h))) = self.get_mut(xy) {
-            if direction.x < 0 {
-                xy.x += 1;
+    /// Gets the index along the perimeter of an item with minimal xy coords `min_xy`
+    /// and size (w, h) of the edge centered at double_xy / 2
+    pub fn perimeter_index(double_xy: XY, min_xy: XY, (w, h): WH) -> usize {
+        let x_mis = double_xy.x.rem_euclid(2);
+        let xy = vec2(double_xy.x.div_euclid(2), double_xy.y.div_euclid(2));
+
+        let idx = if x_mis != 0 {
+            if xy.y == min_xy.y {
+                // Bottom edge
+                xy.x - min_xy.x
+            } else {
+                // Top edge
+                (w + h + w) as isize - (xy.x - min_xy.x) - 1
             }
-            if direction.y < 0 {
-                xy.y += 1;
+        } else {
+            if xy.x == min_xy.x {
+                // Left edge
+                (w + h + w + h) as isize - (xy.y - min_xy.y) - 1
+            } else {
+                // Right edge
+                w as isize + (xy.y - min_xy.y)
             }
+        };
+        idx as usize
+    }
 
-            Some((
-                t,
-                min_xy,
-                (w, h),
-                if x_mis != 0 {
-                    if xy.y == min_xy.y {
-                        // Bottom edge
-                        xy.x - min_xy.x
-                    } else {
-                        // Top edge
-                        (w + h + w) as isize - (xy.x - min_xy.x) - 1
-                    }
-                } else {
-                    if xy.x == min_xy.x {
-                        // Left edge
-                        (w + h + w + h) as isize - (xy.y - min_xy.y) - 1
-                    } else {
-                        // Right edge
-                        w as isize + (xy.y - min_xy.y)
-                    }
-                } as usize,
-            ))
-        } else {
-            None
-        }
+    /// Gets the item touching an edge centered at double_xy / 2, in the direction `direction`,
+    /// and also gets the minimal xy coords, the size, and the index along the perimeter.
+    pub fn get_item_touching_edge(
+        &self,
+        double_xy: XY,
+        direction: XY,
+    ) -> Option<(&T, XY, WH, usize)> {
+        let (t, min_xy, wh) = self.get(Self::cell_touching_edge(double_xy, direction))?;
+
+        Some((
+            t,
+            *min_xy,
+            *wh,
+            Self::perimeter_index(double_xy, *min_xy, *wh),
+        ))
+    }
+
+    /// Gets the item touching an edge centered at double_xy / 2, in the direction `direction`,
+    /// and also gets the minimal xy coords, the size, and the index along the perimeter.
+    pub fn get_item_touching_edge_mut(
+        &mut self,
+        double_xy: XY,
+        direction: XY,
+    ) -> Option<(&mut T, XY, WH, usize)> {
+        let (t, min_xy, wh) = self.get_mut(Self::cell_touching_edge(double_xy, direction))?;
+
+        Some((t, min_xy, wh, Self::perimeter_index(double_xy, min_xy, wh)))
     }
 
     pub fn extend(&mut self, iter: impl IntoIterator<Item = (T, XY, WH)>) {
@@ -420,4 +438,24 @@ mod test {
 
         assert_eq!(("a", vec2(-1, -5), (3, 2)), *grid.iter().next().unwrap());
     }
+
+    #[test]
+    fn test_get_item_touching_edge() {
+        let mut grid = Grid::new();
+        grid.insert("a", vec2(0, 0), (2, 1));
+
+        assert_eq!(
+            Some((&"a", vec2(0, 0), (2, 1), 0)),
+            grid.get_item_touching_edge(vec2(1, 0), vec2(0, 1))
+        );
+        assert_eq!(
+            Some((&"a", vec2(0, 0), (2, 1), 3)),
+            grid.get_item_touching_edge(vec2(3, 2), vec2(0, -1))
+        );
+        assert_eq!(
+            Some((&"a", vec2(0, 0), (2, 1), 2)),
+            grid.get_item_touching_edge(vec2(4, 1), vec2(-1, 0))
+        );
+        assert_eq!(None, grid.get_item_touching_edge(vec2(4, 1), vec2(1, 0)));
+    }
 }
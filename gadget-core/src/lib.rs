@@ -0,0 +1,24 @@
+//! Gadgets, grids of gadgets, the analysis of them, and what they are drawn with.
+//! Nothing here depends on a browser, so it can be used and tested natively.
+
+pub mod analysis;
+pub mod ascii;
+pub mod bit_serde;
+pub mod canonical;
+pub mod document;
+pub mod explorer;
+pub mod gadget;
+pub mod grid;
+pub mod library;
+pub mod math;
+pub mod minimize;
+pub mod preset_gadgets;
+pub mod region;
+pub mod render;
+pub mod replay;
+pub mod script;
+pub mod shape;
+pub mod share;
+pub mod solver;
+pub mod static_map;
+pub mod trail;
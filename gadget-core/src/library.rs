@@ -0,0 +1,210 @@
+use bitvec::slice::BitSlice;
+use fnv::{FnvHashMap, FnvHashSet};
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+
+use std::rc::Rc;
+
+use crate::bit_serde;
+use crate::gadget::{Gadget, GadgetDef, GadgetSerde};
+use crate::grid::WH;
+
+/// A gadget library that can be serialized and deserialized.
+/// Like a serialized grid, the gadgets contain indexes into the list of defs.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GadgetLibrarySerde {
+    defs: Vec<GadgetDef>,
+    /// Palette entries, with their names
+    gadgets: Vec<(String, GadgetSerde)>,
+}
+
+impl GadgetLibrarySerde {
+    pub fn new(gadgets: &[Gadget]) -> Self {
+        let mut defs = vec![];
+        let mut defs_inv = FnvHashMap::default();
+
+        let gadgets = gadgets
+            .iter()
+            .map(|gadget| {
+                (
+                    gadget.name().to_string(),
+                    gadget.get_serializable(&mut defs, &mut defs_inv),
+                )
+            })
+            .collect();
+
+        Self {
+            defs: defs.into_iter().map(|def| (*def).clone()).collect(),
+            gadgets,
+        }
+    }
+
+    /// Turns this back into palette entries, or says why it's invalid
+    pub fn into_gadgets(self) -> Result<Vec<Gadget>, String> {
+        for def in &self.defs {
+            if !def.is_valid() {
+                return Err(format!("Gadget def {:?} is not valid", def));
+            }
+        }
+
+        for (_, gadget) in &self.gadgets {
+            gadget.validate(&self.defs)?;
+        }
+
+        let defs = self.defs.into_iter().map(Rc::new).collect();
+
+        Ok(self
+            .gadgets
+            .into_iter()
+            .map(|(name, gadget)| Gadget::from_serializable(gadget, &defs).name_this(&name))
+            .collect())
+    }
+}
+
+impl bit_serde::Versioned for GadgetLibrarySerde {
+    fn migrate(version: u64, bits: &BitSlice) -> bit_serde::Result<Self> {
+        match version {
+            // The layout hasn't changed since before versioning
+            0 | 1 => bit_serde::from_bits(bits),
+            _ => Err(bit_serde::Error::UnknownVersion(version)),
+        }
+    }
+}
+
+/// Serializes palette entries as a RON library
+pub fn library_to_ron(gadgets: &[Gadget]) -> Result<String, String> {
+    ron::ser::to_string_pretty(&GadgetLibrarySerde::new(gadgets), PrettyConfig::default())
+        .map_err(|e| e.to_string())
+}
+
+/// Deserializes palette entries from a RON library
+pub fn library_from_ron(string: &str) -> Result<Vec<Gadget>, String> {
+    ron::de::from_str::<GadgetLibrarySerde>(string)
+        .map_err(|e| e.to_string())?
+        .into_gadgets()
+}
+
+/// What makes 2 palette entries duplicates of each other:
+/// the def's hash string, the size, and the port map
+fn library_key(gadget: &Gadget) -> (String, WH, Vec<usize>) {
+    (
+        gadget.def().hash_string(),
+        gadget.size(),
+        gadget.port_map().to_vec(),
+    )
+}
+
+/// Adds library entries to the end of a palette, skipping duplicates of entries already there.
+/// Returns the number of entries added.
+pub fn merge_library(palette: &mut Vec<Gadget>, gadgets: Vec<Gadget>) -> usize {
+    let mut keys = palette.iter().map(library_key).collect::<FnvHashSet<_>>();
+    let len = palette.len();
+
+    for gadget in gadgets {
+        if keys.insert(library_key(&gadget)) {
+            palette.push(gadget);
+        }
+    }
+
+    palette.len() - len
+}
+
+/// Gets the palette entries that aren't duplicates of presets
+pub fn custom_gadgets(palette: &[Gadget], presets: &[Gadget]) -> Vec<Gadget> {
+    let keys = presets.iter().map(library_key).collect::<FnvHashSet<_>>();
+
+    palette
+        .iter()
+        .filter(|gadget| !keys.contains(&library_key(gadget)))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gadget::State;
+    use crate::spsp_multi;
+
+    fn toggle() -> Gadget {
+        let def = GadgetDef::from_traversals(2, 2, spsp_multi![((0, 0), (1, 1)), ((1, 1), (0, 0))]);
+        Gadget::new(&Rc::new(def), (1, 1), vec![0, 2], State(0)).name_this("Toggle")
+    }
+
+    fn wire() -> Gadget {
+        let def = GadgetDef::from_traversals(1, 2, spsp_multi![((0, 0), (0, 1)), ((0, 1), (0, 0))]);
+        Gadget::new(&Rc::new(def), (1, 2), vec![0, 3], State(0)).name_this("Wire")
+    }
+
+    #[test]
+    fn test_library_round_trip() {
+        let gadgets = vec![toggle(), wire()];
+        let string = library_to_ron(&gadgets).unwrap();
+        let loaded = library_from_ron(&string).unwrap();
+
+        assert_eq!(loaded.len(), 2);
+        for (gadget, loaded) in gadgets.iter().zip(loaded.iter()) {
+            assert_eq!(loaded.name(), gadget.name());
+            assert_eq!(loaded.def(), gadget.def());
+            assert_eq!(loaded.size(), gadget.size());
+            assert_eq!(loaded.port_map(), gadget.port_map());
+            assert_eq!(loaded.state(), gadget.state());
+        }
+    }
+
+    #[test]
+    fn test_library_shares_defs() {
+        let gadget = toggle();
+        let mut other = gadget.clone();
+        other.set_state(State(1));
+
+        let library = GadgetLibrarySerde::new(&[gadget, other]);
+        assert_eq!(library.defs.len(), 1);
+    }
+
+    #[test]
+    fn test_library_invalid() {
+        assert!(library_from_ron("not a library").is_err());
+
+        let mut library = GadgetLibrarySerde::new(&[toggle()]);
+        library.defs.clear();
+        assert!(library.into_gadgets().is_err());
+    }
+
+    #[test]
+    fn test_merge_library() {
+        let mut palette = vec![toggle()];
+
+        let mut moved = toggle();
+        moved.rotate_ports(1);
+
+        assert_eq!(
+            merge_library(&mut palette, vec![toggle(), wire(), moved, wire()]),
+            2
+        );
+        assert_eq!(palette.len(), 3);
+        assert_eq!(palette[1].name(), "Wire");
+    }
+
+    #[test]
+    fn test_custom_gadgets() {
+        let palette = vec![toggle(), wire()];
+        let custom = custom_gadgets(&palette, &[wire()]);
+
+        assert_eq!(custom.len(), 1);
+        assert_eq!(custom[0].name(), "Toggle");
+    }
+
+    #[test]
+    fn test_library_bit_serde() {
+        let string = bit_serde::to_versioned_base64(&GadgetLibrarySerde::new(&[toggle()])).unwrap();
+        let loaded = bit_serde::from_versioned_base64::<GadgetLibrarySerde>(&string)
+            .unwrap()
+            .into_gadgets()
+            .unwrap();
+
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].name(), "Toggle");
+        assert_eq!(loaded[0].def(), toggle().def());
+    }
+}
@@ -0,0 +1,314 @@
+use fnv::FnvHashMap;
+
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::gadget::{Gadget, GadgetDef, Port, State};
+
+/// The result of minimizing a gadget def
+#[derive(Clone, Debug)]
+pub struct Minimized {
+    pub def: Rc<GadgetDef>,
+    /// The state of the minimized def that the start state became
+    pub start: State,
+    /// The new state of each old state, or None if it's unreachable from the start state
+    pub state_map: Vec<Option<State>>,
+    /// The new port of each old port, or None if it's dead
+    pub port_map: Vec<Option<Port>>,
+    /// Ports that no traversal between reachable states uses
+    pub dead_ports: Vec<Port>,
+}
+
+impl Minimized {
+    /// Remaps a placed gadget of the original def to use the minimized def.
+    /// Dead ports are removed from its port map.
+    /// Returns None if the gadget's state is unreachable from the start state.
+    pub fn remap(&self, gadget: &Gadget) -> Option<Gadget> {
+        let state = self.state_map[gadget.state().id()]?;
+
+        let port_map = gadget
+            .port_map()
+            .iter()
+            .zip(self.port_map.iter())
+            .filter(|(_, new)| new.is_some())
+            .map(|(idx, _)| *idx)
+            .collect();
+
+        Some(Gadget::new(&self.def, gadget.size(), port_map, state).name_this(gadget.name()))
+    }
+}
+
+/// Gets the states reachable from `start` by any sequence of traversals
+pub fn reachable_states(def: &GadgetDef, start: State) -> Vec<bool> {
+    let mut reachable = vec![false; def.num_states()];
+    reachable[start.0] = true;
+
+    let mut queue = VecDeque::new();
+    queue.push_back(start);
+
+    while let Some(state) = queue.pop_front() {
+        for ((_, _), (s1, _)) in def.traversals().filter(|((s0, _), _)| *s0 == state) {
+            if !reachable[s1.0] {
+                reachable[s1.0] = true;
+                queue.push_back(*s1);
+            }
+        }
+    }
+
+    reachable
+}
+
+/// Minimizes a gadget def, as seen from the start state `start`:
+/// removes states that are unreachable from it, merges bisimilar states,
+/// and removes ports that no remaining traversal uses.
+/// States and ports keep their relative order.
+pub fn minimize(def: &GadgetDef, start: State) -> Minimized {
+    let reachable = reachable_states(def, start);
+
+    let mut restrict_map = vec![None; def.num_states()];
+    let mut num_reachable = 0;
+    for (state, reachable) in reachable.iter().enumerate() {
+        if *reachable {
+            restrict_map[state] = Some(State(num_reachable));
+            num_reachable += 1;
+        }
+    }
+
+    let restricted = GadgetDef::from_traversals(
+        num_reachable,
+        def.num_ports(),
+        def.traversals()
+            .filter_map(|((s0, p0), (s1, p1))| {
+                Some(((restrict_map[s0.0]?, *p0), (restrict_map[s1.0]?, *p1)))
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    let classes = bisimulation_classes(&restricted);
+    let merged = quotient(&restricted, &classes);
+
+    let mut used = vec![false; def.num_ports()];
+    for ((_, p0), (_, p1)) in merged.traversals() {
+        used[p0.0] = true;
+        used[p1.0] = true;
+    }
+
+    let mut port_map = vec![None; def.num_ports()];
+    let mut dead_ports = vec![];
+    let mut num_ports = 0;
+    for (port, used) in used.iter().enumerate() {
+        if *used {
+            port_map[port] = Some(Port(num_ports));
+            num_ports += 1;
+        } else {
+            dead_ports.push(Port(port));
+        }
+    }
+
+    let minimized = GadgetDef::from_traversals(
+        merged.num_states(),
+        num_ports,
+        merged
+            .traversals()
+            .map(|((s0, p0), (s1, p1))| {
+                (
+                    (*s0, port_map[p0.0].expect("Used port is dead")),
+                    (*s1, port_map[p1.0].expect("Used port is dead")),
+                )
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    let state_map = restrict_map
+        .iter()
+        .map(|state| state.map(|s| State(classes[s.0])))
+        .collect::<Vec<_>>();
+
+    Minimized {
+        def: Rc::new(minimized),
+        start: state_map[start.0].expect("Start state is unreachable"),
+        state_map,
+        port_map,
+        dead_ports,
+    }
+}
+
+/// A state's class, along with the (entry port, exit port, class of the resulting state)
+/// of each traversal it allows
+type Signature = (usize, Vec<(Port, Port, usize)>);
+
+/// Partitions the states of a def into classes of bisimilar states:
+/// states that allow the same traversals into the same classes.
+/// Returns the class of each state.
+/// Classes are numbered in order of the first state in them.
+pub fn bisimulation_classes(def: &GadgetDef) -> Vec<usize> {
+    let n = def.num_states();
+
+    let mut outgoing = vec![vec![]; n];
+    for ((s0, p0), (s1, p1)) in def.traversals() {
+        outgoing[s0.0].push((*p0, *p1, *s1));
+    }
+
+    let mut classes = vec![0; n];
+    let mut num_classes = if n == 0 { 0 } else { 1 };
+
+    loop {
+        // A state's signature is its class and the traversals it allows into each class
+        let mut signatures: FnvHashMap<Signature, usize> = FnvHashMap::default();
+        let mut new_classes = vec![0; n];
+
+        for s in 0..n {
+            let mut signature = outgoing[s]
+                .iter()
+                .map(|(p0, p1, s1)| (*p0, *p1, classes[s1.0]))
+                .collect::<Vec<_>>();
+            signature.sort();
+            signature.dedup();
+
+            let len = signatures.len();
+            new_classes[s] = *signatures.entry((classes[s], signature)).or_insert(len);
+        }
+
+        classes = new_classes;
+
+        if signatures.len() == num_classes {
+            return classes;
+        }
+        num_classes = signatures.len();
+    }
+}
+
+/// Merges the states of a def according to a class for each state
+pub fn quotient(def: &GadgetDef, classes: &[usize]) -> GadgetDef {
+    GadgetDef::from_traversals(
+        classes.iter().max().map_or(0, |max| max + 1),
+        def.num_ports(),
+        def.traversals()
+            .map(|((s0, p0), (s1, p1))| ((State(classes[s0.0]), *p0), (State(classes[s1.0]), *p1)))
+            .collect::<Vec<_>>(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::spsp_multi;
+    use fnv::FnvHashSet;
+
+    #[test]
+    fn test_bisimulation_classes_distinct() {
+        let def = GadgetDef::from_traversals(2, 2, spsp_multi![((0, 0), (1, 1)), ((1, 1), (0, 0))]);
+        assert_eq!(bisimulation_classes(&def), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_bisimulation_classes_merge() {
+        // States 1 and 2 both let the agent go from port 0 to port 1 forever
+        let def = GadgetDef::from_traversals(
+            3,
+            2,
+            spsp_multi![((0, 1), (1, 0)), ((1, 0), (2, 1)), ((2, 0), (1, 1))],
+        );
+        assert_eq!(bisimulation_classes(&def), vec![0, 1, 1]);
+    }
+
+    #[test]
+    fn test_bisimulation_classes_refine() {
+        // States 0 and 1 look the same at first glance, but lead to different states
+        let def = GadgetDef::from_traversals(
+            3,
+            2,
+            spsp_multi![((0, 0), (1, 1)), ((1, 0), (2, 1)), ((2, 1), (2, 0))],
+        );
+        assert_eq!(bisimulation_classes(&def), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_quotient() {
+        let def = GadgetDef::from_traversals(
+            3,
+            2,
+            spsp_multi![((0, 1), (1, 0)), ((1, 0), (2, 1)), ((2, 0), (1, 1))],
+        );
+        let result = quotient(&def, &[0, 1, 1]);
+
+        assert_eq!(result.num_states(), 2);
+        assert_eq!(result.num_ports(), 2);
+        assert_eq!(
+            result.traversals().copied().collect::<FnvHashSet<_>>(),
+            spsp_multi![((0, 1), (1, 0)), ((1, 0), (1, 1))].collect()
+        );
+    }
+
+    #[test]
+    fn test_reachable_states() {
+        let def = GadgetDef::from_traversals(3, 2, spsp_multi![((0, 0), (1, 0)), ((2, 0), (0, 0))]);
+        assert_eq!(reachable_states(&def, State(0)), vec![true, true, false]);
+        assert_eq!(reachable_states(&def, State(2)), vec![true, true, true]);
+    }
+
+    #[test]
+    fn test_minimize() {
+        // State 0 is unreachable from state 1, states 2 and 3 are bisimilar,
+        // and port 1 is only used by state 0
+        let def = GadgetDef::from_traversals(
+            4,
+            4,
+            spsp_multi![
+                ((0, 1), (1, 1)),
+                ((1, 0), (2, 2)),
+                ((2, 0), (3, 3)),
+                ((3, 0), (2, 3)),
+                ((2, 2), (1, 0)),
+                ((3, 2), (1, 0)),
+            ],
+        );
+        let minimized = minimize(&def, State(1));
+
+        assert_eq!(minimized.start, State(0));
+        assert_eq!(
+            minimized.state_map,
+            vec![None, Some(State(0)), Some(State(1)), Some(State(1))]
+        );
+        assert_eq!(minimized.dead_ports, vec![Port(1)]);
+        assert_eq!(
+            minimized.port_map,
+            vec![Some(Port(0)), None, Some(Port(1)), Some(Port(2))]
+        );
+
+        assert_eq!(minimized.def.num_states(), 2);
+        assert_eq!(minimized.def.num_ports(), 3);
+        assert_eq!(
+            minimized
+                .def
+                .traversals()
+                .copied()
+                .collect::<FnvHashSet<_>>(),
+            spsp_multi![((0, 0), (1, 1)), ((1, 0), (1, 2)), ((1, 1), (0, 0))].collect()
+        );
+    }
+
+    #[test]
+    fn test_minimize_remap() {
+        let def = GadgetDef::from_traversals(
+            3,
+            3,
+            spsp_multi![
+                ((0, 0), (1, 2)),
+                ((0, 2), (0, 0)),
+                ((1, 0), (2, 2)),
+                ((2, 0), (1, 2)),
+            ],
+        );
+        let minimized = minimize(&def, State(0));
+        assert_eq!(minimized.dead_ports, vec![Port(1)]);
+
+        let gadget = Gadget::new(&Rc::new(def), (1, 1), vec![0, 1, 2], State(2)).name_this("Test");
+        let remapped = minimized.remap(&gadget).unwrap();
+
+        assert!(Rc::ptr_eq(remapped.def(), &minimized.def));
+        assert_eq!(remapped.port_map(), &[0, 2]);
+        assert_eq!(remapped.state(), State(1));
+        assert_eq!(remapped.name(), "Test");
+    }
+}
@@ -0,0 +1,630 @@
+use cgmath::vec2;
+use fnv::{FnvHashMap, FnvHashSet};
+use itertools::iproduct;
+
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::gadget::{Agent, Gadget, GadgetDef, Port, State, SP, SPSP};
+use crate::grid::{Grid, WH, XY};
+use crate::math::Vec2i;
+use crate::minimize;
+use crate::solver::{Configuration, Simulation, DIRECTIONS};
+
+/// An edge on the boundary of a region that the agent can enter and leave through
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ExternalPort {
+    /// Twice the position of the center of the edge
+    pub double_xy: XY,
+    /// Direction pointing into the region
+    pub direction: Vec2i,
+}
+
+/// A region of a contraption that is supposed to simulate a gadget.
+/// The region's external ports act as the simulated gadget's ports, numbered in order,
+/// and its representative configurations act as the simulated gadget's states.
+pub struct Region {
+    sim: Simulation,
+    /// Cells covered by the region's gadgets
+    cells: FnvHashSet<XY>,
+    ports: Vec<ExternalPort>,
+    /// Gadget states of each representative, in the order of `positions`
+    representatives: Vec<Vec<State>>,
+}
+
+/// The traversals a region allows in terms of its external ports and representatives
+#[derive(Clone, Debug, Default)]
+pub struct Relation {
+    pub traversals: FnvHashSet<SPSP>,
+    /// Traversals that end in a configuration that is not a representative,
+    /// as (start, exit port, resulting gadget states)
+    pub unrecognized: Vec<(SP, Port, Vec<State>)>,
+    /// Whether every reachable configuration was visited within the budget
+    pub complete: bool,
+}
+
+/// How the traversals of a region compare to the traversals of a gadget def
+#[derive(Clone, Debug)]
+pub struct Comparison {
+    /// Traversals the def has that the region doesn't
+    pub missing: Vec<SPSP>,
+    /// Traversals the region has that the def doesn't
+    pub extra: Vec<SPSP>,
+    pub unrecognized: Vec<(SP, Port, Vec<State>)>,
+    pub complete: bool,
+}
+
+impl Comparison {
+    /// Whether the region simulates the def exactly
+    pub fn is_match(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.unrecognized.is_empty()
+    }
+}
+
+impl Region {
+    /// Creates a region out of the gadgets of a grid
+    pub fn new(grid: &Grid<Gadget>) -> Self {
+        let cells = grid
+            .iter()
+            .flat_map(|(_, xy, (w, h))| {
+                let xy = *xy;
+                iproduct!(0..*w as isize, 0..*h as isize).map(move |(x, y)| xy + vec2(x, y))
+            })
+            .collect();
+
+        Self {
+            sim: Simulation::new(grid),
+            cells,
+            ports: vec![],
+            representatives: vec![],
+        }
+    }
+
+    /// Gets the minimal xy coords of the gadgets,
+    /// in the order their states are stored in representatives
+    pub fn positions(&self) -> &[XY] {
+        self.sim.positions()
+    }
+
+    /// Gets the edges on the boundary of the region
+    pub fn boundary_edges(&self) -> Vec<ExternalPort> {
+        let mut edges = self
+            .cells
+            .iter()
+            .flat_map(|xy| {
+                DIRECTIONS
+                    .iter()
+                    .filter(move |dir| !self.cells.contains(&(xy + **dir)))
+                    .map(move |dir| ExternalPort {
+                        double_xy: xy * 2 + vec2(1, 1) + dir,
+                        direction: -*dir,
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        edges.sort_by_key(|port| (port.double_xy.y, port.double_xy.x));
+        edges
+    }
+
+    pub fn ports(&self) -> &[ExternalPort] {
+        &self.ports
+    }
+
+    /// Marks the boundary edge centered at double_xy / 2 as the next external port,
+    /// or unmarks it if it already is one, renumbering the ports after it.
+    /// Returns whether anything changed.
+    pub fn toggle_port(&mut self, double_xy: XY) -> bool {
+        if let Some(i) = self.ports.iter().position(|p| p.double_xy == double_xy) {
+            self.ports.remove(i);
+            return true;
+        }
+
+        if let Some(port) = self
+            .boundary_edges()
+            .into_iter()
+            .find(|p| p.double_xy == double_xy)
+        {
+            self.ports.push(port);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn representatives(&self) -> &[Vec<State>] {
+        &self.representatives
+    }
+
+    /// Adds a representative configuration for the next simulated state
+    /// and returns that state
+    pub fn add_representative(&mut self, states: Vec<State>) -> State {
+        self.representatives.push(states);
+        State(self.representatives.len() - 1)
+    }
+
+    pub fn clear_representatives(&mut self) {
+        self.representatives.clear();
+    }
+
+    /// Gets the external port the agent is leaving through, if any
+    fn exit_port(&self, agent: &Agent) -> Option<Port> {
+        self.ports
+            .iter()
+            .position(|p| p.double_xy == agent.double_xy() && p.direction == -agent.direction())
+            .map(Port)
+    }
+
+    /// Finds the ways the agent can leave the region after entering through `port`
+    /// with the gadgets in `states`, as (exit port, resulting gadget states) pairs.
+    /// Also returns whether every reachable configuration was visited
+    /// within the budget of `budget` configurations.
+    fn exits(
+        &mut self,
+        states: &[State],
+        port: &ExternalPort,
+        budget: usize,
+    ) -> (Vec<(Port, Vec<State>)>, bool) {
+        let start = Configuration {
+            agent: Agent::new(port.double_xy.cast::<f64>().unwrap() * 0.5, port.direction),
+            states: states.to_vec(),
+        };
+
+        let mut exits = vec![];
+        let mut complete = true;
+
+        let mut visited = FnvHashSet::default();
+        visited.insert(start.clone());
+        let mut queue = VecDeque::new();
+        queue.push_back(start);
+
+        while let Some(config) = queue.pop_front() {
+            if let Some(p1) = self.exit_port(&config.agent) {
+                exits.push((p1, config.states));
+
+                // The agent left the region
+                continue;
+            }
+
+            for (_, next) in self.sim.moves(&config) {
+                if visited.contains(&next) {
+                    continue;
+                }
+
+                if visited.len() >= budget {
+                    complete = false;
+                    continue;
+                }
+
+                visited.insert(next.clone());
+                queue.push_back(next);
+            }
+        }
+
+        (exits, complete)
+    }
+
+    /// Computes the traversals of the region.
+    /// Leaving through the entrance without changing state is always possible
+    /// by turning around, so it isn't counted.
+    /// At most `budget` configurations are visited per (state, entrance) pair.
+    pub fn relation(&mut self, budget: usize) -> Relation {
+        let mut relation = Relation {
+            complete: true,
+            ..Relation::default()
+        };
+
+        let representatives = self
+            .representatives
+            .iter()
+            .enumerate()
+            .map(|(i, states)| (states.clone(), State(i)))
+            .collect::<FnvHashMap<_, _>>();
+
+        for (s0, states) in self.representatives.clone().into_iter().enumerate() {
+            for (p0, port) in self.ports.clone().into_iter().enumerate() {
+                let sp0 = (State(s0), Port(p0));
+
+                let (exits, complete) = self.exits(&states, &port, budget);
+                relation.complete &= complete;
+
+                for (p1, states) in exits {
+                    match representatives.get(&states) {
+                        Some(s1) => {
+                            if (*s1, p1) != sp0 {
+                                relation.traversals.insert((sp0, (*s1, p1)));
+                            }
+                        }
+                        None => relation.unrecognized.push((sp0, p1, states)),
+                    }
+                }
+            }
+        }
+
+        relation.unrecognized.sort();
+        relation.unrecognized.dedup();
+        relation
+    }
+
+    /// Gets the minimal xy coords and the size of the smallest rectangle containing the region
+    pub fn bounding_box(&self) -> (XY, WH) {
+        let min_x = self.cells.iter().map(|xy| xy.x).min().unwrap_or(0);
+        let min_y = self.cells.iter().map(|xy| xy.y).min().unwrap_or(0);
+        let max_x = self.cells.iter().map(|xy| xy.x + 1).max().unwrap_or(0);
+        let max_y = self.cells.iter().map(|xy| xy.y + 1).max().unwrap_or(0);
+
+        (
+            vec2(min_x, min_y),
+            ((max_x - min_x) as usize, (max_y - min_y) as usize),
+        )
+    }
+
+    /// Gets the port map of a gadget the size of the bounding box
+    /// whose ports are at the external ports
+    fn bounding_box_port_map(&self) -> Result<Vec<usize>, String> {
+        let (min_xy, (w, h)) = self.bounding_box();
+        let (w2, h2) = (2 * w as isize, 2 * h as isize);
+
+        self.ports
+            .iter()
+            .enumerate()
+            .map(|(i, port)| {
+                let d = port.double_xy - min_xy * 2;
+                let horizontal = (d.y == 0 || d.y == h2) && d.x > 0 && d.x < w2;
+                let vertical = (d.x == 0 || d.x == w2) && d.y > 0 && d.y < h2;
+
+                if horizontal || vertical {
+                    Ok(Grid::<Gadget>::perimeter_index(
+                        port.double_xy,
+                        min_xy,
+                        (w, h),
+                    ))
+                } else {
+                    Err(format!(
+                        "External port {} is not on the edge of the region's bounding box",
+                        i
+                    ))
+                }
+            })
+            .collect()
+    }
+
+    /// Black-boxes the region into a gadget, starting with the gadgets in `states`.
+    /// The gadget's states are the classes of equivalent configurations the region can reach
+    /// by letting the agent in and out through its external ports,
+    /// with state 0 containing the starting configuration.
+    /// Its ports are the external ports, in order, and its size is the region's bounding box.
+    /// At most `budget` configurations are visited per (configuration, entrance) pair,
+    /// and at most `budget` configurations are distinguished.
+    pub fn compose(&mut self, states: Vec<State>, budget: usize) -> Result<Gadget, String> {
+        if self.ports.is_empty() {
+            return Err("The region has no external ports".to_string());
+        }
+
+        let port_map = self.bounding_box_port_map()?;
+
+        let mut configs = vec![states.clone()];
+        let mut indexes = FnvHashMap::default();
+        indexes.insert(states, 0);
+        let mut traversals = vec![];
+
+        let mut index = 0;
+        while index < configs.len() {
+            let states = configs[index].clone();
+
+            for (p0, port) in self.ports.clone().into_iter().enumerate() {
+                let (exits, complete) = self.exits(&states, &port, budget);
+                if !complete {
+                    return Err(format!(
+                        "Gave up after visiting {} configurations inside the region",
+                        budget
+                    ));
+                }
+
+                for (p1, next) in exits {
+                    let next_index = match indexes.get(&next) {
+                        Some(i) => *i,
+                        None => {
+                            if configs.len() >= budget {
+                                return Err(format!(
+                                    "Gave up after finding {} configurations of the region",
+                                    budget
+                                ));
+                            }
+
+                            indexes.insert(next.clone(), configs.len());
+                            configs.push(next);
+                            configs.len() - 1
+                        }
+                    };
+
+                    traversals.push(((State(index), Port(p0)), (State(next_index), p1)));
+                }
+            }
+
+            index += 1;
+        }
+
+        let def = GadgetDef::from_traversals(configs.len(), self.ports.len(), traversals);
+        let classes = minimize::bisimulation_classes(&def);
+        let def = minimize::quotient(&def, &classes);
+
+        // Trivial traversals are always possible by turning around
+        let def = GadgetDef::from_traversals(
+            def.num_states(),
+            def.num_ports(),
+            def.traversals()
+                .copied()
+                .filter(|(sp0, sp1)| sp0 != sp1)
+                .collect::<Vec<_>>(),
+        );
+
+        let (_, size) = self.bounding_box();
+        Ok(Gadget::new(&Rc::new(def), size, port_map, State(0)))
+    }
+
+    /// Checks whether the region simulates a gadget def,
+    /// with external port i acting as port i and representative i acting as state i.
+    pub fn check(&mut self, def: &GadgetDef, budget: usize) -> Result<Comparison, String> {
+        if self.ports.len() != def.num_ports() {
+            return Err(format!(
+                "The region has {} external ports, but the gadget has {} ports",
+                self.ports.len(),
+                def.num_ports()
+            ));
+        }
+
+        if self.representatives.len() != def.num_states() {
+            return Err(format!(
+                "The region has {} representatives, but the gadget has {} states",
+                self.representatives.len(),
+                def.num_states()
+            ));
+        }
+
+        if self.representatives.iter().collect::<FnvHashSet<_>>().len()
+            != self.representatives.len()
+        {
+            return Err("2 representatives are the same configuration".to_string());
+        }
+
+        let relation = self.relation(budget);
+
+        // Trivial traversals aren't computed, so don't expect them
+        let expected = def
+            .traversals()
+            .copied()
+            .filter(|(sp0, sp1)| sp0 != sp1)
+            .collect::<FnvHashSet<_>>();
+
+        let mut missing = expected
+            .difference(&relation.traversals)
+            .copied()
+            .collect::<Vec<_>>();
+        missing.sort();
+
+        let mut extra = relation
+            .traversals
+            .difference(&expected)
+            .copied()
+            .collect::<Vec<_>>();
+        extra.sort();
+
+        Ok(Comparison {
+            missing,
+            extra,
+            unrecognized: relation.unrecognized,
+            complete: relation.complete,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::solver::DEFAULT_BUDGET;
+    use crate::spsp_multi;
+
+    fn toggle_def() -> GadgetDef {
+        GadgetDef::from_traversals(2, 2, spsp_multi![((0, 0), (1, 1)), ((1, 1), (0, 0))])
+    }
+
+    /// A single vertical toggle with external ports on the bottom and top
+    fn toggle_region() -> Region {
+        let gadget = Gadget::new(&Rc::new(toggle_def()), (1, 1), vec![0, 2], State(0));
+
+        let mut grid = Grid::new();
+        grid.insert(gadget, vec2(0, 0), (1, 1));
+
+        let mut region = Region::new(&grid);
+        assert!(region.toggle_port(vec2(1, 0)));
+        assert!(region.toggle_port(vec2(1, 2)));
+        region.add_representative(vec![State(0)]);
+        region.add_representative(vec![State(1)]);
+        region
+    }
+
+    #[test]
+    fn test_boundary_edges() {
+        let region = toggle_region();
+        assert_eq!(region.boundary_edges().len(), 4);
+        assert_eq!(
+            region.boundary_edges()[0],
+            ExternalPort {
+                double_xy: vec2(1, 0),
+                direction: vec2(0, 1)
+            }
+        );
+    }
+
+    #[test]
+    fn test_toggle_port_not_boundary() {
+        let mut region = toggle_region();
+        assert!(!region.toggle_port(vec2(5, 0)));
+        assert!(region.toggle_port(vec2(1, 0)));
+        assert_eq!(region.ports().len(), 1);
+    }
+
+    #[test]
+    fn test_check_match() {
+        let mut region = toggle_region();
+        let comparison = region.check(&toggle_def(), DEFAULT_BUDGET).unwrap();
+        assert!(comparison.is_match());
+        assert!(comparison.complete);
+    }
+
+    #[test]
+    fn test_check_extra_missing() {
+        let mut region = toggle_region();
+        let def = GadgetDef::from_traversals(2, 2, spsp_multi![((0, 0), (1, 1)), ((0, 1), (1, 0))]);
+        let comparison = region.check(&def, DEFAULT_BUDGET).unwrap();
+        assert_eq!(
+            comparison.missing,
+            spsp_multi![((0, 1), (1, 0))].collect::<Vec<_>>()
+        );
+        assert_eq!(
+            comparison.extra,
+            spsp_multi![((1, 1), (0, 0))].collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_check_unrecognized() {
+        let mut region = toggle_region();
+        region.clear_representatives();
+        region.add_representative(vec![State(0)]);
+
+        let def = GadgetDef::from_traversals(1, 2, spsp_multi![((0, 0), (0, 1))]);
+        let comparison = region.check(&def, DEFAULT_BUDGET).unwrap();
+        assert_eq!(
+            comparison.unrecognized,
+            vec![((State(0), Port(0)), Port(1), vec![State(1)])]
+        );
+        assert!(!comparison.is_match());
+    }
+
+    #[test]
+    fn test_check_wrong_sizes() {
+        let mut region = toggle_region();
+        let def = GadgetDef::from_traversals(2, 3, spsp_multi![]);
+        assert!(region.check(&def, DEFAULT_BUDGET).is_err());
+
+        let def = GadgetDef::from_traversals(1, 2, spsp_multi![]);
+        assert!(region.check(&def, DEFAULT_BUDGET).is_err());
+    }
+
+    #[test]
+    fn test_relation_wires_in_series() {
+        let wire =
+            GadgetDef::from_traversals(1, 2, spsp_multi![((0, 0), (0, 1)), ((0, 1), (0, 0))]);
+        let wire = Rc::new(wire);
+
+        let mut grid = Grid::new();
+        grid.insert(
+            Gadget::new(&wire, (1, 1), vec![0, 2], State(0)),
+            vec2(0, 0),
+            (1, 1),
+        );
+        grid.insert(
+            Gadget::new(&wire, (1, 1), vec![0, 2], State(0)),
+            vec2(0, 1),
+            (1, 1),
+        );
+
+        let mut region = Region::new(&grid);
+        assert_eq!(region.boundary_edges().len(), 6);
+        region.toggle_port(vec2(1, 0));
+        region.toggle_port(vec2(1, 4));
+        region.add_representative(vec![State(0), State(0)]);
+
+        let relation = region.relation(DEFAULT_BUDGET);
+        assert_eq!(
+            relation.traversals,
+            spsp_multi![((0, 0), (0, 1)), ((0, 1), (0, 0))].collect()
+        );
+    }
+
+    #[test]
+    fn test_compose_toggle() {
+        let mut region = toggle_region();
+        let gadget = region.compose(vec![State(0)], DEFAULT_BUDGET).unwrap();
+
+        assert_eq!(gadget.size(), (1, 1));
+        assert_eq!(gadget.port_map(), &[0, 2]);
+        assert_eq!(gadget.def().num_states(), 2);
+        assert_eq!(
+            gadget
+                .def()
+                .traversals()
+                .copied()
+                .collect::<FnvHashSet<_>>(),
+            toggle_def().traversals().copied().collect()
+        );
+    }
+
+    #[test]
+    fn test_compose_merges_equivalent() {
+        // Flips state every time it's crossed, but can always be crossed
+        let def = GadgetDef::from_traversals(2, 2, spsp_multi![((0, 0), (1, 1)), ((1, 0), (0, 1))]);
+        let flipper = Rc::new(def);
+
+        let mut grid = Grid::new();
+        grid.insert(
+            Gadget::new(&flipper, (1, 1), vec![0, 2], State(0)),
+            vec2(0, 0),
+            (1, 1),
+        );
+        grid.insert(
+            Gadget::new(&flipper, (1, 1), vec![0, 2], State(0)),
+            vec2(0, 1),
+            (1, 1),
+        );
+
+        let mut region = Region::new(&grid);
+        region.toggle_port(vec2(1, 0));
+        region.toggle_port(vec2(1, 4));
+
+        let gadget = region
+            .compose(vec![State(0), State(0)], DEFAULT_BUDGET)
+            .unwrap();
+        assert_eq!(gadget.size(), (1, 2));
+        assert_eq!(gadget.port_map(), &[0, 3]);
+        assert_eq!(gadget.def().num_states(), 1);
+        assert_eq!(
+            gadget
+                .def()
+                .traversals()
+                .copied()
+                .collect::<FnvHashSet<_>>(),
+            spsp_multi![((0, 0), (0, 1))].collect()
+        );
+    }
+
+    #[test]
+    fn test_compose_port_inside_bounding_box() {
+        let wire =
+            GadgetDef::from_traversals(1, 2, spsp_multi![((0, 0), (0, 1)), ((0, 1), (0, 0))]);
+        let wire = Rc::new(wire);
+
+        // An L shape
+        let mut grid = Grid::new();
+        grid.insert(
+            Gadget::new(&wire, (1, 1), vec![0, 2], State(0)),
+            vec2(0, 0),
+            (1, 1),
+        );
+        grid.insert(
+            Gadget::new(&wire, (1, 1), vec![0, 2], State(0)),
+            vec2(0, 1),
+            (1, 1),
+        );
+        grid.insert(
+            Gadget::new(&wire, (1, 1), vec![1, 3], State(0)),
+            vec2(1, 0),
+            (1, 1),
+        );
+
+        let mut region = Region::new(&grid);
+        assert!(region.toggle_port(vec2(3, 2)));
+        assert!(region.compose(vec![State(0); 3], DEFAULT_BUDGET).is_err());
+    }
+}
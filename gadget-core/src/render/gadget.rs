@@ -0,0 +1,311 @@
+use super::lang::{self, Grl};
+use super::{gadget_rectangle, Triangles};
+use crate::gadget::Gadget;
+use crate::grid::WH;
+use crate::math::Vec3;
+use crate::shape::{Circle, Path, Shape};
+
+use cgmath::{vec2, vec4, Vector4};
+
+use std::any::Any;
+use std::cell::{Ref, RefCell};
+use std::rc::Rc;
+
+/// A shape that a gadget is drawn with.
+/// The triangles on screen and vector exports are both made from these,
+/// so they look the same.
+#[derive(Clone, Debug)]
+pub enum GadgetShape {
+    /// The background rectangle, from the origin to the gadget's size
+    Rectangle {
+        size: WH,
+    },
+    Circle {
+        center: Vec3,
+        radius: f64,
+        color: Vector4<f32>,
+    },
+    /// A convex polygon
+    Polygon {
+        points: Vec<Vec3>,
+        color: Vector4<f32>,
+    },
+    Path {
+        path: Path,
+        color: Vector4<f32>,
+    },
+    /// Raw triangles, which can have a color per vertex
+    Triangles(Triangles),
+}
+
+impl GadgetShape {
+    pub fn triangles(&self) -> Triangles {
+        match self {
+            GadgetShape::Rectangle { size } => {
+                let mut triangles = gadget_rectangle();
+
+                for v in triangles.vertices_mut() {
+                    v.position.x *= size.0 as f32;
+                    v.position.y *= size.1 as f32;
+                }
+
+                triangles
+            }
+
+            GadgetShape::Circle {
+                center,
+                radius,
+                color,
+            } => Circle::new(center.x, center.y, center.z, *radius).triangles(*color),
+
+            GadgetShape::Polygon { points, color } => (
+                points.clone(),
+                (1..points.len().max(2) as u32 - 1)
+                    .flat_map(|i| vec![0, i, i + 1])
+                    .collect(),
+            )
+                .triangles(*color),
+
+            GadgetShape::Path { path, color } => path.triangles(*color),
+
+            GadgetShape::Triangles(triangles) => triangles.clone(),
+        }
+    }
+
+    /// Gets the depth of the shape; shapes with smaller depth are in front
+    pub fn z(&self) -> f64 {
+        match self {
+            GadgetShape::Rectangle { .. } => 0.0,
+            GadgetShape::Circle { center, .. } => center.z,
+            GadgetShape::Polygon { points, .. } => points.first().map_or(0.0, |p| p.z),
+            GadgetShape::Path { path, .. } => path.z(),
+            GadgetShape::Triangles(triangles) => triangles
+                .vertices()
+                .first()
+                .map_or(0.0, |v| v.position.z as f64),
+        }
+    }
+}
+
+pub struct GadgetRenderInfo {
+    grl: Rc<Grl>,
+    grl_set: bool,
+    triangles: Triangles,
+    //paths: FnvHashMap<PP, Path>,
+    /// Cached model of the triangles, of whatever type the graphics backend uses
+    model: RefCell<Option<Rc<dyn Any>>>,
+}
+
+impl GadgetRenderInfo {
+    pub const RECTANGLE_Z: f64 = -0.001;
+    pub const OUTLINE_Z: f64 = -0.002;
+    pub const PATH_Z: f64 = -0.003;
+    pub const PORT_Z: f64 = -0.004;
+
+    pub fn triangles(&self) -> &Triangles {
+        &self.triangles
+    }
+
+    /// Gets the cached model of type `M` for the triangles,
+    /// making it with `make` first if there isn't one
+    pub fn cached_model<M: Any>(&self, make: impl FnOnce(&Triangles) -> M) -> Ref<'_, M> {
+        let cached = self
+            .model
+            .borrow()
+            .as_ref()
+            .is_some_and(|model| model.is::<M>());
+
+        if !cached {
+            let model = make(&self.triangles);
+            *self.model.borrow_mut() = Some(Rc::new(model));
+        }
+
+        Ref::map(self.model.borrow(), |model| {
+            model.as_ref().unwrap().downcast_ref().unwrap()
+        })
+    }
+
+    pub(crate) fn new() -> Self {
+        Self {
+            triangles: Triangles::new(vec![], vec![]),
+            //paths: FnvHashMap::default(),
+            model: RefCell::new(None),
+            grl: Rc::new(Grl::default()),
+            grl_set: false,
+        }
+    }
+
+    fn has_outline(&self, gadget: &Gadget) -> bool {
+        gadget.def().num_states() > 1
+    }
+
+    // Gets the path a robot takes to go from p0 to p1
+    //fn port_path(ports: PP, port_positions: &Vec<Vec2>) -> Path {
+    //    let positions: [Vec2; 2] = [port_positions[ports.0.id()], port_positions[ports.1.id()]];
+    //    let mut bezier = [vec2(0.0, 0.0), vec2(0.0, 0.0)];
+
+    //    let offset = 0.25;
+
+    //    for (pos, bez) in positions.iter().zip(bezier.iter_mut()) {
+    //        *bez = pos
+    //            + if pos.x.floor() == pos.x {
+    //                // on vertical edge
+    //                if pos.x == 0.0 {
+    //                    // on left edge
+    //                    vec2(offset, 0.0)
+    //                } else {
+    //                    // on right edge
+    //                    vec2(-offset, 0.0)
+    //                }
+    //            } else {
+    //                // on horizontal edge
+    //                if pos.y == 0.0 {
+    //                    // on bottom edge
+    //                    vec2(0.0, offset)
+    //                } else {
+    //                    // on top edge
+    //                    vec2(0.0, -offset)
+    //                }
+    //            }
+    //    }
+
+    //    // Same-port traversal; make it look like a loop
+    //    if bezier[0] == bezier[1] {
+    //        let dv = (bezier[0] - positions[0]).right_ccw();
+
+    //        bezier[0] += dv;
+    //        bezier[1] -= dv;
+    //    }
+
+    //    Path::from_bezier3(
+    //        [positions[0], bezier[0], bezier[1], positions[1]],
+    //        GadgetRenderInfo::PATH_Z,
+    //        0.05,
+    //    )
+    //}
+
+    /// Gets the shapes a gadget is drawn with.
+    /// This uses the renderer language description set by the last update.
+    pub fn gadget_shapes(&self, gadget: &Gadget) -> Vec<GadgetShape> {
+        let mut shapes = vec![];
+
+        // Surrounding rectangle
+        shapes.push(GadgetShape::Rectangle {
+            size: gadget.size(),
+        });
+
+        // Port circles
+        let port_positions = gadget.port_positions();
+        for vec in port_positions.iter() {
+            shapes.push(GadgetShape::Circle {
+                center: vec.extend(GadgetRenderInfo::PORT_Z),
+                radius: 0.05,
+                color: vec4(0.0, 0.0, 0.75, 1.0),
+            });
+        }
+
+        // Outline
+        if self.has_outline(gadget) {
+            let path = Path::new(
+                vec![
+                    vec2(0.0, 0.0),
+                    vec2(0.0, gadget.size().1 as f64),
+                    vec2(gadget.size().0 as f64, gadget.size().1 as f64),
+                    vec2(gadget.size().0 as f64, 0.0),
+                ],
+                GadgetRenderInfo::OUTLINE_Z,
+                0.05,
+                true,
+            );
+
+            shapes.push(GadgetShape::Path {
+                path,
+                color: vec4(0.0, 0.0, 0.0, 1.0),
+            });
+        }
+
+        // Paths
+        shapes.extend(self.grl.gadget_shapes(gadget));
+        shapes
+    }
+
+    /// Updates the rendering information so
+    /// that it is correct when rendering
+    pub(crate) fn update(&mut self, gadget: &Gadget) {
+        self.triangles.clear();
+        //self.paths.clear();
+        *self.model.borrow_mut() = None;
+
+        if !self.grl_set {
+            self.grl_set = true;
+            self.grl = lang::get_grl(gadget.def());
+        }
+
+        for shape in self.gadget_shapes(gadget) {
+            self.triangles.append(shape.triangles());
+        }
+        //for ports in gadget.def().port_traversals_in_state(gadget.state()) {
+        //    let path = GadgetRenderInfo::port_path(ports, &port_positions);
+
+        //    self.paths.insert(ports, path);
+        //}
+
+        //for ((p0, p1), path) in &self.paths {
+        //    let directed = self.paths.get(&(*p1, *p0)).is_none();
+
+        //    // No redundant path drawing!
+        //    if p0 <= p1 || directed {
+        //        self.triangles
+        //            .append(path.triangles(vec4(0.0, 0.0, 0.0, 1.0)));
+        //    }
+
+        //    if directed {
+        //        let dir = path.end_direction();
+        //        let end: Vec2 = port_positions[p1.id()];
+
+        //        let v0: Vec2 = end + dir * -0.2 + dir.right_ccw() * -0.1;
+        //        let v2: Vec2 = end + dir * -0.2 + dir.right_ccw() * 0.1;
+
+        //        self.triangles.append(Triangles::new(
+        //            vec![
+        //                Vertex::new(
+        //                    vec3(v0.x as f32, v0.y as f32, GadgetRenderInfo::PATH_Z as f32),
+        //                    vec3(0.0, 0.0, 0.0),
+        //                    vec4(0.0, 0.0, 0.0, 1.0),
+        //                    [],
+        //                ),
+        //                Vertex::new(
+        //                    vec3(end.x as f32, end.y as f32, GadgetRenderInfo::PATH_Z as f32),
+        //                    vec3(0.0, 0.0, 0.0),
+        //                    vec4(0.0, 0.0, 0.0, 1.0),
+        //                    [],
+        //                ),
+        //                Vertex::new(
+        //                    vec3(v2.x as f32, v2.y as f32, GadgetRenderInfo::PATH_Z as f32),
+        //                    vec3(0.0, 0.0, 0.0),
+        //                    vec4(0.0, 0.0, 0.0, 1.0),
+        //                    [],
+        //                ),
+        //            ],
+        //            vec![0, 1, 2],
+        //        ));
+        //    }
+        //}
+    }
+}
+
+/// Drawing for gadgets, which the gadget module knows nothing about
+pub trait GadgetEx {
+    /// Gets the render info, which is updated if the gadget changed since it was last drawn
+    fn renderer(&self) -> Ref<'_, GadgetRenderInfo>;
+}
+
+impl GadgetEx for Gadget {
+    fn renderer(&self) -> Ref<'_, GadgetRenderInfo> {
+        self.render_cache(|gadget| {
+            let mut render = GadgetRenderInfo::new();
+            render.update(gadget);
+            render
+        })
+    }
+}
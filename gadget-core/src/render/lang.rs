@@ -1,6 +1,6 @@
 use cgmath::prelude::*;
 use cgmath::{vec2, vec3, Vector2, Vector3, Vector4};
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap, FnvHashSet};
 use ref_thread_local::{ref_thread_local, RefThreadLocal};
 use std::cell::RefCell;
 use std::collections::HashMap;
@@ -8,8 +8,9 @@ use std::iter::Sum;
 use std::ops::Add;
 use std::rc::Rc;
 
-use super::{GadgetRenderInfo, Triangles, Vertex};
-use crate::gadget::{Gadget, GadgetDef, State, PP};
+use super::{GadgetRenderInfo, GadgetShape, Triangles, Vertex};
+use crate::canonical::{self, Relabelling};
+use crate::gadget::{Gadget, GadgetDef, Port, State, PP};
 use crate::math::{Mat2, Vec2, Vec3, Vector2Ex, TAU_F64};
 use crate::shape::{Circle, Path, Shape};
 use crate::static_map::StaticMap;
@@ -95,6 +96,23 @@ impl Term {
     }
 }
 
+/// Gets the point at `t` along the path that the traversal between `ports` is drawn with,
+/// along with the direction of the path there
+pub fn port_path_point(ports: PP, t: f64, port_positions: &[Vec2]) -> (Vec2, Vec2) {
+    let vector = |point_factor, dir_factors| {
+        Term {
+            ports,
+            t,
+            point_factor,
+            dir_factors,
+        }
+        .vector(port_positions)
+        .truncate()
+    };
+
+    (vector(1.0, vec2(0.0, 0.0)), vector(0.0, vec2(0.0, 1.0)))
+}
+
 /// Position is some terms added together
 // #[derive(Clone, Debug, PartialEq)]
 // pub struct PositionDef {
@@ -220,6 +238,16 @@ impl GrlPosition {
             GrlPosition::Add(vec) => vec.into_iter().map(|p| p.position(port_positions)).sum(),
         }
     }
+
+    fn relabel_ports(&mut self, ports: &[Port]) {
+        match self {
+            GrlPosition::Absolute(_) => {}
+            GrlPosition::Term(term) => {
+                term.ports = (ports[term.ports.0.id()], ports[term.ports.1.id()])
+            }
+            GrlPosition::Add(vec) => vec.iter_mut().for_each(|p| p.relabel_ports(ports)),
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -230,6 +258,17 @@ pub enum GrlPath {
 }
 
 impl GrlPath {
+    fn relabel_ports(&mut self, ports: &[Port]) {
+        match self {
+            GrlPath::Line { points } => {
+                points.0.relabel_ports(ports);
+                points.1.relabel_ports(ports);
+            }
+            GrlPath::Circle { position, .. } => position.relabel_ports(ports),
+            GrlPath::PortPath { ports: pp, .. } => *pp = (ports[pp.0.id()], ports[pp.1.id()]),
+        }
+    }
+
     fn path(&self, thickness: f64, port_positions: &[Vec2]) -> Path {
         match self {
             GrlPath::Line { points } => {
@@ -327,16 +366,32 @@ fn rectangle_points(center: Vec3, up: Vec3, width: f64, height: f64) -> Vec<Vec3
 }
 
 impl GrlShape {
-    fn triangles(&self, port_positions: &[Vec2]) -> Triangles {
+    fn relabel_ports(&mut self, ports: &[Port]) {
+        match self {
+            GrlShape::Circle { position, .. } => position.relabel_ports(ports),
+            GrlShape::Rectangle { position, up, .. } => {
+                position.relabel_ports(ports);
+                up.relabel_ports(ports);
+            }
+            GrlShape::Path { path, .. } => path.relabel_ports(ports),
+            GrlShape::Triangles { vertices, .. } => vertices
+                .iter_mut()
+                .for_each(|(p, _)| p.relabel_ports(ports)),
+        }
+    }
+
+    /// Gets the shapes this is drawn with
+    fn gadget_shapes(&self, port_positions: &[Vec2]) -> Vec<GadgetShape> {
         match self {
             GrlShape::Circle {
                 position,
                 radius,
                 color,
-            } => {
-                let position = position.position(port_positions);
-                Circle::new(position.x, position.y, position.z, *radius).triangles(*color)
-            }
+            } => vec![GadgetShape::Circle {
+                center: position.position(port_positions),
+                radius: *radius,
+                color: *color,
+            }],
 
             GrlShape::Rectangle {
                 position,
@@ -344,16 +399,15 @@ impl GrlShape {
                 width,
                 height,
                 color,
-            } => (
-                rectangle_points(
+            } => vec![GadgetShape::Polygon {
+                points: rectangle_points(
                     position.position(port_positions),
                     up.position(port_positions),
                     *width,
                     *height,
                 ),
-                vec![0, 1, 2, 2, 3, 0u32],
-            )
-                .triangles(*color),
+                color: *color,
+            }],
 
             GrlShape::Path {
                 path,
@@ -364,48 +418,27 @@ impl GrlShape {
             } => {
                 let mut path = path.path(*thickness, port_positions);
                 let z = path.z();
-                let mut extra_tris = Triangles::default();
+                let mut shapes = vec![];
 
                 if let Some((w, h)) = end_arrow_wh {
                     path = path.iter().subpath(path.len() - *h);
 
                     let dir = path.end_direction();
-                    extra_tris.append(Triangles::new(
-                        vec![
-                            Vertex::new(
-                                (path.end_position() + dir.right_cw() * *w / 2.0)
-                                    .extend(z)
-                                    .cast::<f32>()
-                                    .unwrap(),
-                                vec3(0., 0., 0.),
-                                *color,
-                                [],
-                            ),
-                            Vertex::new(
-                                (path.end_position() + dir * *h)
-                                    .extend(z)
-                                    .cast::<f32>()
-                                    .unwrap(),
-                                vec3(0., 0., 0.),
-                                *color,
-                                [],
-                            ),
-                            Vertex::new(
-                                (path.end_position() + dir.right_ccw() * *w / 2.0)
-                                    .extend(z)
-                                    .cast::<f32>()
-                                    .unwrap(),
-                                vec3(0., 0., 0.),
-                                *color,
-                                [],
-                            ),
+                    shapes.push(GadgetShape::Polygon {
+                        points: vec![
+                            (path.end_position() + dir.right_cw() * *w / 2.0).extend(z),
+                            (path.end_position() + dir * *h).extend(z),
+                            (path.end_position() + dir.right_ccw() * *w / 2.0).extend(z),
                         ],
-                        vec![0, 1, 2],
-                    ));
+                        color: *color,
+                    });
                 }
 
                 match line_style {
-                    GrlLineStyle::Solid => extra_tris.append(path.triangles(*color)),
+                    GrlLineStyle::Solid => shapes.push(GadgetShape::Path {
+                        path,
+                        color: *color,
+                    }),
 
                     GrlLineStyle::Dotted {
                         on_space,
@@ -413,29 +446,34 @@ impl GrlShape {
                     } => {
                         let mut iter = path.iter();
                         while !iter.finished() {
-                            extra_tris.append(iter.subpath(*on_space).triangles(*color));
+                            shapes.push(GadgetShape::Path {
+                                path: iter.subpath(*on_space),
+                                color: *color,
+                            });
                             iter.advance(*off_space);
                         }
                     }
                 }
 
-                extra_tris
+                shapes
             }
 
-            GrlShape::Triangles { vertices, indexes } => Triangles::new(
-                vertices
-                    .iter()
-                    .map(|v| {
-                        Vertex::new(
-                            v.0.position(port_positions).cast::<f32>().unwrap(),
-                            vec3(0., 0., 0.),
-                            v.1,
-                            [],
-                        )
-                    })
-                    .collect(),
-                indexes.clone(),
-            ),
+            GrlShape::Triangles { vertices, indexes } => {
+                vec![GadgetShape::Triangles(Triangles::new(
+                    vertices
+                        .iter()
+                        .map(|v| {
+                            Vertex::new(
+                                v.0.position(port_positions).cast::<f32>().unwrap(),
+                                vec3(0., 0., 0.),
+                                v.1,
+                                [],
+                            )
+                        })
+                        .collect(),
+                    indexes.clone(),
+                ))]
+            }
         }
     }
 }
@@ -446,15 +484,13 @@ pub struct GrlState {
 }
 
 impl GrlState {
-    fn triangles(&self, gadget: &Gadget) -> Triangles {
-        let mut triangles = Triangles::default();
+    fn gadget_shapes(&self, gadget: &Gadget) -> Vec<GadgetShape> {
         let port_positions = gadget.port_positions();
 
-        for new_triangles in self.shapes.iter().map(|s| s.triangles(&port_positions)) {
-            triangles.append(new_triangles);
-        }
-
-        triangles
+        self.shapes
+            .iter()
+            .flat_map(|s| s.gadget_shapes(&port_positions))
+            .collect()
     }
 }
 
@@ -471,8 +507,25 @@ impl Grl {
     pub const DEFAULT_DOTTED_ON_SPACE: f64 = 0.04;
     pub const DEFAULT_DOTTED_OFF_SPACE: f64 = 0.08;
 
-    pub fn triangles(&self, gadget: &Gadget) -> Triangles {
-        self.states[gadget.state().id()].triangles(gadget)
+    /// Gets the shapes a gadget in its current state is drawn with
+    pub fn gadget_shapes(&self, gadget: &Gadget) -> Vec<GadgetShape> {
+        self.states[gadget.state().id()].gadget_shapes(gadget)
+    }
+
+    /// Gets the renderer for the def that a relabelling turns this renderer's def into.
+    /// Reversal is ignored.
+    pub fn relabel(&self, relabelling: &Relabelling) -> Grl {
+        let mut states = vec![GrlState::default(); self.states.len()];
+
+        for (state, grl_state) in self.states.iter().enumerate() {
+            let mut grl_state = grl_state.clone();
+            for shape in grl_state.shapes.iter_mut() {
+                shape.relabel_ports(&relabelling.ports);
+            }
+            states[relabelling.states[state].id()] = grl_state;
+        }
+
+        Grl { states }
     }
 }
 
@@ -703,6 +756,8 @@ impl GrlCache {
         grl
     }
 
+    /// Gets the renderer for a def.
+    /// Custom renderers also apply to defs that are only relabelled versions of their defs.
     fn get(&self, def: &GadgetDef) -> Rc<Grl> {
         let hash_string = def.hash_string();
 
@@ -712,10 +767,24 @@ impl GrlCache {
             return Rc::clone(grl);
         }
 
-        let grl = Rc::new(Self::get_default(def));
-        self.0
-            .borrow_mut()
-            .insert(def.hash_string(), Rc::clone(&grl));
+        // Only defs that could be relabelled versions of ones with custom renderers
+        // are worth searching for a canonical form
+        let canonical = if GRL_INVARIANTS.borrow().contains(&canonical::invariant(def)) {
+            canonical::canonical_hash_string(def, canonical::DEFAULT_BUDGET)
+        } else {
+            None
+        };
+
+        let grl = match canonical.and_then(|(canonical_hash, relabelling)| {
+            let grls = GRLS.borrow();
+            let grl = grls.get(&canonical_hash)?;
+            Some(grl.relabel(&relabelling.inverse()))
+        }) {
+            Some(grl) => Rc::new(grl),
+            None => Rc::new(Self::get_default(def)),
+        };
+
+        self.0.borrow_mut().insert(hash_string, Rc::clone(&grl));
         grl
     }
 }
@@ -728,6 +797,11 @@ ref_thread_local! {
     static managed GRL_CACHE: GrlCache = GrlCache::new();
 }
 
+// Invariants of the defs with custom renderers
+ref_thread_local! {
+    static managed GRL_INVARIANTS: FnvHashSet<canonical::Invariant> = FnvHashSet::default();
+}
+
 type GrlMap = FnvHashMap<String, Rc<Grl>>;
 
 ref_thread_local!(
@@ -736,24 +810,42 @@ ref_thread_local!(
     );
 );
 
-/// If the boolean is true, then replace; otherwise combine with the default
+/// If the boolean is true, then replace; otherwise combine with the default.
+/// Each renderer is stored under its def's hash string,
+/// and also relabelled under the hash string of its def's canonical form.
+/// Exact matches take priority.
+/// Also records the invariants of the defs, for `GrlCache::get` to check before canonicalizing.
 fn grl_map(map: Vec<(Rc<GadgetDef>, Grl, bool)>) -> GrlMap {
-    map.into_iter()
+    let grls = map
+        .into_iter()
         .map(|(def, mut grl, replace)| {
-            (def.hash_string(), {
-                if !replace {
-                    let default = GrlCache::get_default(&def);
-                    for (state, mut default_state) in
-                        grl.states.iter_mut().zip(default.states.into_iter())
-                    {
-                        state.shapes.append(&mut default_state.shapes)
-                    }
+            if !replace {
+                let default = GrlCache::get_default(&def);
+                for (state, mut default_state) in grl.states.iter_mut().zip(default.states) {
+                    state.shapes.append(&mut default_state.shapes)
                 }
+            }
 
-                Rc::new(grl)
-            })
+            (def, grl)
         })
-        .collect()
+        .collect::<Vec<_>>();
+
+    GRL_INVARIANTS
+        .borrow_mut()
+        .extend(grls.iter().map(|(def, _)| canonical::invariant(def)));
+
+    // Defs too symmetric to canonicalize within the budget only match exactly
+    let canonical = grls.iter().filter_map(|(def, grl)| {
+        let (hash_string, relabelling) =
+            canonical::canonical_hash_string(def, canonical::DEFAULT_BUDGET)?;
+        Some((hash_string, Rc::new(grl.relabel(&relabelling))))
+    });
+
+    let exact = grls
+        .iter()
+        .map(|(def, grl)| (def.hash_string(), Rc::new(grl.clone())));
+
+    canonical.chain(exact).collect()
 }
 
 #[cfg(test)]
@@ -828,6 +920,23 @@ mod test {
         )
     }
 
+    #[test]
+    fn test_port_path_point() {
+        // Bottom port to top port of a 1x1 gadget
+        let port_positions = [vec2(0.5, 0.0), vec2(0.5, 1.0)];
+
+        let (start, start_dir) = port_path_point((Port(0), Port(1)), 0.0, &port_positions);
+        assert_eq!(start, vec2(0.5, 0.0));
+        assert!((start_dir - vec2(0.0, 1.0)).magnitude() < 1e-9);
+
+        let (middle, _) = port_path_point((Port(0), Port(1)), 0.5, &port_positions);
+        assert!((middle - vec2(0.5, 0.5)).magnitude() < 1e-9);
+
+        let (end, end_dir) = port_path_point((Port(0), Port(1)), 1.0, &port_positions);
+        assert_eq!(end, vec2(0.5, 1.0));
+        assert!((end_dir - vec2(0.0, 1.0)).magnitude() < 1e-9);
+    }
+
     // TODO: More run-time tests
 
     #[test]
@@ -880,4 +989,23 @@ mod test {
             }
         );
     }
+
+    #[test]
+    fn test_relabel() {
+        let test = grl!(
+            {(circle (0., 1., 2.), 0.5)}
+            {(path (port_path 0 => 1, 0.1 => 0.9, 1.0), solid)}
+        );
+        let relabelling = Relabelling {
+            states: vec![State(1), State(0)],
+            ports: vec![Port(1), Port(2), Port(0)],
+            reversed: false,
+        };
+
+        let expected = grl!(
+            {(path (port_path 1 => 2, 0.1 => 0.9, 1.0), solid)}
+            {(circle (0., 1., 2.), 0.5)}
+        );
+        assert_eq!(test.relabel(&relabelling), expected);
+    }
 }
@@ -0,0 +1,11 @@
+//! What contraptions are drawn with, without a graphics backend
+
+mod camera;
+mod gadget;
+pub mod lang;
+pub mod raster;
+mod triangles;
+
+pub use camera::Camera;
+pub use gadget::{GadgetEx, GadgetRenderInfo, GadgetShape};
+pub use triangles::{gadget_rectangle, Triangles, TrianglesEx, Vertex, VertexEx};
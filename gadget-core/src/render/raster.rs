@@ -0,0 +1,494 @@
+//! Software rendering of contraptions, for images made where there is no WebGL
+
+use cgmath::prelude::*;
+use cgmath::{vec2, vec3, Vector3, Vector4};
+
+use super::{gadget_rectangle, Camera, GadgetEx, GadgetRenderInfo, Triangles};
+use crate::gadget::Gadget;
+use crate::grid::Grid;
+use crate::math::{Mat4, Vec2};
+
+/// A vertex after transformation
+#[derive(Clone, Copy, Debug)]
+struct ScreenVertex {
+    /// Position in pixels, with y pointing down
+    position: Vec2,
+    /// Depth in [0, 1], like the depth buffer on screen
+    depth: f64,
+    /// 1 / w in clip space, for perspective correct interpolation
+    inv_w: f64,
+    color: Vector4<f32>,
+}
+
+/// Signed double area of the triangle (a, b, c);
+/// positive when it winds clockwise on screen
+fn edge(a: Vec2, b: Vec2, c: Vec2) -> f64 {
+    (c.x - a.x) * (b.y - a.y) - (c.y - a.y) * (b.x - a.x)
+}
+
+/// Whether pixels exactly on the edge from a to b are drawn.
+/// An edge shared by 2 triangles goes in opposite directions in each,
+/// so exactly one of them draws those pixels.
+fn owns_edge(a: Vec2, b: Vec2) -> bool {
+    let d = b - a;
+    d.y > 0.0 || (d.y == 0.0 && d.x < 0.0)
+}
+
+/// A color buffer and depth buffer that triangles are drawn to,
+/// with the same depth test and blending as the canvas
+pub struct Raster {
+    width: usize,
+    height: usize,
+    /// Colors, row by row from the top
+    colors: Vec<Vector3<f32>>,
+    depths: Vec<f64>,
+}
+
+impl Raster {
+    /// Creates a cleared raster that is (width, height) pixels big
+    pub fn new(width: usize, height: usize) -> Self {
+        assert!(width > 0 && height > 0, "Raster must not be empty");
+
+        let mut raster = Self {
+            width,
+            height,
+            colors: vec![Vector3::zero(); width * height],
+            depths: vec![0.0; width * height],
+        };
+        raster.clear();
+        raster
+    }
+
+    /// Clears to black and the farthest depth.
+    /// The canvas clears depth to -1, which gets clamped to 0.
+    pub fn clear(&mut self) {
+        for color in &mut self.colors {
+            *color = Vector3::zero();
+        }
+        for depth in &mut self.depths {
+            *depth = 0.0;
+        }
+    }
+
+    /// Gets the color of a pixel, with (0, 0) at the top left
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let color = self.colors[y * self.width + x];
+        let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+        [channel(color.x), channel(color.y), channel(color.z)]
+    }
+
+    fn transform_vertex(
+        &self,
+        transform: &Mat4,
+        position: Vector3<f32>,
+    ) -> Option<(Vec2, f64, f64)> {
+        let clip = transform * position.cast::<f64>().unwrap().extend(1.0);
+        if clip.w <= 0.0 {
+            return None;
+        }
+        let ndc = clip.truncate() / clip.w;
+
+        Some((
+            vec2(
+                (ndc.x + 1.0) / 2.0 * self.width as f64,
+                (1.0 - ndc.y) / 2.0 * self.height as f64,
+            ),
+            (ndc.z + 1.0) / 2.0,
+            1.0 / clip.w,
+        ))
+    }
+
+    /// Draws triangles, transformed by `transform` into clip space
+    pub fn draw_triangles(&mut self, triangles: &Triangles, transform: &Mat4) {
+        let vertices = triangles
+            .vertices()
+            .iter()
+            .map(|v| {
+                self.transform_vertex(transform, v.position)
+                    .map(|(position, depth, inv_w)| ScreenVertex {
+                        position,
+                        depth,
+                        inv_w,
+                        color: v.color,
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        for indexes in triangles.indexes().chunks_exact(3) {
+            let v0 = vertices[indexes[0] as usize];
+            let v1 = vertices[indexes[1] as usize];
+            let v2 = vertices[indexes[2] as usize];
+
+            // Triangles behind the camera are skipped instead of clipped
+            if let (Some(v0), Some(v1), Some(v2)) = (v0, v1, v2) {
+                self.draw_triangle(v0, v1, v2);
+            }
+        }
+    }
+
+    fn draw_triangle(&mut self, v0: ScreenVertex, mut v1: ScreenVertex, mut v2: ScreenVertex) {
+        let mut area = edge(v0.position, v1.position, v2.position);
+        if area == 0.0 {
+            return;
+        }
+        // Nothing is culled, so wind every triangle the same way
+        if area < 0.0 {
+            std::mem::swap(&mut v1, &mut v2);
+            area = -area;
+        }
+
+        let (p0, p1, p2) = (v0.position, v1.position, v2.position);
+
+        let min_x = p0.x.min(p1.x).min(p2.x).floor().max(0.0) as usize;
+        let min_y = p0.y.min(p1.y).min(p2.y).floor().max(0.0) as usize;
+        let max_x = (p0.x.max(p1.x).max(p2.x).ceil().max(0.0) as usize).min(self.width);
+        let max_y = (p0.y.max(p1.y).max(p2.y).ceil().max(0.0) as usize).min(self.height);
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let p = vec2(x as f64 + 0.5, y as f64 + 0.5);
+
+                let w0 = edge(p1, p2, p);
+                let w1 = edge(p2, p0, p);
+                let w2 = edge(p0, p1, p);
+
+                let inside = |w: f64, a: Vec2, b: Vec2| w > 0.0 || (w == 0.0 && owns_edge(a, b));
+                if !inside(w0, p1, p2) || !inside(w1, p2, p0) || !inside(w2, p0, p1) {
+                    continue;
+                }
+
+                let (l0, l1, l2) = (w0 / area, w1 / area, w2 / area);
+
+                // Depth is interpolated linearly on screen, and outside the depth range is clipped
+                let depth = l0 * v0.depth + l1 * v1.depth + l2 * v2.depth;
+                if !(0.0..=1.0).contains(&depth) {
+                    continue;
+                }
+
+                let index = y * self.width + x;
+                if depth < self.depths[index] {
+                    continue;
+                }
+                self.depths[index] = depth;
+
+                let (q0, q1, q2) = (l0 * v0.inv_w, l1 * v1.inv_w, l2 * v2.inv_w);
+                let color = (v0.color * q0 as f32 + v1.color * q1 as f32 + v2.color * q2 as f32)
+                    / (q0 + q1 + q2) as f32;
+
+                let alpha = color.w.clamp(0.0, 1.0);
+                let dest = &mut self.colors[index];
+                *dest = color.truncate() * alpha + *dest * (1.0 - alpha);
+            }
+        }
+    }
+
+    /// Draws a grid of gadgets the way `render_grid` draws it on screen,
+    /// including the background of empty cells.
+    /// Assumes the camera is orthographic and looking directly at the grid.
+    pub fn draw_grid(&mut self, grid: &Grid<Gadget>, camera: &Camera) {
+        let center = camera.position();
+        let ortho = camera.get_projection();
+
+        let width = 2.0 / ortho.x.x;
+        let height = 2.0 / ortho.y.y;
+
+        let min_x = center.x - width / 2.0;
+        let max_x = center.x + width / 2.0;
+        let min_y = center.y - height / 2.0;
+        let max_y = center.y + height / 2.0;
+
+        let view_projection = camera.get_projection() * camera.get_view();
+
+        for (gadget, xy, _) in grid.get_in_bounds(min_x, max_x, min_y, max_y) {
+            let transform =
+                view_projection * Mat4::from_translation(vec3(xy.x as f64, xy.y as f64, 0.0));
+            self.draw_triangles(gadget.renderer().triangles(), &transform);
+        }
+
+        // The background is drawn last on screen too
+        let rectangle = gadget_rectangle();
+        for xy in grid.get_empty_in_bounds(min_x, max_x, min_y, max_y) {
+            let transform = view_projection
+                * Mat4::from_translation(vec3(
+                    xy.x as f64,
+                    xy.y as f64,
+                    GadgetRenderInfo::RECTANGLE_Z,
+                ));
+            self.draw_triangles(&rectangle, &transform);
+        }
+    }
+
+    /// Encodes the colors as an RGB PNG image
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity((3 * self.width + 1) * self.height);
+        for y in 0..self.height {
+            // No filter
+            data.push(0);
+            for x in 0..self.width {
+                data.extend_from_slice(&self.pixel(x, y));
+            }
+        }
+
+        let mut header = vec![];
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // Bit depth 8, truecolor, and the default compression, filtering and interlacing
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        write_chunk(&mut png, b"IHDR", &header);
+        write_chunk(&mut png, b"IDAT", &zlib_stored(&data));
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+/// Writes a PNG chunk with its length and checksum
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wraps data in a zlib stream without compressing it.
+/// Contraption images are small, so this keeps things simple.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = 0xffff;
+
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[1, 0x00, 0x00, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+
+        zlib.push(last as u8);
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+
+    zlib.extend_from_slice(&adler32(data).to_be_bytes());
+    zlib
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % MOD;
+        b = (b + a) % MOD;
+    }
+    (b << 16) | a
+}
+
+/// Draws the part of a grid between `min` and `max` as a PNG image that is
+/// (width, height) pixels big, with the same camera setup as on screen
+pub fn grid_to_png(
+    grid: &Grid<Gadget>,
+    min: Vec2,
+    max: Vec2,
+    (width, height): (usize, usize),
+) -> Vec<u8> {
+    let center = (min + max) / 2.0;
+    let camera = Camera::new_orthographic(
+        center.extend(0.0),
+        center.extend(-1.0),
+        vec3(0.0, 1.0, 0.0),
+        max.x - min.x,
+        max.y - min.y,
+        1.0,
+    );
+
+    let mut raster = Raster::new(width, height);
+    raster.draw_grid(grid, &camera);
+    raster.to_png()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::preset_gadgets::preset_gadgets;
+    use crate::render::Vertex;
+
+    fn square(z: f32, color: Vector4<f32>) -> Triangles {
+        let vertex = |x, y| Vertex::new(vec3(x, y, z), vec3(0.0, 0.0, 0.0), color, []);
+        Triangles::new(
+            vec![
+                vertex(0.0, 0.0),
+                vertex(1.0, 0.0),
+                vertex(1.0, 1.0),
+                vertex(0.0, 1.0),
+            ],
+            vec![0, 1, 2, 2, 3, 0],
+        )
+    }
+
+    /// The camera the app uses, looking at the unit square
+    fn camera() -> Camera {
+        Camera::new_orthographic(
+            vec3(0.5, 0.5, 0.0),
+            vec3(0.5, 0.5, -1.0),
+            vec3(0.0, 1.0, 0.0),
+            1.0,
+            1.0,
+            1.0,
+        )
+    }
+
+    fn transform(camera: &Camera) -> Mat4 {
+        camera.get_projection() * camera.get_view()
+    }
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn test_zlib_stored() {
+        assert_eq!(
+            zlib_stored(&[]),
+            vec![0x78, 0x01, 1, 0x00, 0x00, 0xff, 0xff, 0, 0, 0, 1]
+        );
+
+        let data = vec![7; 0x10000];
+        let zlib = zlib_stored(&data);
+        assert_eq!(zlib.len(), 2 + 2 * 5 + data.len() + 4);
+        assert_eq!(zlib[2], 0);
+        assert_eq!(zlib[2 + 5 + 0xffff], 1);
+    }
+
+    #[test]
+    fn test_coverage() {
+        let camera = camera();
+        let mut raster = Raster::new(4, 4);
+
+        let vertex = |x, y| {
+            Vertex::new(
+                vec3(x, y, -0.5),
+                vec3(0.0, 0.0, 0.0),
+                Vector4::new(1.0, 1.0, 1.0, 1.0),
+                [],
+            )
+        };
+        let triangle = Triangles::new(
+            vec![vertex(0.0, 0.0), vertex(1.0, 0.0), vertex(0.0, 1.0)],
+            vec![0, 1, 2],
+        );
+        raster.draw_triangles(&triangle, &transform(&camera));
+
+        // Bottom left is covered, and top right is not
+        assert_eq!(raster.pixel(0, 3), [255, 255, 255]);
+        assert_eq!(raster.pixel(3, 0), [0, 0, 0]);
+        assert_eq!(raster.pixel(0, 1), [255, 255, 255]);
+        assert_eq!(raster.pixel(1, 0), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_depth() {
+        let camera = camera();
+        let red = square(
+            GadgetRenderInfo::PORT_Z as f32,
+            Vector4::new(1.0, 0.0, 0.0, 1.0),
+        );
+        let green = square(
+            GadgetRenderInfo::RECTANGLE_Z as f32,
+            Vector4::new(0.0, 1.0, 0.0, 1.0),
+        );
+
+        // Smaller z is in front, whatever the order
+        for order in &[[&red, &green], [&green, &red]] {
+            let mut raster = Raster::new(2, 2);
+            for triangles in order {
+                raster.draw_triangles(triangles, &transform(&camera));
+            }
+            assert_eq!(raster.pixel(1, 1), [255, 0, 0]);
+        }
+
+        // Positive z is behind the camera
+        let mut raster = Raster::new(2, 2);
+        raster.draw_triangles(
+            &square(0.5, Vector4::new(1.0, 1.0, 1.0, 1.0)),
+            &transform(&camera),
+        );
+        assert_eq!(raster.pixel(1, 1), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_blending() {
+        let camera = camera();
+        let mut raster = Raster::new(8, 8);
+        raster.draw_triangles(
+            &square(-0.5, Vector4::new(1.0, 1.0, 1.0, 0.5)),
+            &transform(&camera),
+        );
+
+        // The shared diagonal is not blended twice
+        for y in 0..8 {
+            for x in 0..8 {
+                assert_eq!(raster.pixel(x, y), [128, 128, 128]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_grid_to_png() {
+        let presets = preset_gadgets();
+        let toggle = presets
+            .iter()
+            .find(|gadget| gadget.name() == "Toggle")
+            .unwrap()
+            .clone();
+
+        let mut grid = Grid::new();
+        grid.insert(toggle, vec2(0, 0), (1, 1));
+
+        let camera = Camera::new_orthographic(
+            vec3(1.0, 0.5, 0.0),
+            vec3(1.0, 0.5, -1.0),
+            vec3(0.0, 1.0, 0.0),
+            2.0,
+            1.0,
+            1.0,
+        );
+        let mut raster = Raster::new(200, 100);
+        raster.draw_grid(&grid, &camera);
+
+        // The bottom port is drawn over the gadget
+        assert_eq!(raster.pixel(50, 97), [0, 0, 191]);
+        // The empty cell has a background, which is brightest at the top right
+        assert_eq!(raster.pixel(199, 0), [229, 255, 255]);
+
+        let png = grid_to_png(&grid, vec2(0.0, 0.0), vec2(2.0, 1.0), (200, 100));
+        assert_eq!(png, raster.to_png());
+
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\0\xc8\0\0\0\x64"));
+        assert!(png.ends_with(b"\0\0\0\0IEND\xae\x42\x60\x82"));
+    }
+}
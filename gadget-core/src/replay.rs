@@ -0,0 +1,390 @@
+//! Recordings of agents moving through a contraption, so solutions can be shared and watched.
+//!
+//! A replay starts from where the agents were and what states the gadgets were in,
+//! and has every move after that, so it can be played back from any point.
+
+use bitvec::slice::BitSlice;
+use cgmath::vec2;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::bit_serde;
+use crate::gadget::{Agent, Gadget, State};
+use crate::grid::{Grid, XY};
+use crate::solver::{Step, DIRECTIONS};
+
+/// Minimal xy coords of the gadgets in a grid, bottom row first,
+/// in the order a replay stores their starting states
+fn positions(grid: &Grid<Gadget>) -> Vec<XY> {
+    let mut positions = grid.iter().map(|(_, xy, _)| *xy).collect::<Vec<_>>();
+    positions.sort_by_key(|xy| (xy.y, xy.x));
+    positions
+}
+
+/// Moves of agents, and where they started from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Replay {
+    /// Starting agents, in order
+    starts: Vec<Agent>,
+    /// Starting gadget states, in the order of `positions`
+    states: Vec<State>,
+    /// Moves, each with the index of the agent making it
+    steps: Vec<(usize, Step)>,
+}
+
+impl Replay {
+    /// Starts a recording with agents where they are and gadgets in the states they are in
+    pub fn new(grid: &Grid<Gadget>, agents: &[Agent]) -> Self {
+        let states = positions(grid)
+            .into_iter()
+            .map(|xy| grid.get(xy).unwrap().0.state())
+            .collect();
+
+        Self {
+            starts: agents.to_vec(),
+            states,
+            steps: vec![],
+        }
+    }
+
+    pub fn starts(&self) -> &[Agent] {
+        &self.starts
+    }
+
+    pub fn steps(&self) -> &[(usize, Step)] {
+        &self.steps
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Records a move of the agent at index `agent`
+    pub fn push(&mut self, agent: usize, step: Step) {
+        self.steps.push((agent, step));
+    }
+
+    /// Forgets the last move, and returns it
+    pub fn pop(&mut self) -> Option<(usize, Step)> {
+        self.steps.pop()
+    }
+
+    /// Forgets every move after the first `len`
+    pub fn truncate(&mut self, len: usize) {
+        self.steps.truncate(len);
+    }
+
+    /// Puts the gadgets back in their starting states, and returns the agents at their starts.
+    /// Fails if the grid doesn't have the gadgets the replay was recorded with.
+    pub fn rewind(&self, grid: &mut Grid<Gadget>) -> Result<Vec<Agent>, String> {
+        let positions = positions(grid);
+        if positions.len() != self.states.len() {
+            return Err(format!(
+                "The replay is for {} gadgets, but the contraption has {}",
+                self.states.len(),
+                positions.len()
+            ));
+        }
+
+        for (xy, state) in positions.iter().zip(&self.states) {
+            let num_states = grid.get(*xy).unwrap().0.def().num_states();
+            if state.id() >= num_states {
+                return Err(format!(
+                    "The replay starts the gadget at ({}, {}) in state {}, which it doesn't have",
+                    xy.x,
+                    xy.y,
+                    state.id()
+                ));
+            }
+        }
+
+        for (xy, state) in positions.into_iter().zip(&self.states) {
+            grid.get_mut(xy).unwrap().0.set_state(*state);
+        }
+
+        Ok(self.starts.clone())
+    }
+
+    /// Makes move `index` of the replay.
+    /// Returns the position and previous state of the gadget that changed state, if one did.
+    pub fn play_step(
+        &self,
+        index: usize,
+        grid: &mut Grid<Gadget>,
+        agents: &mut [Agent],
+    ) -> Option<(XY, State)> {
+        let (agent, step) = self.steps.get(index)?;
+        agents
+            .get_mut(*agent)?
+            .advance(grid, step.input, step.back, step.choice)
+            .map(|(_, xy, state)| (xy, state))
+    }
+
+    /// Rewinds, then makes the first `num_steps` moves.
+    /// Returns the agents after them.
+    pub fn seek(&self, grid: &mut Grid<Gadget>, num_steps: usize) -> Result<Vec<Agent>, String> {
+        let mut agents = self.rewind(grid)?;
+
+        for index in 0..num_steps.min(self.steps.len()) {
+            self.play_step(index, grid, &mut agents);
+        }
+
+        Ok(agents)
+    }
+}
+
+/// A replay that can be serialized and deserialized.
+/// Directions are indexes into `DIRECTIONS`.
+#[derive(Serialize, Deserialize, Debug)]
+struct ReplaySerde {
+    /// (double the position, direction) of each agent
+    starts: Vec<((isize, isize), usize)>,
+    states: Vec<State>,
+    /// (agent index, input, back, choice)
+    steps: Vec<(usize, usize, bool, usize)>,
+}
+
+/// Gets the index of a direction in `DIRECTIONS`
+fn direction_index(direction: XY) -> usize {
+    DIRECTIONS
+        .iter()
+        .position(|d| *d == direction)
+        .expect("Agents and inputs always have a valid direction")
+}
+
+impl ReplaySerde {
+    fn new(replay: &Replay) -> Self {
+        Self {
+            starts: replay
+                .starts
+                .iter()
+                .map(|agent| {
+                    let double_xy = agent.double_xy();
+                    (
+                        (double_xy.x, double_xy.y),
+                        direction_index(agent.direction()),
+                    )
+                })
+                .collect(),
+            states: replay.states.clone(),
+            steps: replay
+                .steps
+                .iter()
+                .map(|(agent, step)| (*agent, direction_index(step.input), step.back, step.choice))
+                .collect(),
+        }
+    }
+
+    /// Builds the replay, or returns an error message if a direction or agent is out of bounds,
+    /// or an agent isn't on the middle of a cell edge facing across it
+    fn into_replay(self) -> Result<Replay, String> {
+        let direction = |index: usize| {
+            DIRECTIONS
+                .get(index)
+                .copied()
+                .ok_or_else(|| format!("Direction {} is out of bounds", index))
+        };
+
+        let starts = self
+            .starts
+            .into_iter()
+            .map(|((x, y), dir)| {
+                let direction = direction(dir)?;
+
+                let on_horizontal_edge = x.rem_euclid(2) != 0;
+                if on_horizontal_edge == (y.rem_euclid(2) != 0) {
+                    return Err(format!(
+                        "({}, {}) is not the middle of a cell edge",
+                        x as f64 * 0.5,
+                        y as f64 * 0.5
+                    ));
+                }
+                if on_horizontal_edge != (direction.x == 0) {
+                    return Err("An agent must face across its edge".to_string());
+                }
+
+                Ok(Agent::new(vec2(x as f64, y as f64) * 0.5, direction))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let steps = self
+            .steps
+            .into_iter()
+            .map(|(agent, input, back, choice)| {
+                if agent >= starts.len() {
+                    return Err(format!("Agent {} is out of bounds", agent));
+                }
+                Ok((agent, Step::new(direction(input)?, back, choice)))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Replay {
+            starts,
+            states: self.states,
+            steps,
+        })
+    }
+}
+
+impl Serialize for Replay {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        ReplaySerde::new(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Replay {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        ReplaySerde::deserialize(deserializer)?
+            .into_replay()
+            .map_err(|e| D::Error::custom(&e))
+    }
+}
+
+impl bit_serde::Versioned for Replay {
+    fn migrate(version: u64, _bits: &BitSlice) -> bit_serde::Result<Self> {
+        // Replays didn't exist before version 2
+        Err(bit_serde::Error::UnknownVersion(version))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gadget::GadgetDef;
+    use crate::spsp_multi;
+
+    use std::rc::Rc;
+
+    /// Toggles at (0, 0) and (0, 1), with ports on the bottom and top
+    fn toggle_grid() -> Grid<Gadget> {
+        let def = Rc::new(GadgetDef::from_traversals(
+            2,
+            2,
+            spsp_multi![((0, 0), (1, 1)), ((1, 1), (0, 0))],
+        ));
+        let mut grid = Grid::new();
+        for y in 0..2 {
+            grid.insert(
+                Gadget::new(&def, (1, 1), vec![0, 2], State(0)),
+                vec2(0, y),
+                (1, 1),
+            );
+        }
+        grid
+    }
+
+    fn states(grid: &Grid<Gadget>) -> Vec<usize> {
+        positions(grid)
+            .into_iter()
+            .map(|xy| grid.get(xy).unwrap().0.state().id())
+            .collect()
+    }
+
+    /// A replay of an agent going up through both toggles,
+    /// and then another one trying to follow it, which the bottom toggle doesn't let through
+    fn replay(grid: &mut Grid<Gadget>) -> Replay {
+        let mut agents = vec![
+            Agent::new(vec2(0.5, 0.0), vec2(0, 1)),
+            Agent::new(vec2(0.5, 0.0), vec2(0, 1)),
+        ];
+        let mut replay = Replay::new(grid, &agents);
+
+        for agent in &[0, 0, 1] {
+            let step = Step::new(vec2(0, 1), false, 0);
+            agents[*agent].advance(grid, step.input, step.back, step.choice);
+            replay.push(*agent, step);
+        }
+        replay
+    }
+
+    #[test]
+    fn test_seek() {
+        let mut grid = toggle_grid();
+        let replay = replay(&mut grid);
+        assert_eq!(replay.len(), 3);
+        assert_eq!(states(&grid), vec![1, 1]);
+
+        let agents = replay.seek(&mut grid, 0).unwrap();
+        assert_eq!(agents, replay.starts());
+        assert_eq!(states(&grid), vec![0, 0]);
+
+        let agents = replay.seek(&mut grid, 2).unwrap();
+        assert_eq!(agents[0].position(), vec2(0.5, 2.0));
+        assert_eq!(agents[1].position(), vec2(0.5, 0.0));
+        assert_eq!(states(&grid), vec![1, 1]);
+
+        // Seeking past the end stops at the end
+        let agents = replay.seek(&mut grid, 10).unwrap();
+        assert_eq!(agents[1].position(), vec2(0.5, 0.0));
+        assert_eq!(states(&grid), vec![1, 1]);
+    }
+
+    #[test]
+    fn test_rewind_mismatch() {
+        let mut grid = toggle_grid();
+        let replay = replay(&mut grid);
+
+        let mut smaller = grid.clone();
+        smaller.remove(vec2(0, 1));
+        assert_eq!(
+            replay.rewind(&mut smaller).unwrap_err(),
+            "The replay is for 2 gadgets, but the contraption has 1"
+        );
+
+        let def = Rc::new(GadgetDef::new(1, 2));
+        let mut different = grid.clone();
+        different.insert(
+            Gadget::new(&def, (1, 1), vec![0, 2], State(0)),
+            vec2(0, 1),
+            (1, 1),
+        );
+
+        let mut replay = replay;
+        replay.states[1] = State(1);
+        assert_eq!(
+            replay.rewind(&mut different).unwrap_err(),
+            "The replay starts the gadget at (0, 1) in state 1, which it doesn't have"
+        );
+    }
+
+    #[test]
+    fn test_serde() {
+        let mut grid = toggle_grid();
+        let mut replay = replay(&mut grid);
+        replay.push(1, Step::new(vec2(-1, 0), true, 2));
+
+        let string = bit_serde::to_versioned_base64(&replay).unwrap();
+        let loaded: Replay = bit_serde::from_versioned_base64(&string).unwrap();
+        assert_eq!(loaded, replay);
+
+        let bits = bit_serde::to_bits(&ReplaySerde {
+            starts: vec![((1, 0), 0)],
+            states: vec![],
+            steps: vec![(1, 0, false, 0)],
+        })
+        .unwrap();
+        assert!(bit_serde::from_bits::<Replay>(&bits).is_err());
+
+        // Agents off an edge's middle, or facing along their edge
+        for start in &[((0, 0), 0), ((1, 1), 0), ((1, 0), 1), ((0, 1), 0)] {
+            let bits = bit_serde::to_bits(&ReplaySerde {
+                starts: vec![*start],
+                states: vec![],
+                steps: vec![],
+            })
+            .unwrap();
+            assert!(bit_serde::from_bits::<Replay>(&bits).is_err());
+        }
+    }
+}
@@ -0,0 +1,613 @@
+//! Scripts of agent moves, for running contraptions without a player.
+//!
+//! ```text
+//! # Comments start with a hash
+//! start 0.5 0 up
+//! move W W D
+//! move ^S A2
+//! expect agent 0.5 1 down
+//! expect gadget 0 0 1
+//! ```
+//!
+//! `start` puts the agent at a position, facing up, left, down, or right.
+//! `move` gives the agent inputs, which are W, A, S, and D for up, left, down, and right,
+//! like in play mode.
+//! An input starting with `^` is made with Shift held, so it moves the agent backward,
+//! and an input ending with a number n takes the nth traversal if there are several to choose from.
+//! Otherwise the first one is taken.
+//!
+//! `expect` checks the contraption after the moves so far.
+//! `expect agent` gives where the agent should be and which way it should face, like `start`,
+//! and `expect gadget` gives a cell and the state the gadget covering it should be in.
+//! Running a script stops at the first expectation that isn't met.
+
+use cgmath::vec2;
+
+use std::fmt::{self, Display, Formatter};
+
+use crate::gadget::Agent;
+use crate::gadget::{Gadget, State};
+use crate::grid::{Grid, XY};
+use crate::math::Vec2i;
+use crate::solver::{Step, DIRECTIONS};
+
+/// Names of the directions in `DIRECTIONS`, in the same order
+const DIRECTION_NAMES: [&str; 4] = ["up", "left", "down", "right"];
+
+/// Input keys for the directions in `DIRECTIONS`, in the same order
+const INPUT_KEYS: [char; 4] = ['W', 'A', 'S', 'D'];
+
+/// Marks an input made with Shift held
+const BACK_MARKER: char = '^';
+
+/// Gets the name of a direction the agent can face
+pub fn direction_name(direction: Vec2i) -> &'static str {
+    DIRECTIONS
+        .iter()
+        .position(|d| *d == direction)
+        .map_or("nowhere", |i| DIRECTION_NAMES[i])
+}
+
+/// Writes a step the way scripts give it
+pub fn step_string(step: &Step) -> String {
+    let key = DIRECTIONS
+        .iter()
+        .position(|d| *d == step.input)
+        .map_or('?', |i| INPUT_KEYS[i]);
+
+    let mut string = String::new();
+    if step.back {
+        string.push(BACK_MARKER);
+    }
+    string.push(key);
+    if step.choice > 0 {
+        string += &(step.choice + 1).to_string();
+    }
+    string
+}
+
+/// Splits a line into words, along with the columns they start at
+fn words(line: &str) -> Vec<(usize, &str)> {
+    let mut words = vec![];
+    let mut start = None;
+
+    for (i, c) in line
+        .char_indices()
+        .chain(std::iter::once((line.len(), ' ')))
+    {
+        match (c.is_whitespace(), start) {
+            (true, Some(s)) => {
+                words.push((s, &line[s..i]));
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => {}
+        }
+    }
+
+    words
+}
+
+fn parse_direction(word: &str) -> Result<Vec2i, String> {
+    DIRECTION_NAMES
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(word))
+        .map(|i| DIRECTIONS[i])
+        .ok_or_else(|| {
+            format!(
+                "Direction {:?} is not one of {}",
+                word,
+                DIRECTION_NAMES.join(", ")
+            )
+        })
+}
+
+fn parse_step(word: &str) -> Result<Step, String> {
+    let mut chars = word.chars().peekable();
+
+    let back = chars.peek() == Some(&BACK_MARKER);
+    if back {
+        chars.next();
+    }
+
+    let input = chars
+        .next()
+        .and_then(|key| INPUT_KEYS.iter().position(|k| k.eq_ignore_ascii_case(&key)))
+        .map(|i| DIRECTIONS[i])
+        .ok_or_else(|| format!("Input {:?} is not W, A, S, or D", word))?;
+
+    let choice = chars.collect::<String>();
+    let choice = if choice.is_empty() {
+        0
+    } else {
+        match choice.parse::<usize>() {
+            Ok(choice) if choice > 0 => choice - 1,
+            _ => return Err(format!("Input {:?} has an invalid traversal number", word)),
+        }
+    };
+
+    Ok(Step::new(input, back, choice))
+}
+
+/// Parses the position and direction after the command at column `col`.
+/// Errors have the column of the problem.
+fn parse_agent(col: usize, args: &[(usize, &str)]) -> Result<Agent, (usize, String)> {
+    if args.len() != 3 {
+        return Err((col, "Expected a position and a direction".to_string()));
+    }
+
+    let mut coords = [0.0; 2];
+    for (coord, (col, arg)) in coords.iter_mut().zip(args) {
+        *coord = arg
+            .parse::<f64>()
+            .map_err(|_| (*col, format!("{:?} is not a number", arg)))?;
+    }
+    let (col, direction) = args[2];
+    let direction = parse_direction(direction).map_err(|e| (col, e))?;
+
+    let agent = Agent::new(vec2(coords[0], coords[1]), direction);
+
+    // The agent must be on the middle of an edge, facing across it
+    let double_xy = agent.double_xy();
+    let position = agent.position();
+    let on_edge = (double_xy.x.rem_euclid(2) != 0) != (double_xy.y.rem_euclid(2) != 0);
+
+    if !on_edge || position != vec2(coords[0], coords[1]) {
+        return Err((
+            args[0].0,
+            format!(
+                "({}, {}) is not the middle of a cell edge",
+                coords[0], coords[1]
+            ),
+        ));
+    }
+    if (double_xy.x.rem_euclid(2) != 0) != (direction.x == 0) {
+        return Err((col, "The agent must face across its edge".to_string()));
+    }
+
+    Ok(agent)
+}
+
+/// Parses the cell and state after `expect gadget` at column `col`.
+/// Errors have the column of the problem.
+fn parse_gadget_state(col: usize, args: &[(usize, &str)]) -> Result<(XY, State), (usize, String)> {
+    if args.len() != 3 {
+        return Err((col, "Expected a cell and a state".to_string()));
+    }
+
+    let mut numbers = [0; 3];
+    for (number, (col, arg)) in numbers.iter_mut().zip(args) {
+        *number = arg
+            .parse::<isize>()
+            .map_err(|_| (*col, format!("{:?} is not a whole number", arg)))?;
+    }
+    if numbers[2] < 0 {
+        return Err((args[2].0, format!("{:?} is not a state", args[2].1)));
+    }
+
+    Ok((vec2(numbers[0], numbers[1]), State(numbers[2] as usize)))
+}
+
+/// Describes where an agent is and which way it faces
+fn agent_string(agent: &Agent) -> String {
+    let position = agent.position();
+    format!(
+        "({}, {}) facing {}",
+        position.x,
+        position.y,
+        direction_name(agent.direction())
+    )
+}
+
+/// Something a script expects of the contraption
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expectation {
+    /// The agent is at a position, facing a direction
+    Agent(Agent),
+    /// The gadget covering a cell is in a state
+    GadgetState(XY, State),
+}
+
+impl Expectation {
+    /// Checks the expectation, and describes how it isn't met if it isn't
+    pub fn check(&self, grid: &Grid<Gadget>, agent: &Agent) -> Result<(), String> {
+        match self {
+            Expectation::Agent(expected) => {
+                if agent != expected {
+                    return Err(format!(
+                        "Expected the agent at {}, but it is at {}",
+                        agent_string(expected),
+                        agent_string(agent)
+                    ));
+                }
+            }
+
+            Expectation::GadgetState(xy, state) => {
+                let (gadget, _, _) = grid.get(*xy).ok_or_else(|| {
+                    format!(
+                        "Expected a gadget at ({}, {}), but there is none",
+                        xy.x, xy.y
+                    )
+                })?;
+
+                if gadget.state() != *state {
+                    return Err(format!(
+                        "Expected the gadget at ({}, {}) in state {}, but it is in state {}",
+                        xy.x,
+                        xy.y,
+                        state.id(),
+                        gadget.state().id()
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Where running a script failed
+#[derive(Clone, Debug, PartialEq)]
+pub struct Failure {
+    /// Line of the script that failed, starting at 1
+    pub line: usize,
+    /// Number of steps taken before the failure
+    pub steps: usize,
+    pub message: String,
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Line {}, after {} step{}: {}",
+            self.line,
+            self.steps,
+            if self.steps == 1 { "" } else { "s" },
+            self.message
+        )
+    }
+}
+
+/// A starting position for the agent, moves for it to make,
+/// and what to expect along the way
+#[derive(Clone, Debug)]
+pub struct Script {
+    start: Agent,
+    steps: Vec<Step>,
+    /// Line each step is on, starting at 1
+    step_lines: Vec<usize>,
+    /// Expectations, with their line and the number of steps taken before they are checked,
+    /// in order
+    expectations: Vec<(usize, usize, Expectation)>,
+}
+
+impl Script {
+    /// Parses a script. Errors start with the line and column of the problem.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let at = |line: usize, col: usize, e: String| format!("{}:{}: {}", line + 1, col + 1, e);
+
+        let mut start = None;
+        let mut steps = vec![];
+        let mut step_lines = vec![];
+        let mut expectations = vec![];
+
+        for (l, line) in text.lines().enumerate() {
+            let line = match line.find('#') {
+                Some(i) => &line[..i],
+                None => line,
+            };
+            let words = words(line);
+
+            let (col, command) = match words.first() {
+                Some(word) => *word,
+                None => continue,
+            };
+
+            match command {
+                "start" => {
+                    if start.is_some() {
+                        return Err(at(l, col, "The agent already has a start".to_string()));
+                    }
+                    let agent = parse_agent(col, &words[1..]).map_err(|(c, e)| at(l, c, e))?;
+                    start = Some(agent);
+                }
+
+                "move" => {
+                    if start.is_none() {
+                        return Err(at(l, col, "The agent must start before moving".to_string()));
+                    }
+                    for (col, word) in &words[1..] {
+                        steps.push(parse_step(word).map_err(|e| at(l, *col, e))?);
+                        step_lines.push(l + 1);
+                    }
+                }
+
+                "expect" => {
+                    let expectation = match words.get(1) {
+                        Some((col, "agent")) => {
+                            parse_agent(*col, &words[2..]).map(Expectation::Agent)
+                        }
+                        Some((col, "gadget")) => parse_gadget_state(*col, &words[2..])
+                            .map(|(xy, state)| Expectation::GadgetState(xy, state)),
+                        Some((col, _)) => Err((*col, "Expected agent or gadget".to_string())),
+                        None => Err((col, "Expected agent or gadget".to_string())),
+                    }
+                    .map_err(|(c, e)| at(l, c, e))?;
+
+                    expectations.push((l + 1, steps.len(), expectation));
+                }
+
+                _ => {
+                    return Err(at(
+                        l,
+                        col,
+                        format!(
+                            "Unknown command {:?}; expected start, move, or expect",
+                            command
+                        ),
+                    ))
+                }
+            }
+        }
+
+        let start = start.ok_or_else(|| "The script has no start".to_string())?;
+        Ok(Self {
+            start,
+            steps,
+            step_lines,
+            expectations,
+        })
+    }
+
+    pub fn start(&self) -> &Agent {
+        &self.start
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    pub fn num_expectations(&self) -> usize {
+        self.expectations.len()
+    }
+
+    /// Runs the script on a grid, calling `on_step` after each step
+    /// with the step, the agent, and the position and previous state of the gadget
+    /// that changed state, if one did.
+    /// Returns the agent at the end, or where the script first failed.
+    /// A step fails if it takes a traversal other than the first one and there is no such traversal.
+    pub fn run(
+        &self,
+        grid: &mut Grid<Gadget>,
+        mut on_step: impl FnMut(&Step, &Agent, Option<(Vec2i, usize)>),
+    ) -> Result<Agent, Failure> {
+        let mut agent = self.start.clone();
+        let mut expectations = self.expectations.iter().peekable();
+
+        for taken in 0..=self.steps.len() {
+            while let Some((line, _, expectation)) =
+                expectations.peek().copied().filter(|(_, s, _)| *s == taken)
+            {
+                expectations.next();
+                expectation.check(grid, &agent).map_err(|message| Failure {
+                    line: *line,
+                    steps: taken,
+                    message,
+                })?;
+            }
+
+            let step = match self.steps.get(taken) {
+                Some(step) => step,
+                None => break,
+            };
+
+            if step.choice > 0 {
+                let num_traversals = agent
+                    .traversals(grid, step.input, step.back)
+                    .map_or(0, |(_, sps)| sps.len());
+
+                if step.choice >= num_traversals {
+                    return Err(Failure {
+                        line: self.step_lines[taken],
+                        steps: taken,
+                        message: format!(
+                            "{} takes traversal {}, but there {}",
+                            step_string(step),
+                            step.choice + 1,
+                            match num_traversals {
+                                0 => "are none".to_string(),
+                                1 => "is only 1".to_string(),
+                                n => format!("are only {}", n),
+                            }
+                        ),
+                    });
+                }
+            }
+
+            let changed = agent
+                .advance(grid, step.input, step.back, step.choice)
+                .map(|(_, xy, state)| (xy, state.id()));
+            on_step(step, &agent, changed);
+        }
+
+        Ok(agent)
+    }
+
+    /// Runs the script on a copy of a grid, for checking that the contraption still behaves
+    pub fn check(&self, grid: &Grid<Gadget>) -> Result<Agent, Failure> {
+        self.run(&mut grid.clone(), |_, _, _| {})
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gadget::{GadgetDef, State};
+    use crate::spsp_multi;
+
+    use std::rc::Rc;
+
+    /// A toggle at the origin, with ports on the bottom and top
+    fn toggle_grid() -> Grid<Gadget> {
+        let def = GadgetDef::from_traversals(2, 2, spsp_multi![((0, 0), (1, 1)), ((1, 1), (0, 0))]);
+        let mut grid = Grid::new();
+        grid.insert(
+            Gadget::new(&Rc::new(def), (1, 1), vec![0, 2], State(0)),
+            vec2(0, 0),
+            (1, 1),
+        );
+        grid
+    }
+
+    #[test]
+    fn test_parse() {
+        let script = Script::parse(
+            "
+# Through the toggle and back
+start 0.5 0 up  # below it
+move w ^S
+move D2
+",
+        )
+        .unwrap();
+
+        assert_eq!(script.start(), &Agent::new(vec2(0.5, 0.0), vec2(0, 1)));
+        assert_eq!(
+            script.steps(),
+            &[
+                Step::new(vec2(0, 1), false, 0),
+                Step::new(vec2(0, -1), true, 0),
+                Step::new(vec2(1, 0), false, 1),
+            ]
+        );
+
+        let strings = script.steps().iter().map(step_string).collect::<Vec<_>>();
+        assert_eq!(strings, vec!["W", "^S", "D2"]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |text: &str| Script::parse(text).unwrap_err();
+
+        assert_eq!(error("move W"), "1:1: The agent must start before moving");
+        assert_eq!(
+            error("start 0.5 0 up\nmove Q"),
+            "2:6: Input \"Q\" is not W, A, S, or D"
+        );
+        assert_eq!(
+            error("start 0.5 0 up\nmove W0"),
+            "2:6: Input \"W0\" has an invalid traversal number"
+        );
+        assert_eq!(
+            error("start 0.5 0.5 up"),
+            "1:7: (0.5, 0.5) is not the middle of a cell edge"
+        );
+        assert_eq!(
+            error("start 0.5 0 left"),
+            "1:13: The agent must face across its edge"
+        );
+        assert_eq!(
+            error("start 0.5 0 north"),
+            "1:13: Direction \"north\" is not one of up, left, down, right"
+        );
+        assert_eq!(
+            error("start 0.5 up"),
+            "1:1: Expected a position and a direction"
+        );
+        assert_eq!(
+            error("jump"),
+            "1:1: Unknown command \"jump\"; expected start, move, or expect"
+        );
+        assert_eq!(
+            error("start 0.5 0 up\nexpect agent 0.5 1"),
+            "2:8: Expected a position and a direction"
+        );
+        assert_eq!(
+            error("start 0.5 0 up\nexpect gadget 0 0 -1"),
+            "2:19: \"-1\" is not a state"
+        );
+        assert_eq!(
+            error("start 0.5 0 up\nexpect toggle 0 0 1"),
+            "2:8: Expected agent or gadget"
+        );
+        assert_eq!(error("# nothing"), "The script has no start");
+    }
+
+    #[test]
+    fn test_run() {
+        let mut grid = toggle_grid();
+        let script = Script::parse("start 0.5 0 up\nmove W S S").unwrap();
+
+        let mut changes = vec![];
+        let agent = script
+            .run(&mut grid, |step, _, changed| {
+                changes.push((step_string(step), changed))
+            })
+            .unwrap();
+
+        // Up through the toggle, turn around, and back down through it
+        assert_eq!(
+            changes,
+            vec![
+                ("W".to_string(), Some((vec2(0, 0), 0))),
+                ("S".to_string(), None),
+                ("S".to_string(), Some((vec2(0, 0), 1))),
+            ]
+        );
+        assert_eq!(agent.position(), vec2(0.5, 0.0));
+        assert_eq!(direction_name(agent.direction()), "down");
+        assert_eq!(grid.get(vec2(0, 0)).unwrap().0.state(), State(0));
+    }
+
+    #[test]
+    fn test_expectations() {
+        let grid = toggle_grid();
+        let check = |text: &str| Script::parse(text).unwrap().check(&grid);
+
+        let agent = check(
+            "
+start 0.5 0 up
+expect gadget 0 0 0
+move W
+expect agent 0.5 1 up
+expect gadget 0 0 1
+",
+        )
+        .unwrap();
+        assert_eq!(agent.position(), vec2(0.5, 1.0));
+
+        // Checking works on a copy
+        assert_eq!(grid.get(vec2(0, 0)).unwrap().0.state(), State(0));
+
+        let failure = check("start 0.5 0 up\nmove W S\nexpect agent 0.5 1 up\nmove S").unwrap_err();
+        assert_eq!(failure.line, 3);
+        assert_eq!(failure.steps, 2);
+        assert_eq!(
+            failure.to_string(),
+            "Line 3, after 2 steps: \
+             Expected the agent at (0.5, 1) facing up, but it is at (0.5, 1) facing down"
+        );
+
+        assert_eq!(
+            check("start 0.5 0 up\nmove W\nexpect gadget 0 0 0")
+                .unwrap_err()
+                .message,
+            "Expected the gadget at (0, 0) in state 0, but it is in state 1"
+        );
+        assert_eq!(
+            check("start 0.5 0 up\nexpect gadget 3 0 0")
+                .unwrap_err()
+                .to_string(),
+            "Line 2, after 0 steps: Expected a gadget at (3, 0), but there is none"
+        );
+        assert_eq!(
+            check("start 0.5 0 up\nmove W\nmove W2").unwrap_err(),
+            Failure {
+                line: 3,
+                steps: 1,
+                message: "W2 takes traversal 2, but there are none".to_string(),
+            }
+        );
+    }
+}
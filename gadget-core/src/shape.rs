@@ -205,6 +205,26 @@ impl Path {
         PathIter::new(self)
     }
 
+    /// Gets the boundary of the area this path covers when drawn,
+    /// as 1 polygon if it's open and 2 (outer and inner) if it's closed
+    pub fn outlines(&self) -> Vec<Vec<Vec2>> {
+        let positions = self
+            .positions_f64()
+            .into_iter()
+            .map(|xyz| xyz.truncate())
+            .collect::<Vec<_>>();
+        let left = positions.iter().step_by(2).copied();
+        let right = positions.iter().skip(1).step_by(2).copied();
+
+        if self.closed {
+            vec![left.collect(), right.collect()]
+        } else {
+            let mut right = right.collect::<Vec<_>>();
+            right.reverse();
+            vec![left.chain(right).collect()]
+        }
+    }
+
     pub fn len(&self) -> f64 {
         let mut len = self
             .xys
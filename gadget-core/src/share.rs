@@ -0,0 +1,156 @@
+//! Contraptions shared as links.
+//!
+//! The hash of a link has the grid, optionally followed by palette entries
+//! that aren't presets, so whoever opens the link can paint with them too,
+//! and then optionally by a replay of agents moving through the contraption.
+//! A link with a replay but no palette entries has an empty palette segment.
+
+use crate::bit_serde;
+use crate::gadget::Gadget;
+use crate::grid::Grid;
+use crate::library::GadgetLibrarySerde;
+use crate::replay::Replay;
+
+/// Separates the grid from the palette, and the palette from the replay, in the URL's hash
+pub const URL_PALETTE_SEPARATOR: char = '.';
+
+/// Gets the hash of a link, or the string itself if it is just a hash
+fn url_hash(url: &str) -> &str {
+    match url.find('#') {
+        Some(i) => &url[i + 1..],
+        None => url,
+    }
+}
+
+/// Gets the grid segment of a link, or of its hash
+pub fn grid_segment(url: &str) -> &str {
+    // Links from before palettes were saved only have the grid
+    url_hash(url)
+        .split(URL_PALETTE_SEPARATOR)
+        .next()
+        .unwrap_or("")
+}
+
+/// Gets the palette segment of a link, or of its hash, if there is one
+pub fn palette_segment(url: &str) -> Option<&str> {
+    url_hash(url)
+        .split(URL_PALETTE_SEPARATOR)
+        .nth(1)
+        .filter(|segment| !segment.is_empty())
+}
+
+/// Gets the replay segment of a link, or of its hash, if there is one
+pub fn replay_segment(url: &str) -> Option<&str> {
+    url_hash(url)
+        .split(URL_PALETTE_SEPARATOR)
+        .nth(2)
+        .filter(|segment| !segment.is_empty())
+}
+
+/// Builds the hash of a link from its segments, leaving out the ones there aren't
+pub fn url_hash_from_segments(grid: &str, palette: Option<&str>, replay: Option<&str>) -> String {
+    let mut hash = grid.to_string();
+
+    if palette.is_some() || replay.is_some() {
+        hash.push(URL_PALETTE_SEPARATOR);
+        hash += palette.unwrap_or("");
+    }
+    if let Some(replay) = replay {
+        hash.push(URL_PALETTE_SEPARATOR);
+        hash += replay;
+    }
+
+    hash
+}
+
+/// Decodes the grid of a link, or of its hash
+pub fn grid_from_url(url: &str) -> Result<Grid<Gadget>, String> {
+    bit_serde::from_versioned_base64(grid_segment(url)).map_err(|e| e.to_string())
+}
+
+/// Decodes the palette entries saved after the grid in a link, if there are any
+pub fn palette_from_url(url: &str) -> Option<Result<Vec<Gadget>, String>> {
+    let segment = palette_segment(url)?;
+
+    Some(
+        bit_serde::from_versioned_base64::<GadgetLibrarySerde>(segment)
+            .map_err(|e| e.to_string())
+            .and_then(|library| library.into_gadgets()),
+    )
+}
+
+/// Decodes the replay saved after the palette in a link, if there is one
+pub fn replay_from_url(url: &str) -> Option<Result<Replay, String>> {
+    let segment = replay_segment(url)?;
+    Some(bit_serde::from_versioned_base64(segment).map_err(|e| e.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gadget::{Agent, GadgetDef, State};
+    use crate::spsp_multi;
+
+    use cgmath::vec2;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_segments() {
+        assert_eq!(grid_segment("https://example.com/#abc.def"), "abc");
+        assert_eq!(palette_segment("https://example.com/#abc.def"), Some("def"));
+
+        assert_eq!(grid_segment("abc"), "abc");
+        assert_eq!(palette_segment("abc"), None);
+        assert_eq!(grid_segment(""), "");
+
+        assert_eq!(replay_segment("#abc.def"), None);
+        assert_eq!(palette_segment("#abc..ghi"), None);
+        assert_eq!(replay_segment("#abc..ghi"), Some("ghi"));
+
+        assert_eq!(url_hash_from_segments("abc", None, None), "abc");
+        assert_eq!(url_hash_from_segments("abc", Some("def"), None), "abc.def");
+        assert_eq!(url_hash_from_segments("abc", None, Some("ghi")), "abc..ghi");
+        assert_eq!(
+            url_hash_from_segments("abc", Some("def"), Some("ghi")),
+            "abc.def.ghi"
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let def = GadgetDef::from_traversals(2, 2, spsp_multi![((0, 0), (1, 1)), ((1, 1), (0, 0))]);
+        let gadget = Gadget::new(&Rc::new(def), (1, 1), vec![0, 2], State(1)).name_this("Toggle");
+
+        let mut grid = Grid::new();
+        grid.insert(gadget.clone(), vec2(3, -2), (1, 1));
+
+        let hash = bit_serde::to_versioned_base64(&grid).unwrap();
+        let palette = bit_serde::to_versioned_base64(&GadgetLibrarySerde::new(&[gadget])).unwrap();
+        let url = format!(
+            "https://example.com/#{}{}{}",
+            hash, URL_PALETTE_SEPARATOR, palette
+        );
+
+        let loaded = grid_from_url(&url).unwrap();
+        let (gadget, xy, _) = loaded.iter().next().unwrap();
+        assert_eq!(*xy, vec2(3, -2));
+        assert_eq!(gadget.state(), State(1));
+
+        let palette = palette_from_url(&url).unwrap().unwrap();
+        assert_eq!(palette.len(), 1);
+        assert_eq!(palette[0].name(), "Toggle");
+
+        assert!(palette_from_url(&hash).is_none());
+        assert!(replay_from_url(&url).is_none());
+        assert!(grid_from_url("#not a grid").is_err());
+
+        let replay = Replay::new(&grid, &[Agent::new(vec2(3.5, -2.0), vec2(0, 1))]);
+        let url = url_hash_from_segments(
+            &hash,
+            None,
+            Some(&bit_serde::to_versioned_base64(&replay).unwrap()),
+        );
+        assert_eq!(replay_from_url(&url).unwrap().unwrap(), replay);
+        assert!(palette_from_url(&url).is_none());
+    }
+}
@@ -0,0 +1,317 @@
+use cgmath::vec2;
+use fnv::FnvHashMap;
+
+use std::collections::VecDeque;
+use std::fmt::{self, Display, Formatter};
+
+use crate::gadget::{Agent, Gadget, State};
+use crate::grid::{Grid, XY};
+use crate::math::{Vec2, Vec2i, Vector2Ex};
+
+/// The directions the agent can be told to go in
+pub const DIRECTIONS: [Vec2i; 4] = [
+    Vec2i { x: 0, y: 1 },
+    Vec2i { x: -1, y: 0 },
+    Vec2i { x: 0, y: -1 },
+    Vec2i { x: 1, y: 0 },
+];
+
+/// The default maximum number of configurations to visit while solving, exploring, or checking a region
+pub const DEFAULT_BUDGET: usize = 100000;
+
+/// A single input to the agent, as would be passed to `Agent::advance`
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Step {
+    pub input: Vec2i,
+    pub back: bool,
+    pub choice: usize,
+}
+
+impl Step {
+    pub fn new(input: Vec2i, back: bool, choice: usize) -> Self {
+        Self {
+            input,
+            back,
+            choice,
+        }
+    }
+}
+
+/// The agent along with the state of every gadget in the contraption
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Configuration {
+    pub agent: Agent,
+    /// Gadget states, in the order of `Simulation::positions`
+    pub states: Vec<State>,
+}
+
+/// Computes the moves the agent can make in a contraption
+/// without disturbing the original grid
+pub struct Simulation {
+    grid: Grid<Gadget>,
+    /// Minimal xy coords of the gadgets, in the order their states are stored in
+    positions: Vec<XY>,
+    indexes: FnvHashMap<XY, usize>,
+}
+
+impl Simulation {
+    pub fn new(grid: &Grid<Gadget>) -> Self {
+        let mut positions = grid.iter().map(|(_, xy, _)| *xy).collect::<Vec<_>>();
+        positions.sort_by_key(|xy| (xy.y, xy.x));
+
+        let indexes = positions
+            .iter()
+            .enumerate()
+            .map(|(i, xy)| (*xy, i))
+            .collect();
+
+        Self {
+            grid: grid.clone(),
+            positions,
+            indexes,
+        }
+    }
+
+    /// Gets the minimal xy coords of the gadgets, in the order their states are stored in
+    pub fn positions(&self) -> &[XY] {
+        &self.positions
+    }
+
+    /// Gets the configuration of the contraption with some agent
+    pub fn configuration(&self, agent: &Agent) -> Configuration {
+        Configuration {
+            agent: agent.clone(),
+            states: self
+                .positions
+                .iter()
+                .map(|xy| self.grid.get(*xy).expect("Gadget disappeared").0.state())
+                .collect(),
+        }
+    }
+
+    /// Puts all the gadgets into the states of some configuration
+    pub fn set_states(&mut self, states: &[State]) {
+        for (xy, state) in self.positions.iter().zip(states) {
+            let (gadget, _, _) = self.grid.get_mut(*xy).expect("Gadget disappeared");
+            if gadget.state() != *state {
+                gadget.set_state(*state);
+            }
+        }
+    }
+
+    /// Gets the steps the agent can take from some configuration,
+    /// along with the configurations they lead to.
+    /// Steps that do nothing are left out.
+    pub fn moves(&mut self, config: &Configuration) -> Vec<(Step, Configuration)> {
+        self.set_states(&config.states);
+
+        let mut moves = vec![];
+
+        for input in DIRECTIONS.iter().copied() {
+            let opposite = input.dot_ex(config.agent.direction()) == -1;
+
+            if opposite {
+                // Turn around
+                let mut agent = config.agent.clone();
+                agent.flip();
+                moves.push((
+                    Step::new(input, false, 0),
+                    Configuration {
+                        agent,
+                        states: config.states.clone(),
+                    },
+                ));
+            }
+
+            let back = opposite;
+
+            if let Some((xy, sps)) = config.agent.traversals(&self.grid, input, back) {
+                let index = self.indexes[&xy];
+
+                for (choice, sp) in sps.into_iter().enumerate() {
+                    let mut agent = config.agent.clone();
+                    agent.traverse(&mut self.grid, xy, sp);
+
+                    // Undo the state change for the next traversal
+                    let (gadget, _, _) = self.grid.get_mut(xy).expect("Gadget disappeared");
+                    gadget.set_state(config.states[index]);
+
+                    let mut states = config.states.clone();
+                    states[index] = sp.0;
+
+                    moves.push((
+                        Step::new(input, back, choice),
+                        Configuration { agent, states },
+                    ));
+                }
+            }
+        }
+
+        moves
+    }
+}
+
+/// Why a solution could not be found
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SolveError {
+    /// Every reachable configuration was visited, and none of them reached the target
+    Unreachable,
+    /// Too many configurations were visited
+    BudgetExceeded(usize),
+}
+
+impl Display for SolveError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            SolveError::Unreachable => write!(f, "the target is unreachable"),
+            SolveError::BudgetExceeded(budget) => {
+                write!(f, "gave up after visiting {} configurations", budget)
+            }
+        }
+    }
+}
+
+/// Finds the shortest sequence of steps that gets the agent to the edge centered at `target`,
+/// facing either way, visiting at most `budget` configurations.
+pub fn solve(
+    grid: &Grid<Gadget>,
+    agent: &Agent,
+    target: Vec2,
+    budget: usize,
+) -> Result<Vec<Step>, SolveError> {
+    let target = vec2(
+        (target.x * 2.0).round() as isize,
+        (target.y * 2.0).round() as isize,
+    );
+    let reached = |agent: &Agent| {
+        let position = agent.position() * 2.0;
+        vec2(position.x as isize, position.y as isize) == target
+    };
+
+    let mut sim = Simulation::new(grid);
+    let start = sim.configuration(agent);
+
+    // Configurations along with the index of their parent and the step from it
+    let mut visited: Vec<(Configuration, Option<(usize, Step)>)> = vec![(start.clone(), None)];
+    let mut indexes = FnvHashMap::default();
+    indexes.insert(start, 0);
+
+    let mut queue = VecDeque::new();
+    queue.push_back(0);
+
+    while let Some(index) = queue.pop_front() {
+        if reached(&visited[index].0.agent) {
+            let mut steps = vec![];
+            let mut index = index;
+
+            while let Some((parent, step)) = visited[index].1 {
+                steps.push(step);
+                index = parent;
+            }
+
+            steps.reverse();
+            return Ok(steps);
+        }
+
+        let config = visited[index].0.clone();
+
+        for (step, next) in sim.moves(&config) {
+            if indexes.contains_key(&next) {
+                continue;
+            }
+
+            if visited.len() >= budget {
+                return Err(SolveError::BudgetExceeded(budget));
+            }
+
+            indexes.insert(next.clone(), visited.len());
+            queue.push_back(visited.len());
+            visited.push((next, Some((index, step))));
+        }
+    }
+
+    Err(SolveError::Unreachable)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gadget::GadgetDef;
+    use crate::spsp_multi;
+    use std::rc::Rc;
+
+    /// A door that only opens after the agent bounces off its bottom
+    fn test_grid() -> Grid<Gadget> {
+        let def = GadgetDef::from_traversals(2, 2, spsp_multi![((0, 0), (1, 0)), ((1, 0), (1, 1))]);
+        let gadget = Gadget::new(&Rc::new(def), (1, 1), vec![0, 2], State(0));
+
+        let mut grid = Grid::new();
+        grid.insert(gadget, vec2(0, 0), (1, 1));
+        grid
+    }
+
+    #[test]
+    fn test_solve_trivial() {
+        let grid = test_grid();
+        let agent = Agent::new(vec2(0.5, 0.0), vec2(0, 1));
+
+        assert_eq!(
+            solve(&grid, &agent, vec2(0.5, 0.0), DEFAULT_BUDGET),
+            Ok(vec![])
+        );
+    }
+
+    #[test]
+    fn test_solve_found() {
+        let mut grid = test_grid();
+        let mut agent = Agent::new(vec2(0.5, 0.0), vec2(0, 1));
+
+        let steps = solve(&grid, &agent, vec2(0.5, 1.0), DEFAULT_BUDGET).unwrap();
+        assert_eq!(steps.len(), 3);
+
+        for step in steps {
+            agent.advance(&mut grid, step.input, step.back, step.choice);
+        }
+        assert_eq!(agent.position(), vec2(0.5, 1.0));
+    }
+
+    #[test]
+    fn test_solve_unreachable() {
+        let grid = test_grid();
+        let agent = Agent::new(vec2(0.5, 0.0), vec2(0, 1));
+
+        assert_eq!(
+            solve(&grid, &agent, vec2(0.5, 2.0), DEFAULT_BUDGET),
+            Err(SolveError::Unreachable)
+        );
+    }
+
+    #[test]
+    fn test_solve_budget() {
+        let grid = test_grid();
+        let agent = Agent::new(vec2(0.5, 0.0), vec2(0, 1));
+
+        assert_eq!(
+            solve(&grid, &agent, vec2(0.5, 1.0), 1),
+            Err(SolveError::BudgetExceeded(1))
+        );
+    }
+
+    #[test]
+    fn test_moves_keep_states() {
+        let grid = test_grid();
+        let mut sim = Simulation::new(&grid);
+        let agent = Agent::new(vec2(0.5, 0.0), vec2(0, 1));
+        let config = sim.configuration(&agent);
+
+        let moves = sim.moves(&config);
+        assert!(moves.contains(&(
+            Step::new(vec2(0, 1), false, 0),
+            Configuration {
+                agent: Agent::new(vec2(0.5, 0.0), vec2(0, -1)),
+                states: vec![State(1)],
+            }
+        )));
+        assert_eq!(sim.configuration(&agent), config);
+    }
+}
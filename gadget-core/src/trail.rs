@@ -0,0 +1,142 @@
+//! Trails of the traversals agents have taken, so it is easy to see where they have been
+
+use fnv::FnvHashMap;
+
+use crate::gadget::{Agent, Gadget, State, PP};
+use crate::grid::XY;
+
+/// A traversal an agent took through a gadget
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct TrailStep {
+    /// Minimal xy coords of the gadget
+    pub xy: XY,
+    /// Entry and exit ports
+    pub ports: PP,
+    /// State the gadget ended up in
+    pub state: State,
+}
+
+impl TrailStep {
+    /// Gets the traversal an agent took from where it was (`from`) to where it is (`to`)
+    /// through `gadget`, with minimal xy coords `xy`, in the state the traversal left it in.
+    /// Returns `None` if the agent didn't go from one of the gadget's ports to another.
+    pub fn new(from: &Agent, to: &Agent, gadget: &Gadget, xy: XY) -> Option<Self> {
+        Some(Self {
+            xy,
+            ports: (
+                gadget.port_at(from.double_xy() - xy * 2)?,
+                gadget.port_at(to.double_xy() - xy * 2)?,
+            ),
+            state: gadget.state(),
+        })
+    }
+}
+
+/// The traversals taken, in order, and how many times each was taken
+#[derive(Clone, Debug, Default)]
+pub struct Trail {
+    steps: Vec<TrailStep>,
+    counts: FnvHashMap<TrailStep, usize>,
+}
+
+impl Trail {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn steps(&self) -> &[TrailStep] {
+        &self.steps
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn push(&mut self, step: TrailStep) {
+        self.steps.push(step);
+        *self.counts.entry(step).or_insert(0) += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.steps.clear();
+        self.counts.clear();
+    }
+
+    /// Gets the number of times a traversal was taken
+    pub fn count(&self, step: &TrailStep) -> usize {
+        self.counts.get(step).copied().unwrap_or(0)
+    }
+
+    /// Gets each traversal taken, along with the number of times it was taken
+    pub fn counts(&self) -> impl Iterator<Item = (&TrailStep, usize)> {
+        self.counts.iter().map(|(step, n)| (step, *n))
+    }
+
+    /// Gets the most times any traversal was taken
+    pub fn max_count(&self) -> usize {
+        self.counts.values().copied().max().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gadget::{GadgetDef, Port};
+    use crate::grid::Grid;
+    use crate::spsp_multi;
+
+    use cgmath::vec2;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_trail() {
+        // Toggle at (2, 1), with ports on the bottom and top
+        let def = GadgetDef::from_traversals(2, 2, spsp_multi![((0, 0), (1, 1)), ((1, 1), (0, 0))]);
+        let mut grid = Grid::new();
+        grid.insert(
+            Gadget::new(&Rc::new(def), (1, 1), vec![0, 2], State(0)),
+            vec2(2, 1),
+            (1, 1),
+        );
+
+        let mut agent = Agent::new(vec2(2.5, 1.0), vec2(0, 1));
+        let mut trail = Trail::new();
+
+        // Up, turn around, down, turn around, up
+        for input in &[vec2(0, 1), vec2(0, -1), vec2(0, -1), vec2(0, 1), vec2(0, 1)] {
+            let from = agent.clone();
+            if let Some((gadget, xy, _)) = agent.advance(&mut grid, *input, false, 0) {
+                trail.push(TrailStep::new(&from, &agent, gadget, xy).unwrap());
+            }
+        }
+
+        let up = TrailStep {
+            xy: vec2(2, 1),
+            ports: (Port(0), Port(1)),
+            state: State(1),
+        };
+        let down = TrailStep {
+            xy: vec2(2, 1),
+            ports: (Port(1), Port(0)),
+            state: State(0),
+        };
+        assert_eq!(trail.steps(), &[up, down, up]);
+        assert_eq!(trail.count(&up), 2);
+        assert_eq!(trail.count(&down), 1);
+        assert_eq!(trail.max_count(), 2);
+
+        // The agent has to start at one of the gadget's ports
+        let elsewhere = Agent::new(vec2(0.5, 1.0), vec2(0, 1));
+        let (gadget, _, _) = grid.get(vec2(2, 1)).unwrap();
+        assert_eq!(TrailStep::new(&elsewhere, &agent, gadget, vec2(2, 1)), None);
+
+        trail.clear();
+        assert!(trail.is_empty());
+        assert_eq!(trail.count(&up), 0);
+        assert_eq!(trail.max_count(), 0);
+    }
+}
@@ -0,0 +1,331 @@
+use fnv::FnvHashSet;
+
+use std::rc::Rc;
+
+use crate::gadget::{Gadget, GadgetDef, Port, State, SPSP};
+use crate::grid::WH;
+
+/// A gadget def being built by hand, along with the layout of its gadget
+#[derive(Clone, Debug)]
+pub struct DefEditor {
+    num_states: usize,
+    num_ports: usize,
+    size: WH,
+    /// Maps each port to its position index along the perimeter, like `Gadget`'s port map
+    port_map: Vec<usize>,
+    traversals: FnvHashSet<SPSP>,
+    /// The (from, to) states whose traversals are being edited
+    shown_states: (State, State),
+    /// The port being moved around the perimeter
+    selected_port: Port,
+}
+
+impl DefEditor {
+    /// Creates an editor for a 1x1 gadget with 1 state and ports on the bottom and top
+    pub fn new() -> Self {
+        Self {
+            num_states: 1,
+            num_ports: 2,
+            size: (1, 1),
+            port_map: vec![0, 2],
+            traversals: FnvHashSet::default(),
+            shown_states: (State(0), State(0)),
+            selected_port: Port(0),
+        }
+    }
+
+    /// Creates an editor for a copy of a gadget
+    pub fn from_gadget(gadget: &Gadget) -> Self {
+        Self {
+            num_states: gadget.def().num_states(),
+            num_ports: gadget.def().num_ports(),
+            size: gadget.size(),
+            port_map: gadget.port_map().to_vec(),
+            traversals: gadget.def().traversals().copied().collect(),
+            shown_states: (State(0), State(0)),
+            selected_port: Port(0),
+        }
+    }
+
+    pub fn num_states(&self) -> usize {
+        self.num_states
+    }
+
+    /// Sets the number of states, removing traversals with states that no longer exist.
+    /// There is always at least 1 state.
+    pub fn set_num_states(&mut self, num_states: usize) {
+        self.num_states = num_states.max(1);
+
+        let n = self.num_states;
+        self.traversals
+            .retain(|((s0, _), (s1, _))| s0.0 < n && s1.0 < n);
+
+        let (from, to) = self.shown_states;
+        self.shown_states = (State(from.0.min(n - 1)), State(to.0.min(n - 1)));
+    }
+
+    /// Gets the (from, to) states whose traversals are being edited
+    pub fn shown_states(&self) -> (State, State) {
+        self.shown_states
+    }
+
+    /// Sets the (from, to) states whose traversals are being edited, wrapping around
+    pub fn set_shown_states(&mut self, (from, to): (State, State)) {
+        self.shown_states = (
+            State(from.0 % self.num_states),
+            State(to.0 % self.num_states),
+        );
+    }
+
+    pub fn num_ports(&self) -> usize {
+        self.num_ports
+    }
+
+    fn perimeter(&self) -> usize {
+        2 * self.size.0 + 2 * self.size.1
+    }
+
+    /// Gets the first position index along the perimeter at or after `start`,
+    /// going around in direction `step`, that no port is at
+    fn free_position(&self, start: usize, step: isize) -> Option<usize> {
+        let perimeter = self.perimeter() as isize;
+
+        (0..perimeter)
+            .map(|i| (start as isize + i * step).rem_euclid(perimeter) as usize)
+            .find(|idx| !self.port_map.contains(idx))
+    }
+
+    /// Sets the number of ports, removing traversals with ports that no longer exist.
+    /// New ports go in free spots along the perimeter.
+    /// Returns whether the number could be set; there can't be more ports than spots.
+    pub fn set_num_ports(&mut self, num_ports: usize) -> bool {
+        if num_ports > self.perimeter() {
+            return false;
+        }
+
+        while self.port_map.len() > num_ports {
+            self.port_map.pop();
+        }
+        while self.port_map.len() < num_ports {
+            let idx = self
+                .free_position(0, 1)
+                .expect("Not enough room for the ports");
+            self.port_map.push(idx);
+        }
+
+        self.num_ports = num_ports;
+        self.traversals
+            .retain(|((_, p0), (_, p1))| p0.0 < num_ports && p1.0 < num_ports);
+        self.selected_port = Port(self.selected_port.0.min(num_ports.saturating_sub(1)));
+        true
+    }
+
+    pub fn size(&self) -> WH {
+        self.size
+    }
+
+    /// Sets the size of the gadget, moving ports that fall off the perimeter to free spots.
+    /// Returns whether the size could be set; the perimeter must have room for every port
+    /// and both dimensions must be positive.
+    pub fn set_size(&mut self, size: WH) -> bool {
+        if size.0 == 0 || size.1 == 0 || 2 * size.0 + 2 * size.1 < self.num_ports {
+            return false;
+        }
+
+        self.size = size;
+        let perimeter = self.perimeter();
+
+        for i in 0..self.port_map.len() {
+            if self.port_map[i] >= perimeter {
+                self.port_map[i] = self
+                    .free_position(0, 1)
+                    .expect("Not enough room for the ports");
+            }
+        }
+        true
+    }
+
+    pub fn port_map(&self) -> &[usize] {
+        &self.port_map
+    }
+
+    /// Gets the port being moved around the perimeter
+    pub fn selected_port(&self) -> Port {
+        self.selected_port
+    }
+
+    /// Selects the port to move around the perimeter, wrapping around
+    pub fn select_port(&mut self, port: Port) {
+        if self.num_ports > 0 {
+            self.selected_port = Port(port.0 % self.num_ports);
+        }
+    }
+
+    /// Moves a port to the next free spot along the perimeter,
+    /// counterclockwise if `step` is 1 and clockwise if `step` is -1
+    pub fn move_port(&mut self, port: Port, step: isize) {
+        let start =
+            (self.port_map[port.0] as isize + step).rem_euclid(self.perimeter() as isize) as usize;
+
+        if let Some(idx) = self.free_position(start, step) {
+            self.port_map[port.0] = idx;
+        }
+    }
+
+    pub fn has_traversal(&self, traversal: SPSP) -> bool {
+        self.traversals.contains(&traversal)
+    }
+
+    /// Adds a traversal if it isn't there, or removes it if it is
+    pub fn toggle_traversal(&mut self, traversal: SPSP) {
+        if !self.traversals.remove(&traversal) {
+            self.traversals.insert(traversal);
+        }
+    }
+
+    pub fn def(&self) -> GadgetDef {
+        GadgetDef::from_traversals(
+            self.num_states,
+            self.num_ports,
+            self.traversals.iter().copied(),
+        )
+    }
+
+    /// Builds the gadget in state 0, or says what's wrong with it
+    pub fn build(&self) -> Result<Gadget, String> {
+        let def = self.def();
+        if !def.is_valid() {
+            return Err("The gadget def is invalid".to_string());
+        }
+
+        if self.port_map.len() != self.num_ports {
+            return Err(format!(
+                "The port map has {} ports, but the def has {}",
+                self.port_map.len(),
+                self.num_ports
+            ));
+        }
+
+        if let Some(idx) = self.port_map.iter().find(|idx| **idx >= self.perimeter()) {
+            return Err(format!("Port position {} is off the perimeter", idx));
+        }
+
+        if self.port_map.iter().collect::<FnvHashSet<_>>().len() != self.port_map.len() {
+            return Err("2 ports are in the same spot".to_string());
+        }
+
+        Ok(Gadget::new(
+            &Rc::new(def),
+            self.size,
+            self.port_map.clone(),
+            State(0),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::spsp_multi;
+
+    #[test]
+    fn test_build_new() {
+        let gadget = DefEditor::new().build().unwrap();
+        assert_eq!(gadget.def().num_states(), 1);
+        assert_eq!(gadget.def().num_ports(), 2);
+        assert_eq!(gadget.port_map(), &[0, 2]);
+    }
+
+    #[test]
+    fn test_toggle_traversal() {
+        let mut editor = DefEditor::new();
+        let traversal = spsp_multi![((0, 0), (0, 1))].next().unwrap();
+
+        editor.toggle_traversal(traversal);
+        assert!(editor.has_traversal(traversal));
+        assert_eq!(editor.def().traversals().count(), 1);
+
+        editor.toggle_traversal(traversal);
+        assert!(!editor.has_traversal(traversal));
+    }
+
+    #[test]
+    fn test_set_num_states_removes_traversals() {
+        let mut editor = DefEditor::new();
+        editor.set_num_states(2);
+        editor.toggle_traversal(spsp_multi![((0, 0), (1, 1))].next().unwrap());
+        editor.toggle_traversal(spsp_multi![((0, 0), (0, 1))].next().unwrap());
+
+        editor.set_num_states(0);
+        assert_eq!(editor.num_states(), 1);
+        assert_eq!(
+            editor.def().traversals().copied().collect::<Vec<_>>(),
+            spsp_multi![((0, 0), (0, 1))].collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_shown_states_wrap() {
+        let mut editor = DefEditor::new();
+        editor.set_num_states(3);
+        editor.set_shown_states((State(2), State(3)));
+        assert_eq!(editor.shown_states(), (State(2), State(0)));
+
+        editor.set_num_states(2);
+        assert_eq!(editor.shown_states(), (State(1), State(0)));
+    }
+
+    #[test]
+    fn test_set_num_ports() {
+        let mut editor = DefEditor::new();
+        editor.toggle_traversal(spsp_multi![((0, 0), (0, 1))].next().unwrap());
+
+        assert!(editor.set_num_ports(4));
+        assert_eq!(editor.port_map(), &[0, 2, 1, 3]);
+        assert!(!editor.set_num_ports(5));
+
+        assert!(editor.set_num_ports(1));
+        assert_eq!(editor.port_map(), &[0]);
+        assert_eq!(editor.def().traversals().count(), 0);
+    }
+
+    #[test]
+    fn test_set_size() {
+        let mut editor = DefEditor::new();
+        assert!(editor.set_size((2, 1)));
+        assert_eq!(editor.port_map(), &[0, 2]);
+
+        editor.move_port(Port(1), 1);
+        editor.move_port(Port(1), 1);
+        assert_eq!(editor.port_map(), &[0, 4]);
+
+        assert!(editor.set_size((1, 1)));
+        assert_eq!(editor.port_map(), &[0, 1]);
+        assert!(!editor.set_size((0, 1)));
+
+        editor.set_num_ports(4);
+        assert!(!editor.set_size((1, 0)));
+    }
+
+    #[test]
+    fn test_move_port_skips_taken() {
+        let mut editor = DefEditor::new();
+        editor.move_port(Port(0), 1);
+        assert_eq!(editor.port_map(), &[1, 2]);
+        editor.move_port(Port(0), 1);
+        assert_eq!(editor.port_map(), &[3, 2]);
+        editor.move_port(Port(0), -1);
+        assert_eq!(editor.port_map(), &[1, 2]);
+    }
+
+    #[test]
+    fn test_from_gadget_round_trip() {
+        let def = GadgetDef::from_traversals(2, 2, spsp_multi![((0, 0), (1, 1)), ((1, 1), (0, 0))]);
+        let gadget = Gadget::new(&Rc::new(def), (1, 2), vec![0, 3], State(0));
+
+        let built = DefEditor::from_gadget(&gadget).build().unwrap();
+        assert_eq!(built.def(), gadget.def());
+        assert_eq!(built.size(), (1, 2));
+        assert_eq!(built.port_map(), &[0, 3]);
+    }
+}
@@ -12,19 +12,18 @@ extern crate ron;
 extern crate serde;
 extern crate winit;
 
-mod bit_serde;
 mod bitfield;
-mod gadget;
-mod grid;
-mod math;
-mod preset_gadgets;
+mod def_editor;
 mod render;
-mod shape;
-mod static_map;
 mod ui;
 mod widget;
 
-use cgmath::{vec2, vec3};
+use gadget_core::spsp_multi;
+use gadget_core::{analysis, ascii, bit_serde, canonical, document, explorer};
+use gadget_core::{gadget, grid, library, math, minimize, preset_gadgets, region, replay};
+use gadget_core::{script, shape, share, solver, static_map, trail};
+
+use cgmath::{vec2, vec3, vec4, Vector4};
 use conrod_core::text::{font, Font};
 use conrod_core::{Ui, UiBuilder};
 use fnv::FnvHashSet;
@@ -33,8 +32,11 @@ use golem::blend::BlendMode;
 use golem::depth::{DepthTestFunction, DepthTestMode};
 use golem::Context;
 
+use itertools::Itertools;
 use ref_thread_local::RefThreadLocal;
 
+use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 
@@ -46,13 +48,23 @@ use winit::event_loop::{ControlFlow, EventLoop};
 use winit::platform::web::WindowExtWebSys;
 use winit::window::WindowBuilder;
 
-use gadget::{Agent, Gadget, GadgetDef, State};
+use def_editor::DefEditor;
+use explorer::Exploration;
+use gadget::{AdvancePreview, Agent, Gadget, GadgetDef, State};
 use grid::Grid;
-use math::Vec2;
+use library::GadgetLibrarySerde;
+use math::{Vec2, Vec2i};
+use region::Region;
 use render::TEXTURES;
-use render::{Camera, GadgetRenderer, SelectionRenderer, UiRenderer};
+use render::{AgentAnimation, AgentEx, UiRenderer};
+use render::{Camera, GadgetEx, GadgetRenderer, PortHighlightRenderer, SelectionRenderer};
+use render::{GadgetModelEx, MoveHintRenderer, TrailRenderer};
 use render::{MODELS, SHADERS, TRIANGLESES};
-use ui::{LeftMouseAction, Mode, WidgetIds};
+use replay::Replay;
+use script::Script;
+use solver::Step;
+use trail::{Trail, TrailStep};
+use ui::{LeftMouseAction, Mode, Playback, TraversalChoice, WidgetIds};
 
 #[macro_export]
 macro_rules! log {
@@ -97,8 +109,15 @@ pub struct Fonts {
 pub enum UndoAction {
     GadgetInsert { position: grid::XY },
     GadgetRemove { gadget: Gadget, position: grid::XY },
-    AgentMove { position: Vec2, direction: grid::XY },
+    /// An agent moved, where `agent` is its index in the list of agents
+    AgentMove { agent: usize, position: Vec2, direction: grid::XY },
     GadgetChangeState { position: grid::XY, state: State },
+    /// A gadget was added to the end of the gadget selector
+    PaletteAdd,
+    /// A gadget was removed from the end of the gadget selector
+    PaletteRemove { gadget: Gadget },
+    /// The gadget at `index` in the gadget selector was replaced, and `gadget` is the old one
+    PaletteReplace { index: usize, gadget: Gadget },
     Batch(Vec<UndoAction>),
 }
 
@@ -146,10 +165,11 @@ impl UndoStack {
             }
 
             UndoAction::AgentMove {
+                agent: index,
                 position,
                 direction,
             } => {
-                if let Some(agent) = app.agent.as_mut() {
+                if let Some(agent) = app.agents.get_mut(index) {
                     let old_position = agent.position();
                     let old_direction = agent.direction();
 
@@ -160,6 +180,7 @@ impl UndoStack {
                     }
 
                     Some(UndoAction::AgentMove {
+                        agent: index,
                         position: old_position,
                         direction: old_direction,
                     })
@@ -182,6 +203,35 @@ impl UndoStack {
                 })
             }
 
+            UndoAction::PaletteAdd => {
+                let gadget = app
+                    .palette_pop()
+                    .expect("A PaletteAdd action was inserted when no gadget was added");
+
+                if app.gadget_selection == Some(app.gadget_select.len()) {
+                    app.gadget_selection = None;
+                }
+                Some(UndoAction::PaletteRemove { gadget })
+            }
+
+            UndoAction::PaletteRemove { gadget } => {
+                app.palette_push(gadget);
+                Some(UndoAction::PaletteAdd)
+            }
+
+            UndoAction::PaletteReplace { index, gadget } => {
+                let old_gadget = app.palette_replace(index, gadget);
+
+                // The gadget being painted was made from the old one
+                if app.gadget_selection == Some(index) && app.gadget_tile.is_some() {
+                    app.gadget_tile = Some(app.gadget_select[index].clone());
+                }
+                Some(UndoAction::PaletteReplace {
+                    index,
+                    gadget: old_gadget,
+                })
+            }
+
             UndoAction::Batch(actions) => {
                 let mut rev_actions = vec![];
 
@@ -270,10 +320,61 @@ pub struct App<'a> {
     gadget_renderer: GadgetRenderer,
     /// A list of gadgets that can be selected from the selector
     gadget_select: Vec<Gadget>,
+    /// Descriptions of the properties of the gadgets in the selector, in the same order.
+    /// Computed when a gadget is added, since analyzing it every frame is slow.
+    gadget_select_properties: Vec<String>,
+    /// The preset gadgets, which start out in the selector and aren't saved with the library
+    presets: Vec<Gadget>,
     gadget_selection: Option<usize>,
     /// The gadget currently being used to paint tiles
     gadget_tile: Option<Gadget>,
-    agent: Option<Agent>,
+    /// Agents in play, in the order they were placed
+    agents: Vec<Agent>,
+    /// Index of the agent that moves in play mode
+    active_agent: usize,
+    /// Traversals the agent is choosing between in play mode
+    traversal_choice: Option<TraversalChoice>,
+    port_highlight_renderer: PortHighlightRenderer,
+    /// Edge the solver is trying to get the agent to in play mode
+    solve_target: Option<Vec2>,
+    /// Remaining steps of the solution to `solve_target`
+    solution: VecDeque<Step>,
+    solve_target_renderer: PortHighlightRenderer,
+    /// Report of the configurations reachable in play mode
+    exploration: Option<Exploration>,
+    /// Index of the last configuration jumped to in the exploration
+    exploration_index: usize,
+    /// Result of the last test script run
+    script_report: String,
+    /// Moves made in play mode, from the start of play
+    recording: Option<Replay>,
+    /// Moves taken off the recording by undoing, for redoing
+    recording_redo: Vec<(usize, Step)>,
+    /// Replay being watched in play mode
+    playback: Option<Playback>,
+    /// Agent moving through a gadget
+    animation: Option<AgentAnimation>,
+    /// Traversals taken in play mode
+    trail: Trail,
+    /// Whether the trail is shown as a heatmap of how many times each traversal was taken
+    trail_heatmap: bool,
+    trail_renderer: TrailRenderer,
+    move_hint_renderer: MoveHintRenderer,
+    /// Time an agent takes to move through a gadget, in milliseconds
+    animation_duration: f64,
+    /// Inputs made while an agent was moving, as (input, back) pairs
+    queued_inputs: VecDeque<(Vec2i, bool)>,
+    /// Region being checked for simulating a gadget
+    region: Option<Region>,
+    /// Result of the last region check
+    region_report: String,
+    region_renderer: PortHighlightRenderer,
+    /// Gadget def being built by hand
+    def_editor: DefEditor,
+    /// Result of the last attempt to add the edited def to the gadget selector
+    def_editor_report: String,
+    /// Result of the last attempt to import a contraption from text
+    contraption_report: String,
     gadget_select_rep: Gadget,
     /// A list of gadget positions in the contraption that are selected,
     /// along with cached sizes
@@ -292,13 +393,27 @@ pub struct App<'a> {
     // One for editing, and one for playing
     undo_stacks: [Option<UndoStack>; 2],
     undo_stack_index: usize,
-    _modifiers: ModifiersState,
+    /// Modifier keys held down as of the last keyboard input
+    modifiers: ModifiersState,
 }
 
 impl<'a> App<'a> {
     const HEIGHT_MIN: f64 = 1.0;
     const HEIGHT_MAX: f64 = 32.0;
     const WIDTH_MAX: f64 = 128.0;
+    /// Pixels per grid unit in exported images
+    const PNG_CELL_SIZE: usize = 64;
+
+    /// Colors of agents, in the order they are placed.
+    /// The first is the color of the agent button.
+    const AGENT_COLORS: [[f32; 3]; 6] = [
+        [0.0, 0.8, 0.0],
+        [0.0, 0.3, 0.9],
+        [0.9, 0.5, 0.0],
+        [0.6, 0.0, 0.8],
+        [0.85, 0.0, 0.0],
+        [0.0, 0.6, 0.6],
+    ];
 
     pub fn new(gl: Rc<Context>, ui: &mut Ui, _width: u32, _height: u32) -> Self {
         let camera = Camera::new_orthographic(
@@ -312,6 +427,16 @@ impl<'a> App<'a> {
 
         let grid = load_grid_from_url().unwrap_or_else(|| Grid::new());
 
+        let presets = preset_gadgets::preset_gadgets();
+        let mut gadget_select = presets.clone();
+        if let Some(gadgets) = load_library_from_storage() {
+            library::merge_library(&mut gadget_select, gadgets);
+        }
+        if let Some(gadgets) = load_palette_from_url() {
+            library::merge_library(&mut gadget_select, gadgets);
+        }
+        let gadget_select_properties = gadget_select.iter().map(describe_properties).collect();
+
         //let def = GadgetDef::from_traversals(2, 2, vec![((0, 0), (1, 1)), ((1, 1), (0, 0))]);
 
         //let gadget = Gadget::new(
@@ -349,6 +474,11 @@ impl<'a> App<'a> {
         let paste_renderer = GadgetRenderer::new(&gl);
         let ui_renderer = UiRenderer::new(&gl);
         let selection_renderer = SelectionRenderer::new(&gl);
+        let port_highlight_renderer = PortHighlightRenderer::new(&gl);
+        let solve_target_renderer = PortHighlightRenderer::new(&gl);
+        let region_renderer = PortHighlightRenderer::new(&gl);
+        let trail_renderer = TrailRenderer::new(&gl);
+        let move_hint_renderer = MoveHintRenderer::new(&gl);
 
         let fonts = Fonts {
             regular: ui.fonts.insert(
@@ -371,7 +501,7 @@ impl<'a> App<'a> {
 
         ui.theme.font_id = Some(fonts.regular);
 
-        Self {
+        let mut app = Self {
             gl,
             camera,
             center: vec2(0.0, 0.0),
@@ -380,10 +510,37 @@ impl<'a> App<'a> {
             grid_mouse_position: vec2(0.0, 0.0),
             int_mouse_position: vec2(0, 0),
             gadget_renderer,
-            gadget_select: preset_gadgets::preset_gadgets(),
+            gadget_select,
+            gadget_select_properties,
+            presets,
             gadget_selection: None,
             gadget_tile: None,
-            agent: None,
+            agents: vec![],
+            active_agent: 0,
+            traversal_choice: None,
+            port_highlight_renderer,
+            solve_target: None,
+            solution: VecDeque::new(),
+            solve_target_renderer,
+            exploration: None,
+            exploration_index: 0,
+            script_report: String::new(),
+            recording: None,
+            recording_redo: vec![],
+            playback: None,
+            animation: None,
+            trail: Trail::new(),
+            trail_heatmap: false,
+            trail_renderer,
+            move_hint_renderer,
+            animation_duration: AgentAnimation::DEFAULT_DURATION,
+            queued_inputs: VecDeque::new(),
+            region: None,
+            region_report: String::new(),
+            region_renderer,
+            def_editor: DefEditor::new(),
+            def_editor_report: String::new(),
+            contraption_report: String::new(),
             gadget_select_rep,
             selection: FnvHashSet::default(),
             selection_renderer,
@@ -397,8 +554,14 @@ impl<'a> App<'a> {
             _fonts: fonts,
             undo_stacks: [Some(UndoStack::new()), Some(UndoStack::new())],
             undo_stack_index: 0,
-            _modifiers: ModifiersState::default(),
+            modifiers: ModifiersState::default(),
+        };
+
+        if let Some(replay) = load_replay_from_url() {
+            app.watch_replay(replay);
         }
+
+        app
     }
 
     // Convenience functions that assume the logic is correct
@@ -418,6 +581,10 @@ impl<'a> App<'a> {
     pub fn invalidate_before_undo(&mut self) {
         // A selected gadget may be deleted
         self.selection.clear();
+        // The agent may have moved
+        self.traversal_choice = None;
+        self.solution.clear();
+        self.stop_animation();
     }
 
     pub fn undo(&mut self) {
@@ -426,6 +593,10 @@ impl<'a> App<'a> {
         let mut stack = self.undo_stack_take();
         stack.undo(self);
         self.undo_stacks[self.undo_stack_index] = Some(stack);
+
+        if self.mode == Mode::Play {
+            self.sync_recording(true);
+        }
     }
 
     pub fn redo(&mut self) {
@@ -434,6 +605,10 @@ impl<'a> App<'a> {
         let mut stack = self.undo_stack_take();
         stack.redo(self);
         self.undo_stacks[self.undo_stack_index] = Some(stack);
+
+        if self.mode == Mode::Play {
+            self.sync_recording(false);
+        }
     }
 
     pub fn add_gadget_to_grid(&mut self, gadget: Gadget, position: grid::XY) {
@@ -497,7 +672,7 @@ impl<'a> App<'a> {
         }
 
         if self.mode == Mode::AgentPlace {
-            if let Some(agent) = &mut self.agent {
+            if let Some(agent) = self.agent_mut() {
                 agent.flip();
             }
         }
@@ -594,227 +769,1357 @@ impl<'a> App<'a> {
         }
     }
 
-    pub fn pan(&mut self, xy: Vec2) {
-        self.center += xy;
-    }
+    /// Minimizes the def of the gadget being painted, starting from its current state.
+    /// Its entry in the gadget selector and the placed gadgets with the same def
+    /// are remapped to the minimized def, except for placed ones in unreachable states.
+    /// The entry keeps its state if that is reachable, and takes the painted gadget's otherwise.
+    pub fn minimize_tile_def(&mut self) {
+        let (index, tile) = match (self.gadget_selection, &self.gadget_tile) {
+            (Some(index), Some(tile)) => (index, tile),
+            _ => return,
+        };
 
-    pub fn zoom(&mut self, center: Vec2, amount: f64, ui: &Ui) {
-        let prev_height = self.height;
-        self.height += amount;
-        self.clamp_height(&ui);
-        self.center = center + (self.center - center) * self.height / prev_height;
-    }
+        let old_def = Rc::clone(tile.def());
+        let minimized = minimize::minimize(&old_def, tile.state());
+        log!(
+            "Minimized from {} states and {} ports to {} states and {} ports",
+            old_def.num_states(),
+            old_def.num_ports(),
+            minimized.def.num_states(),
+            minimized.def.num_ports()
+        );
+        if !minimized.dead_ports.is_empty() {
+            log!(
+                "Removed dead ports {:?}",
+                minimized
+                    .dead_ports
+                    .iter()
+                    .map(|p| p.id())
+                    .collect::<Vec<_>>()
+            );
+        }
 
-    pub fn cut(&mut self, center: bool) {
-        if self.selection.len() > 0 {
-            self.paste = self.copy_selected_gadgets(center);
-            self.remove_selected_gadgets();
-            self.set_mode(Mode::GadgetPaste);
+        let entry = &self.gadget_select[index];
+        let new_entry = minimized.remap(entry).unwrap_or_else(|| {
+            let mut entry = entry.clone();
+            entry.set_state(tile.state());
+            minimized.remap(&entry).expect("Start state is unreachable")
+        });
+        self.gadget_tile = minimized.remap(tile);
+
+        let old_entry = self.palette_replace(index, new_entry);
+        self.undo_stack_mut().push(UndoAction::PaletteReplace {
+            index,
+            gadget: old_entry,
+        });
+
+        let remapped = self
+            .grid
+            .iter()
+            .filter(|(gadget, _, _)| **gadget.def() == *old_def)
+            .map(|(gadget, xy, _)| (*xy, minimized.remap(gadget)))
+            .collect::<Vec<_>>();
+
+        let mut num_unreachable = 0;
+        for (xy, gadget) in remapped {
+            match gadget {
+                Some(gadget) => self.add_gadget_to_grid(gadget, xy),
+                None => num_unreachable += 1,
+            }
         }
-    }
+        self.undo_stack_mut().batch();
 
-    pub fn copy(&mut self, center: bool) {
-        if self.selection.len() > 0 {
-            self.paste = self.copy_selected_gadgets(center);
-            self.set_mode(Mode::GadgetPaste);
+        if num_unreachable > 0 {
+            log!(
+                "Left {} placed gadgets alone because their states are unreachable",
+                num_unreachable
+            );
         }
     }
 
-    pub fn paste(&mut self) {
-        if self.mode != Mode::GadgetMove && !self.paste.is_empty() {
-            self.set_mode(Mode::GadgetPaste);
-        }
+    /// Gets the agent that moves in play mode, if there is one
+    pub fn agent(&self) -> Option<&Agent> {
+        self.agents.get(self.active_agent)
     }
 
-    pub fn update(&mut self, ui: &mut Ui) {
-        self.clamp_height(ui);
+    pub fn agent_mut(&mut self) -> Option<&mut Agent> {
+        self.agents.get_mut(self.active_agent)
+    }
 
-        self.update_ui(ui);
+    /// Gets the color of the agent at `index`.
+    /// Agents other than the active one are faded.
+    pub fn agent_color(&self, index: usize) -> Vector4<f32> {
+        let [r, g, b] = Self::AGENT_COLORS[index % Self::AGENT_COLORS.len()];
+        let alpha = if index == self.active_agent { 1.0 } else { 0.5 };
+        vec4(r, g, b, alpha)
+    }
 
-        self.camera.set_orthographic_projection(
-            ui.win_w / ui.win_h * self.height,
-            self.height,
-            1.0,
-        );
+    /// Starts placing a new agent, which joins the agents already in play
+    pub fn place_agent(&mut self) {
+        if self.mode == Mode::AgentPlace {
+            return;
+        }
 
-        let cx = self.center.x;
-        let cy = self.center.y;
-        self.camera
-            .set_view(vec3(cx, cy, 0.0), vec3(cx, cy, -1.0), vec3(0.0, 1.0, 0.0));
+        self.set_mode(Mode::AgentPlace);
+        self.agents.push(Agent::new(vec2(0.5, 0.0), vec2(0, 1)));
+        self.active_agent = self.agents.len() - 1;
     }
 
-    pub fn render(&mut self, ui: &mut Ui, width: f64, height: f64) {
-        self.gl.clear();
+    /// Makes the agent at `index` the one that moves in play mode.
+    /// Anything found for the previous agent is forgotten.
+    pub fn set_active_agent(&mut self, index: usize) {
+        if index == self.active_agent || index >= self.agents.len() {
+            return;
+        }
 
-        self.selection_renderer.render(
-            self.selection.iter().copied(),
-            &self.camera,
-            vec2(0, 0),
-            SelectionRenderer::Z,
-        );
+        self.active_agent = index;
+        self.traversal_choice = None;
+        self.solve_target = None;
+        self.solution.clear();
+        self.exploration = None;
+    }
 
-        render::render_grid(
-            &self.grid,
-            &self.camera,
-            &mut self.gadget_renderer,
-            vec2(0, 0),
-            0.0,
-            true,
-        );
+    /// Makes the agent `offset` places after the active one active, wrapping around
+    pub fn cycle_active_agent(&mut self, offset: isize) {
+        if !self.agents.is_empty() {
+            let num_agents = self.agents.len() as isize;
+            let index = (self.active_agent as isize + offset).rem_euclid(num_agents);
+            self.set_active_agent(index as usize);
+        }
+    }
 
-        if self.mode == Mode::GadgetMove {
-            render::render_grid(
-                &self.moving,
-                &self.camera,
-                &mut self.paste_renderer,
-                self.int_mouse_position,
-                -0.25,
-                false,
-            );
+    /// Handles a click at `position` in play mode.
+    /// While the player is choosing a traversal, this is about the traversal.
+    /// Otherwise, clicking an agent makes it active.
+    pub fn click_play(&mut self, position: Vec2) {
+        if self.traversal_choice.is_some() {
+            self.click_traversal_choice(position);
+            return;
+        }
 
-            self.selection_renderer.render(
-                self.moving.iter().map(|(_, xy, wh)| (*xy, *wh)),
-                &self.camera,
-                self.int_mouse_position,
-                SelectionRenderer::Z - 0.1,
-            );
+        if let Some(index) = self.agents.iter().position(|agent| {
+            let diff = agent.position() - position;
+            diff.x * diff.x + diff.y * diff.y <= 0.25 * 0.25
+        }) {
+            self.set_active_agent(index);
         }
+    }
 
-        if self.mode == Mode::GadgetPaste {
-            render::render_grid(
-                &self.paste,
-                &self.camera,
-                &mut self.paste_renderer,
-                self.int_mouse_position,
-                -0.25,
-                false,
-            );
+    /// Moves the active agent with a step.
+    /// The move is recorded on the undo stack as a single batch,
+    /// and in the recording if it did anything.
+    fn move_agent(&mut self, step: Step) {
+        let index = self.active_agent;
+        let agent = match self.agents.get_mut(index) {
+            Some(agent) => agent,
+            None => return,
+        };
+        // Borrowing rules require that self.undo_stack is obtained directly
+        let undo_stack = self.undo_stacks[self.undo_stack_index]
+            .as_mut()
+            .expect("Tried to get undo stack while undoing/redoing");
+        let prev_position = agent.position();
+        let prev_direction = agent.direction();
+        let from = agent.clone();
+        let traversed = gadget_in_front(&self.grid, agent, &step);
+
+        let result = agent
+            .advance(&mut self.grid, step.input, step.back, step.choice)
+            .map(|(_, xy, state)| (xy, state));
+        let moved = agent.position() != prev_position || agent.direction() != prev_direction;
+
+        if moved {
+            undo_stack.push(UndoAction::AgentMove {
+                agent: index,
+                position: prev_position,
+                direction: prev_direction,
+            })
         }
 
-        if let Some(gadget) = &self.gadget_tile {
-            gadget
-                .renderer()
-                .model(&self.gl)
-                .prepare_render()
-                .render_position(
-                    vec3(
-                        self.int_mouse_position.x as f64,
-                        self.int_mouse_position.y as f64,
-                        -0.25,
-                    ),
-                    &self.camera,
-                );
+        if let Some((xy, state)) = result {
+            undo_stack.push(UndoAction::GadgetChangeState {
+                position: xy,
+                state,
+            });
+        }
+
+        undo_stack.batch();
+
+        if moved || result.is_some() {
+            self.record_move(step);
         }
+        self.show_move(index, &from, traversed);
+    }
 
-        if let Some(agent) = &self.agent {
-            agent.render(&self.camera);
+    /// Shows agent `index` going from `from` to where it is now through `traversed`,
+    /// the gadget it went through in its previous state, if there is one.
+    /// The agent is animated, and the traversal is added to the trail.
+    fn show_move(&mut self, index: usize, from: &Agent, traversed: Option<(grid::XY, Gadget)>) {
+        let step = traversed.as_ref().and_then(|(xy, _)| {
+            let (gadget, _, _) = self.grid.get(*xy)?;
+            TrailStep::new(from, self.agents.get(index)?, gadget, *xy)
+        });
+        if let Some(step) = step {
+            self.trail.push(step);
         }
 
-        self.render_ui(ui, width, height);
+        self.animation = traversed.and_then(|(xy, gadget)| {
+            AgentAnimation::new(
+                index,
+                from,
+                self.agents.get(index)?,
+                gadget,
+                xy,
+                now(),
+                self.animation_duration,
+            )
+        });
     }
 
-    pub fn handle_input(&mut self, event: &Event<()>) {
-        match event {
-            //Event::WindowEvent {
-            //    event:
-            //        WindowEvent::MouseWheel {
-            //            delta: MouseScrollDelta::PixelDelta(LogicalPosition { y: delta, .. }),
-            //            ..
-            //        },
-            //    ..
-            //} => {
-            //    self.height = self.height + *delta / 64.0
-            //}
+    /// Stops animating the agent, and forgets the inputs made during the animation
+    fn stop_animation(&mut self) {
+        self.animation = None;
+        self.queued_inputs.clear();
+    }
 
-            //TODO: Implement the event in winit for the web
-            //Event::WindowEvent {
-            //    event: WindowEvent::ModifiersChanged(state),
-            //    ..
-            //} => {
-            //    self.modifiers = *state;
-            //    log!("Modifiers: {:?}", self.modifiers);
-            //}
-            // Until WindowEvent::ModifiersChanged gets implemeneted for web
-            #[allow(deprecated)]
-            Event::WindowEvent {
-                event:
-                    WindowEvent::KeyboardInput {
-                        input:
-                            KeyboardInput {
-                                virtual_keycode: Some(keycode),
-                                state,
-                                modifiers,
-                                ..
-                            },
-                        ..
-                    },
-                ..
-            } => {
-                if modifiers.ctrl() {
-                    if let ElementState::Pressed = state {
-                        match keycode {
-                            VirtualKeyCode::Z => {
-                                self.undo();
-                            }
+    /// Finishes the agent's animation if it is time to,
+    /// then makes the next input made during it
+    fn update_animation(&mut self) {
+        let time = now();
 
-                            VirtualKeyCode::Y => {
-                                self.redo();
-                            }
+        if self
+            .animation
+            .as_ref()
+            .is_some_and(|animation| animation.is_finished(time))
+        {
+            self.animation = None;
 
-                            VirtualKeyCode::X => {
-                                self.cut(false);
-                            }
+            if let Some((input, back)) = self.queued_inputs.pop_front() {
+                self.advance_agent(input, back);
+            }
+        }
+    }
 
-                            VirtualKeyCode::C => {
-                                self.copy(false);
-                            }
+    /// Advances the agent in play mode with some input.
+    /// If `back` is true, an input opposite the agent's direction
+    /// moves it backward through the gadget instead of turning it around.
+    /// If there are multiple traversals to choose from,
+    /// the player gets to choose one instead.
+    pub fn advance_agent(&mut self, input: Vec2i, back: bool) {
+        // Wait for the agent to get where it's going
+        if self.animation.is_some() {
+            self.queued_inputs.push_back((input, back));
+            return;
+        }
 
-                            VirtualKeyCode::V => {
-                                self.paste();
-                            }
+        self.traversal_choice = None;
+        // The solution only works from where it was found
+        self.solution.clear();
+
+        if let Some((xy, targets)) = self
+            .agent()
+            .and_then(|agent| agent.traversals(&self.grid, input, back))
+        {
+            if targets.len() > 1 {
+                if let Some((gadget, _, _)) = self.grid.get(xy) {
+                    self.traversal_choice =
+                        Some(TraversalChoice::new(input, back, xy, targets, gadget));
+                    // The inputs after this were made without knowing about the choice
+                    self.queued_inputs.clear();
+                    return;
+                }
+            }
+        }
 
-                            VirtualKeyCode::S => {
-                                crate::save_grid_in_url(&self.grid);
-                            }
+        self.move_agent(Step::new(input, back, 0));
+    }
 
-                            VirtualKeyCode::A => {
-                                if self.mode != Mode::GadgetMove {
-                                    self.set_mode(Mode::Select);
-                                    self.selection
-                                        .extend(self.grid.iter().map(|(_, xy, wh)| (*xy, *wh)));
-                                }
-                            }
+    /// Makes the agent take the highlighted traversal, if the player is choosing one
+    pub fn confirm_traversal_choice(&mut self) {
+        if let Some(choice) = self.traversal_choice.take() {
+            self.move_agent(choice.step());
+        }
+    }
 
-                            _ => {}
-                        }
+    /// Handles a click at `position` while the player is choosing a traversal.
+    /// Clicking an exit port highlights a traversal through it,
+    /// and clicking it again cycles through the traversals through that port.
+    /// Clicking the highlighted one when it is the only one through its port,
+    /// or clicking elsewhere on the gadget, takes it.
+    pub fn click_traversal_choice(&mut self, position: Vec2) {
+        let choice = match self.traversal_choice.as_mut() {
+            Some(choice) => choice,
+            None => return,
+        };
+        let gadget = match self.grid.get(choice.xy()) {
+            Some((gadget, _, _)) => gadget,
+            None => return,
+        };
+
+        let offset = position - choice.xy().cast::<f64>().unwrap();
+        let port_positions = gadget.port_positions();
+        let (w, h) = gadget.size();
+
+        let clicked = port_positions.iter().position(|pos| {
+            let diff = *pos - offset;
+            diff.x * diff.x + diff.y * diff.y <= 0.25 * 0.25
+        });
+        let at_port = |port: usize| {
+            choice
+                .targets()
+                .iter()
+                .enumerate()
+                .filter(move |(_, (_, p))| p.id() == port)
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>()
+        };
+
+        match clicked.map(at_port) {
+            Some(indexes) if !indexes.is_empty() => {
+                if let Some(i) = indexes.iter().position(|i| *i == choice.index()) {
+                    if indexes.len() == 1 {
+                        self.confirm_traversal_choice();
+                    } else {
+                        choice.set_index(indexes[(i + 1) % indexes.len()]);
                     }
                 } else {
-                    if let ElementState::Pressed = state {
-                        match keycode {
-                            VirtualKeyCode::R | VirtualKeyCode::T => {
-                                let num_turns = if *keycode == VirtualKeyCode::R { 1 } else { -1 };
-                                self.rotate_active(num_turns);
-                            }
-
-                            VirtualKeyCode::X => {
-                                self.flip_x_active();
-                            }
+                    choice.set_index(indexes[0]);
+                }
+            }
+            Some(_) => {}
+            None => {
+                let inside = offset.x >= 0.0
+                    && offset.y >= 0.0
+                    && offset.x <= w as f64
+                    && offset.y <= h as f64;
+                if inside {
+                    self.confirm_traversal_choice();
+                }
+            }
+        }
+    }
 
-                            VirtualKeyCode::Y => {
-                                self.flip_y_active();
-                            }
+    /// Finds the shortest way for the agent to get to the edge centered at `target`
+    /// so it can be played back with `step_solution`
+    pub fn solve_to(&mut self, target: Vec2) {
+        self.solve_target = Some(target);
+        self.solution.clear();
+
+        if let Some(agent) = self.agents.get(self.active_agent) {
+            match solver::solve(&self.grid, agent, target, solver::DEFAULT_BUDGET) {
+                Ok(steps) => {
+                    log!("Found a solution with {} steps", steps.len());
+                    self.solution = steps.into();
+                }
 
-                            VirtualKeyCode::U => {
-                                self.twist_active();
-                            }
+                Err(e) => {
+                    elog!("Failed to find a solution: {}", e);
+                }
+            }
+        }
+    }
 
-                            VirtualKeyCode::C => {
-                                self.cycle_state_active();
-                            }
+    /// Takes the next step of the solution found by `solve_to`, if any
+    pub fn step_solution(&mut self) {
+        self.traversal_choice = None;
 
-                            VirtualKeyCode::O => {
-                                self.rotate_ports_active(1);
-                            }
+        if let Some(step) = self.solution.pop_front() {
+            self.move_agent(step);
+        }
+    }
+
+    /// Finds all the configurations reachable from the agent's current one
+    pub fn explore(&mut self) {
+        if let Some(agent) = self.agents.get(self.active_agent) {
+            let exploration = Exploration::explore(&self.grid, agent, solver::DEFAULT_BUDGET);
+
+            log!(
+                "Found {} configurations, {} dead ends, and {} unvisited states{}",
+                exploration.configurations().len(),
+                exploration.dead_ends().len(),
+                exploration.unvisited_states().len(),
+                if exploration.is_complete() {
+                    ""
+                } else {
+                    " before giving up"
+                }
+            );
+
+            self.exploration = Some(exploration);
+            self.exploration_index = 0;
+        }
+    }
+
+    /// Puts the agent and gadgets in the configuration at `index` in the exploration.
+    /// This is recorded on the undo stack as a single batch.
+    pub fn jump_to_configuration(&mut self, index: usize) {
+        let (config, positions) = match self.exploration.as_ref().and_then(|e| {
+            Some((
+                e.configurations().get(index)?.clone(),
+                e.positions().to_vec(),
+            ))
+        }) {
+            Some(result) => result,
+            None => return,
+        };
+
+        self.exploration_index = index;
+        self.traversal_choice = None;
+        self.solution.clear();
+
+        let mut agents = self.agents.clone();
+        match agents.get_mut(self.active_agent) {
+            Some(agent) => *agent = config.agent,
+            None => return,
+        }
+
+        self.set_configuration(agents, positions.into_iter().zip(config.states));
+        // The moves to get here aren't known
+        self.restart_recording();
+    }
+
+    /// Puts every agent and some gadgets in new positions and states.
+    /// This is recorded on the undo stack as a single batch.
+    fn set_configuration(
+        &mut self,
+        agents: Vec<Agent>,
+        states: impl IntoIterator<Item = (grid::XY, State)>,
+    ) {
+        self.stop_animation();
+
+        // Borrowing rules require that self.undo_stack is obtained directly
+        let undo_stack = self.undo_stacks[self.undo_stack_index]
+            .as_mut()
+            .expect("Tried to get undo stack while undoing/redoing");
+
+        for (index, (agent, new_agent)) in self.agents.iter_mut().zip(agents).enumerate() {
+            if *agent != new_agent {
+                undo_stack.push(UndoAction::AgentMove {
+                    agent: index,
+                    position: agent.position(),
+                    direction: agent.direction(),
+                });
+                *agent = new_agent;
+            }
+        }
+
+        for (xy, state) in states {
+            if let Some((gadget, _, _)) = self.grid.get_mut(xy) {
+                if gadget.state() != state {
+                    undo_stack.push(UndoAction::GadgetChangeState {
+                        position: xy,
+                        state: gadget.state(),
+                    });
+                    gadget.set_state(state);
+                }
+            }
+        }
+
+        undo_stack.batch();
+    }
+
+    /// Starts recording the moves in play mode over, from where the agents and gadgets are now.
+    /// This stops watching a replay.
+    pub fn restart_recording(&mut self) {
+        self.playback = None;
+        self.recording = Some(Replay::new(&self.grid, &self.agents));
+        self.recording_redo.clear();
+    }
+
+    /// Records a move of the active agent.
+    /// Moving while watching a replay records the moves watched so far, then that move.
+    fn record_move(&mut self, step: Step) {
+        if let Some(playback) = self.playback.take() {
+            let position = playback.position();
+            let mut replay = playback.into_replay();
+            replay.truncate(position);
+            self.recording = Some(replay);
+        }
+
+        if let Some(recording) = &mut self.recording {
+            recording.push(self.active_agent, step);
+        }
+        self.recording_redo.clear();
+    }
+
+    /// Gets whether the first `len` moves of a replay put the agents and gadgets where they are
+    fn replay_matches(&self, replay: &Replay, len: usize) -> bool {
+        let mut grid = self.grid.clone();
+
+        match replay.seek(&mut grid, len) {
+            Ok(agents) => {
+                agents == self.agents
+                    && grid.iter().all(|(gadget, xy, _)| {
+                        self.grid.get(*xy).map(|(g, _, _)| g.state()) == Some(gadget.state())
+                    })
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Keeps the recording in step with an undo or redo in play mode.
+    /// If it can't be, the recording starts over.
+    fn sync_recording(&mut self, undone: bool) {
+        // Undoing while watching a replay stops watching it
+        let mut recording = match self.recording.take() {
+            Some(recording) => recording,
+            None => {
+                self.restart_recording();
+                return;
+            }
+        };
+
+        if undone {
+            self.recording_redo.extend(recording.pop());
+        } else if let Some((agent, step)) = self.recording_redo.pop() {
+            recording.push(agent, step);
+        }
+
+        if self.replay_matches(&recording, recording.len()) {
+            self.recording = Some(recording);
+        } else {
+            self.restart_recording();
+        }
+    }
+
+    /// Starts watching a replay in play mode, from the start
+    pub fn watch_replay(&mut self, replay: Replay) {
+        if let Err(e) = replay.rewind(&mut self.grid.clone()) {
+            elog!("Failed to watch replay: {}", e);
+            return;
+        }
+
+        // Start playing over, with the replay's agents
+        self.set_mode(Mode::None);
+        self.agents = replay.starts().to_vec();
+        self.active_agent = 0;
+        self.set_mode(Mode::Play);
+
+        self.recording = None;
+        self.playback = Some(Playback::new(replay));
+        self.seek_playback(0);
+    }
+
+    /// Puts the agents and gadgets where the first `position` moves of the watched replay take them.
+    /// This is recorded on the undo stack as a single batch.
+    pub fn seek_playback(&mut self, position: usize) {
+        let playback = match self.playback.as_mut() {
+            Some(playback) => playback,
+            None => return,
+        };
+        let prev_position = playback.position();
+        playback.set_position(position);
+
+        let mut grid = self.grid.clone();
+        let agents = match playback.replay().seek(&mut grid, playback.position()) {
+            Ok(agents) => agents,
+            Err(e) => {
+                elog!("Failed to watch replay: {}", e);
+                return;
+            }
+        };
+
+        // Show the agent making the move if there is just one
+        let next_move = if playback.position() == prev_position + 1 {
+            playback.replay().steps().get(prev_position).cloned()
+        } else {
+            None
+        };
+        let animated = next_move.and_then(|(index, step)| {
+            let from = self.agents.get(index)?.clone();
+            let traversed = gadget_in_front(&self.grid, &from, &step);
+            Some((index, from, traversed))
+        });
+
+        self.traversal_choice = None;
+        self.solution.clear();
+        self.set_configuration(
+            agents,
+            grid.iter().map(|(gadget, xy, _)| (*xy, gadget.state())),
+        );
+
+        if let Some((index, from, traversed)) = animated {
+            self.show_move(index, &from, traversed);
+        }
+    }
+
+    /// Moves the watched replay forward or backward by some number of moves, and pauses it
+    pub fn step_playback(&mut self, offset: isize) {
+        if let Some(playback) = self.playback.as_mut() {
+            playback.set_playing(false, now());
+            let position = (playback.position() as isize + offset).max(0) as usize;
+            self.seek_playback(position);
+        }
+    }
+
+    /// Plays or pauses the watched replay.
+    /// Playing at the end starts from the start.
+    pub fn toggle_playback(&mut self) {
+        let playback = match self.playback.as_mut() {
+            Some(playback) => playback,
+            None => return,
+        };
+        let playing = !playback.is_playing();
+        playback.set_playing(playing, now());
+
+        if playing && playback.position() == playback.replay().len() {
+            self.seek_playback(0);
+        }
+    }
+
+    /// Makes the next move of the watched replay if it is playing and it is time to
+    fn update_playback(&mut self) {
+        // Let the agent finish its move first
+        if self.animation.is_some() {
+            return;
+        }
+
+        let playback = match self.playback.as_mut() {
+            Some(playback) => playback,
+            None => return,
+        };
+
+        if playback.is_move_due(now()) {
+            let position = playback.position() + 1;
+            if position >= playback.replay().len() {
+                playback.set_playing(false, now());
+            }
+            self.seek_playback(position);
+        }
+    }
+
+    /// Gets the replay to share along with the contraption, if there is one.
+    /// This is the replay being watched, or otherwise the moves recorded in play mode.
+    pub fn replay_to_share(&self) -> Option<&Replay> {
+        match &self.playback {
+            Some(playback) => Some(playback.replay()),
+            None => self
+                .recording
+                .as_ref()
+                .filter(|recording| !recording.is_empty()),
+        }
+    }
+
+    /// Starts checking whether the selected gadgets simulate a gadget
+    pub fn start_region(&mut self) {
+        if self.selection.is_empty() {
+            return;
+        }
+
+        let grid = self
+            .grid
+            .iter()
+            .filter(|(_, xy, wh)| self.selection.contains(&(*xy, *wh)))
+            .cloned()
+            .collect::<Grid<_>>();
+
+        self.region = Some(Region::new(&grid));
+        self.region_report.clear();
+        self.set_mode(Mode::RegionEdit);
+    }
+
+    /// Marks or unmarks the edge centered at `position` as an external port of the region
+    pub fn toggle_region_port(&mut self, position: Vec2) {
+        if let Some(region) = &mut self.region {
+            let double_xy = vec2(
+                (position.x * 2.0).round() as isize,
+                (position.y * 2.0).round() as isize,
+            );
+
+            if region.toggle_port(double_xy) {
+                self.region_report.clear();
+            }
+        }
+    }
+
+    /// Cycles the state of the region gadget at `xy`
+    pub fn cycle_region_gadget_state(&mut self, xy: grid::XY) {
+        let region = match &self.region {
+            Some(region) => region,
+            None => return,
+        };
+
+        if let Some((gadget, xy, _)) = self.grid.get_mut(xy) {
+            if region.positions().contains(&xy) {
+                let state = gadget.state();
+                gadget.cycle_state();
+
+                let undo_stack = self.undo_stacks[self.undo_stack_index]
+                    .as_mut()
+                    .expect("Tried to get undo stack while undoing/redoing");
+                undo_stack.push(UndoAction::GadgetChangeState {
+                    position: xy,
+                    state,
+                });
+                undo_stack.batch();
+            }
+        }
+    }
+
+    /// Adds the current states of the region's gadgets as the representative
+    /// of the next simulated state
+    pub fn add_region_representative(&mut self) {
+        let grid = &self.grid;
+
+        if let Some(region) = &mut self.region {
+            let states = region
+                .positions()
+                .iter()
+                .map(|xy| {
+                    grid.get(*xy)
+                        .map_or(State(0), |(gadget, _, _)| gadget.state())
+                })
+                .collect();
+
+            let state = region.add_representative(states);
+            log!("Added a representative for state {}", state.id());
+            self.region_report.clear();
+        }
+    }
+
+    pub fn clear_region_representatives(&mut self) {
+        if let Some(region) = &mut self.region {
+            region.clear_representatives();
+            self.region_report.clear();
+        }
+    }
+
+    /// Black-boxes the region, starting from the current states of its gadgets,
+    /// into a new gadget at the end of the gadget selector
+    pub fn compose_region(&mut self) {
+        let grid = &self.grid;
+        let region = match &mut self.region {
+            Some(region) => region,
+            None => return,
+        };
+
+        let states = region
+            .positions()
+            .iter()
+            .map(|xy| {
+                grid.get(*xy)
+                    .map_or(State(0), |(gadget, _, _)| gadget.state())
+            })
+            .collect();
+
+        self.region_report = match region.compose(states, solver::DEFAULT_BUDGET) {
+            Ok(gadget) => {
+                let name = format!("Composite {}", self.gadget_select.len());
+                let report = format!(
+                    "Added {} with {} states and {} ports",
+                    name,
+                    gadget.def().num_states(),
+                    gadget.def().num_ports()
+                );
+                self.palette_push(gadget.name_this(&name));
+                self.undo_stack_mut().push(UndoAction::PaletteAdd);
+                self.undo_stack_mut().batch();
+                report
+            }
+
+            Err(e) => e,
+        };
+    }
+
+    /// Starts building a gadget def by hand,
+    /// copying the gadget being painted if there is one
+    pub fn start_def_edit(&mut self) {
+        if let Some(gadget) = &self.gadget_tile {
+            self.def_editor = DefEditor::from_gadget(gadget);
+        }
+
+        self.def_editor_report.clear();
+        self.set_mode(Mode::DefEdit);
+    }
+
+    /// Adds the gadget being built in the def editor to the end of the gadget selector
+    pub fn add_edited_def(&mut self) {
+        self.def_editor_report = match self.def_editor.build() {
+            Ok(gadget) => {
+                let name = format!("Custom {}", self.gadget_select.len());
+                self.palette_push(gadget.name_this(&name));
+                self.undo_stack_mut().push(UndoAction::PaletteAdd);
+                self.undo_stack_mut().batch();
+                format!("Added {}", name)
+            }
+
+            Err(e) => e,
+        };
+    }
+
+    /// Adds a gadget to the end of the gadget selector
+    fn palette_push(&mut self, gadget: Gadget) {
+        self.gadget_select_properties
+            .push(describe_properties(&gadget));
+        self.gadget_select.push(gadget);
+    }
+
+    /// Removes the gadget at the end of the gadget selector, if there is one
+    fn palette_pop(&mut self) -> Option<Gadget> {
+        self.gadget_select_properties.pop();
+        self.gadget_select.pop()
+    }
+
+    /// Replaces the gadget at some index in the gadget selector, returning the old one
+    fn palette_replace(&mut self, index: usize, gadget: Gadget) -> Gadget {
+        self.gadget_select_properties[index] = describe_properties(&gadget);
+        std::mem::replace(&mut self.gadget_select[index], gadget)
+    }
+
+    /// Adds gadgets to the end of the gadget selector, skipping ones that are already there.
+    /// Returns the number of gadgets added.
+    pub fn merge_library(&mut self, gadgets: Vec<Gadget>) -> usize {
+        let added = library::merge_library(&mut self.gadget_select, gadgets);
+        let new_entries = &self.gadget_select[self.gadget_select.len() - added..];
+        self.gadget_select_properties
+            .extend(new_entries.iter().map(describe_properties));
+
+        if added > 0 {
+            for _ in 0..added {
+                self.undo_stack_mut().push(UndoAction::PaletteAdd);
+            }
+            self.undo_stack_mut().batch();
+        }
+        added
+    }
+
+    /// Replaces the contraption with another one, as 1 undoable action
+    pub fn replace_grid(&mut self, grid: Grid<Gadget>) {
+        let positions = self.grid.iter().map(|(_, xy, _)| *xy).collect::<Vec<_>>();
+        for xy in positions {
+            self.remove_gadget_from_grid(xy);
+        }
+
+        for (gadget, xy, _) in grid.into_iter() {
+            self.add_gadget_to_grid(gadget, xy);
+        }
+        self.undo_stack_mut().batch();
+    }
+
+    /// Handles the file that was picked for import, if it finished loading
+    fn handle_imported_file(&mut self) {
+        match IMPORTED_FILE.with(|imported| imported.borrow_mut().take()) {
+            Some((ImportKind::Library, string)) => self.merge_imported_library(&string),
+            Some((ImportKind::Contraption, string)) => self.load_imported_contraption(&string),
+            Some((ImportKind::Script, string)) => self.run_imported_script(&string),
+            None => {}
+        }
+    }
+
+    /// Replaces the contraption with one read from a RON document or an ASCII drawing
+    fn load_imported_contraption(&mut self, string: &str) {
+        let grid = if string.trim_start().starts_with('(') {
+            document::grid_from_ron(string)
+        } else {
+            ascii::grid_from_ascii(string, &self.gadget_select)
+        };
+
+        self.contraption_report = match grid {
+            Ok(grid) => {
+                self.set_mode(Mode::None);
+                self.replace_grid(grid);
+                String::new()
+            }
+
+            Err(e) => {
+                elog!("Failed to import contraption: {}", e);
+                format!("Failed to import contraption: {}", e)
+            }
+        };
+    }
+
+    /// Runs a test script that was picked for import on a copy of the contraption,
+    /// and reports whether it passed
+    fn run_imported_script(&mut self, string: &str) {
+        self.script_report = match Script::parse(string) {
+            Ok(script) => match script.check(&self.grid) {
+                Ok(_) => format!(
+                    "Script passed: {} steps, {} expectations met",
+                    script.steps().len(),
+                    script.num_expectations()
+                ),
+
+                Err(failure) => {
+                    elog!("Script failed: {}", failure);
+                    format!("Script failed: {}", failure)
+                }
+            },
+
+            Err(e) => {
+                elog!("Failed to read script: {}", e);
+                format!("Failed to read script: {}", e)
+            }
+        };
+    }
+
+    /// Merges a gadget library that was picked for import
+    fn merge_imported_library(&mut self, string: &str) {
+        self.def_editor_report = match library::library_from_ron(string) {
+            Ok(gadgets) => {
+                let added = self.merge_library(gadgets);
+                format!("Imported {} new gadgets", added)
+            }
+
+            Err(e) => {
+                elog!("Failed to import gadget library: {}", e);
+                format!("Failed to import gadget library: {}", e)
+            }
+        };
+    }
+
+    /// Downloads the selected gadgets, or the whole contraption if nothing is selected,
+    /// as an SVG figure
+    pub fn export_svg(&self) {
+        let svg = if self.selection.is_empty() {
+            render::svg::gadgets_to_svg(self.grid.iter())
+        } else {
+            render::svg::gadgets_to_svg(
+                self.selection
+                    .iter()
+                    .filter_map(|(xy, _)| self.grid.get(*xy)),
+            )
+        };
+
+        if let Err(e) = download_text(&svg, "contraption.svg") {
+            elog!("Contraption failed to export as SVG: {:?}", e);
+        }
+    }
+
+    /// Downloads the selected gadgets, or the whole contraption if nothing is selected,
+    /// as a PNG image
+    pub fn export_png(&self) {
+        let grid = self
+            .grid
+            .iter()
+            .filter(|(_, xy, wh)| self.selection.is_empty() || self.selection.contains(&(*xy, *wh)))
+            .cloned()
+            .collect::<Grid<_>>();
+
+        let min_x = grid.iter().map(|(_, xy, _)| xy.x).min().unwrap_or(0);
+        let min_y = grid.iter().map(|(_, xy, _)| xy.y).min().unwrap_or(0);
+        let max_x = grid
+            .iter()
+            .map(|(_, xy, (w, _))| xy.x + *w as isize)
+            .max()
+            .unwrap_or(1);
+        let max_y = grid
+            .iter()
+            .map(|(_, xy, (_, h))| xy.y + *h as isize)
+            .max()
+            .unwrap_or(1);
+
+        let png = render::raster::grid_to_png(
+            &grid,
+            vec2(min_x as f64, min_y as f64),
+            vec2(max_x as f64, max_y as f64),
+            (
+                Self::PNG_CELL_SIZE * (max_x - min_x) as usize,
+                Self::PNG_CELL_SIZE * (max_y - min_y) as usize,
+            ),
+        );
+
+        if let Err(e) = download_data(&png, "image/png", "contraption.png") {
+            elog!("Contraption failed to export as PNG: {:?}", e);
+        }
+    }
+
+    /// Gets the gadgets in the selector that the user made or imported, rather than the presets
+    pub fn custom_gadgets(&self) -> Vec<Gadget> {
+        library::custom_gadgets(&self.gadget_select, &self.presets)
+    }
+
+    /// Saves the grid in the URL and the custom gadgets in local storage
+    pub fn save(&self) {
+        save_grid_in_url(&self.grid, &self.custom_gadgets(), self.replay_to_share());
+        save_library_in_storage(&self.custom_gadgets());
+    }
+
+    /// Checks whether the region simulates the gadget at `index` in the gadget selector
+    pub fn check_region(&mut self, index: usize) {
+        let region = match &mut self.region {
+            Some(region) => region,
+            None => return,
+        };
+        let gadget = &self.gadget_select[index];
+
+        self.region_report = match region.check(gadget.def(), solver::DEFAULT_BUDGET) {
+            Ok(comparison) => {
+                let mut report = if comparison.is_match() {
+                    format!("The region simulates {}", gadget.name())
+                } else {
+                    format!("The region does not simulate {}", gadget.name())
+                };
+
+                if !comparison.complete {
+                    report += "\n(Gave up before visiting everything)";
+                }
+
+                for (name, traversals) in &[
+                    ("Missing", &comparison.missing),
+                    ("Extra", &comparison.extra),
+                ] {
+                    if !traversals.is_empty() {
+                        report += &format!("\n{}:", name);
+                    }
+
+                    for ((s0, p0), (s1, p1)) in traversals.iter().take(8) {
+                        report += &format!(
+                            "\n    ({}, {}) -> ({}, {})",
+                            s0.id(),
+                            p0.id(),
+                            s1.id(),
+                            p1.id()
+                        );
+                    }
+                }
+
+                if !comparison.unrecognized.is_empty() {
+                    report += "\nEnds in no representative:";
+                }
+
+                for ((s0, p0), p1, _) in comparison.unrecognized.iter().take(8) {
+                    report += &format!("\n    ({}, {}) -> (?, {})", s0.id(), p0.id(), p1.id());
+                }
+
+                report
+            }
+
+            Err(e) => e,
+        };
+    }
+
+    pub fn pan(&mut self, xy: Vec2) {
+        self.center += xy;
+    }
+
+    pub fn zoom(&mut self, center: Vec2, amount: f64, ui: &Ui) {
+        let prev_height = self.height;
+        self.height += amount;
+        self.clamp_height(&ui);
+        self.center = center + (self.center - center) * self.height / prev_height;
+    }
+
+    pub fn cut(&mut self, center: bool) {
+        if self.selection.len() > 0 {
+            self.paste = self.copy_selected_gadgets(center);
+            self.remove_selected_gadgets();
+            self.set_mode(Mode::GadgetPaste);
+        }
+    }
+
+    pub fn copy(&mut self, center: bool) {
+        if self.selection.len() > 0 {
+            self.paste = self.copy_selected_gadgets(center);
+            self.set_mode(Mode::GadgetPaste);
+        }
+    }
+
+    pub fn paste(&mut self) {
+        if self.mode != Mode::GadgetMove && !self.paste.is_empty() {
+            self.set_mode(Mode::GadgetPaste);
+        }
+    }
+
+    pub fn update(&mut self, ui: &mut Ui) {
+        self.clamp_height(ui);
+
+        self.handle_imported_file();
+        self.update_animation();
+        self.update_playback();
+        self.update_ui(ui);
+
+        self.camera.set_orthographic_projection(
+            ui.win_w / ui.win_h * self.height,
+            self.height,
+            1.0,
+        );
+
+        let cx = self.center.x;
+        let cy = self.center.y;
+        self.camera
+            .set_view(vec3(cx, cy, 0.0), vec3(cx, cy, -1.0), vec3(0.0, 1.0, 0.0));
+    }
+
+    pub fn render(&mut self, ui: &mut Ui, width: f64, height: f64) {
+        self.gl.clear();
+
+        self.selection_renderer.render(
+            self.selection.iter().copied(),
+            &self.camera,
+            vec2(0, 0),
+            SelectionRenderer::Z,
+        );
+
+        render::render_grid(
+            &self.grid,
+            &self.camera,
+            &mut self.gadget_renderer,
+            vec2(0, 0),
+            0.0,
+            true,
+        );
+
+        if self.mode == Mode::GadgetMove {
+            render::render_grid(
+                &self.moving,
+                &self.camera,
+                &mut self.paste_renderer,
+                self.int_mouse_position,
+                -0.25,
+                false,
+            );
+
+            self.selection_renderer.render(
+                self.moving.iter().map(|(_, xy, wh)| (*xy, *wh)),
+                &self.camera,
+                self.int_mouse_position,
+                SelectionRenderer::Z - 0.1,
+            );
+        }
+
+        if self.mode == Mode::GadgetPaste {
+            render::render_grid(
+                &self.paste,
+                &self.camera,
+                &mut self.paste_renderer,
+                self.int_mouse_position,
+                -0.25,
+                false,
+            );
+        }
+
+        if let Some(gadget) = &self.gadget_tile {
+            gadget
+                .renderer()
+                .model(&self.gl)
+                .prepare_render()
+                .render_position(
+                    vec3(
+                        self.int_mouse_position.x as f64,
+                        self.int_mouse_position.y as f64,
+                        -0.25,
+                    ),
+                    &self.camera,
+                );
+        }
+
+        if let Some(choice) = &self.traversal_choice {
+            let xy = choice.xy();
+
+            // Preview the state the gadget would end up in
+            choice
+                .preview()
+                .renderer()
+                .model(&self.gl)
+                .prepare_render()
+                .render_position(vec3(xy.x as f64, xy.y as f64, -0.25), &self.camera);
+
+            let port_positions = choice.preview().port_positions();
+            let selected = choice.target().1;
+            let ports = choice
+                .targets()
+                .iter()
+                .map(|(_, port)| *port)
+                .dedup()
+                .map(|port| (port_positions[port.id()], port == selected));
+
+            self.port_highlight_renderer
+                .render(ports, &self.camera, xy, PortHighlightRenderer::Z);
+        }
+
+        if let Some(region) = &self.region {
+            let ports = region.ports();
+
+            self.region_renderer.render(
+                region.boundary_edges().into_iter().map(|edge| {
+                    (
+                        edge.double_xy.cast::<f64>().unwrap() * 0.5,
+                        ports.contains(&edge),
+                    )
+                }),
+                &self.camera,
+                vec2(0, 0),
+                PortHighlightRenderer::Z,
+            );
+        }
+
+        if let Some(target) = self.solve_target {
+            self.solve_target_renderer.render(
+                Some((target, true)),
+                &self.camera,
+                vec2(0, 0),
+                PortHighlightRenderer::Z,
+            );
+        }
+
+        if self.mode == Mode::Play {
+            self.trail_renderer
+                .render(&self.trail, &self.grid, self.trail_heatmap, &self.camera);
+        }
+
+        // Show what each input would do, once the agent is done moving
+        if self.mode == Mode::Play
+            && self.animation.is_none()
+            && self.traversal_choice.is_none()
+            && !self.playback.as_ref().is_some_and(|p| p.is_playing())
+        {
+            if let Some(agent) = self.agents.get(self.active_agent) {
+                // Shift makes inputs move the agent backward
+                let back = self.modifiers.shift();
+                self.move_hint_renderer.render(
+                    agent,
+                    &input_previews(&self.grid, agent, back),
+                    &self.grid,
+                    &self.camera,
+                );
+            }
+        }
+
+        let time = now();
+        for (i, agent) in self.agents.iter().enumerate() {
+            match &self.animation {
+                Some(animation) if animation.agent() == i && !animation.is_finished(time) => {
+                    animation.render(&self.gl, &self.camera, self.agent_color(i), time)
+                }
+                _ => agent.render(&self.camera, self.agent_color(i)),
+            }
+        }
+
+        self.render_ui(ui, width, height);
+    }
+
+    pub fn handle_input(&mut self, event: &Event<()>) {
+        match event {
+            //Event::WindowEvent {
+            //    event:
+            //        WindowEvent::MouseWheel {
+            //            delta: MouseScrollDelta::PixelDelta(LogicalPosition { y: delta, .. }),
+            //            ..
+            //        },
+            //    ..
+            //} => {
+            //    self.height = self.height + *delta / 64.0
+            //}
+
+            //TODO: Implement the event in winit for the web
+            //Event::WindowEvent {
+            //    event: WindowEvent::ModifiersChanged(state),
+            //    ..
+            //} => {
+            //    self.modifiers = *state;
+            //    log!("Modifiers: {:?}", self.modifiers);
+            //}
+            // Until WindowEvent::ModifiersChanged gets implemeneted for web
+            #[allow(deprecated)]
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                virtual_keycode: Some(keycode),
+                                state,
+                                modifiers,
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => {
+                self.modifiers = *modifiers;
+
+                if modifiers.ctrl() {
+                    if let ElementState::Pressed = state {
+                        match keycode {
+                            VirtualKeyCode::Z => {
+                                self.undo();
+                            }
+
+                            VirtualKeyCode::Y => {
+                                self.redo();
+                            }
+
+                            VirtualKeyCode::X => {
+                                self.cut(false);
+                            }
+
+                            VirtualKeyCode::C => {
+                                self.copy(false);
+                            }
+
+                            VirtualKeyCode::V => {
+                                self.paste();
+                            }
+
+                            VirtualKeyCode::S if modifiers.shift() => {
+                                crate::export_contraption(&self.grid);
+                            }
+
+                            VirtualKeyCode::S => {
+                                self.save();
+                            }
+
+                            VirtualKeyCode::E if modifiers.shift() => {
+                                self.export_svg();
+                            }
+
+                            VirtualKeyCode::E => {
+                                crate::export_library(&self.custom_gadgets());
+                            }
+
+                            VirtualKeyCode::G if modifiers.shift() => {
+                                self.export_png();
+                            }
+
+                            VirtualKeyCode::O if modifiers.shift() => {
+                                crate::import_contraption();
+                            }
+
+                            VirtualKeyCode::O => {
+                                crate::import_library();
+                            }
+
+                            VirtualKeyCode::A if modifiers.shift() => {
+                                crate::export_ascii(&self.grid);
+                            }
+
+                            VirtualKeyCode::A => {
+                                if self.mode != Mode::GadgetMove {
+                                    self.set_mode(Mode::Select);
+                                    self.selection
+                                        .extend(self.grid.iter().map(|(_, xy, wh)| (*xy, *wh)));
+                                }
+                            }
+
+                            _ => {}
+                        }
+                    }
+                } else {
+                    if let ElementState::Pressed = state {
+                        match keycode {
+                            VirtualKeyCode::R | VirtualKeyCode::T => {
+                                let num_turns = if *keycode == VirtualKeyCode::R { 1 } else { -1 };
+                                self.rotate_active(num_turns);
+                            }
+
+                            VirtualKeyCode::X => {
+                                self.flip_x_active();
+                            }
+
+                            VirtualKeyCode::Y => {
+                                self.flip_y_active();
+                            }
+
+                            VirtualKeyCode::U => {
+                                self.twist_active();
+                            }
+
+                            VirtualKeyCode::C => {
+                                self.cycle_state_active();
+                            }
+
+                            VirtualKeyCode::O => {
+                                self.rotate_ports_active(1);
+                            }
 
                             VirtualKeyCode::P => {
                                 self.rotate_ports_active(-1);
@@ -825,8 +2130,7 @@ impl<'a> App<'a> {
                             }
 
                             VirtualKeyCode::H => {
-                                self.set_mode(Mode::AgentPlace);
-                                self.agent = Some(Agent::new(vec2(0.5, 0.0), vec2(0, 1)));
+                                self.place_agent();
                             }
 
                             VirtualKeyCode::J => {
@@ -838,8 +2142,42 @@ impl<'a> App<'a> {
                                 self.undo_stack_mut().batch();
                             }
 
+                            VirtualKeyCode::K => {
+                                if self.mode == Mode::Select {
+                                    self.start_region();
+                                }
+                            }
+
+                            VirtualKeyCode::M => {
+                                if self.mode == Mode::RegionEdit {
+                                    self.add_region_representative();
+                                }
+                            }
+
+                            VirtualKeyCode::B => {
+                                if self.mode == Mode::RegionEdit {
+                                    self.compose_region();
+                                }
+                            }
+
+                            VirtualKeyCode::L => {
+                                if self.mode != Mode::Play && self.mode != Mode::DefEdit {
+                                    self.start_def_edit();
+                                }
+                            }
+
+                            VirtualKeyCode::I => {
+                                if self.mode == Mode::TilePaint {
+                                    self.minimize_tile_def();
+                                }
+                            }
+
                             VirtualKeyCode::Escape => {
-                                if self.mode == Mode::GadgetPaste || self.mode == Mode::TilePaint {
+                                if self.mode == Mode::GadgetPaste
+                                    || self.mode == Mode::TilePaint
+                                    || self.mode == Mode::RegionEdit
+                                    || self.mode == Mode::DefEdit
+                                {
                                     self.set_mode(Mode::Select);
                                 }
                             }
@@ -849,7 +2187,10 @@ impl<'a> App<'a> {
                     }
 
                     if self.mode == Mode::Play {
-                        if *state == ElementState::Pressed && modifiers.is_empty() {
+                        // Shift + the opposite direction moves the agent backward
+                        if *state == ElementState::Pressed
+                            && (modifiers.is_empty() || *modifiers == ModifiersState::SHIFT)
+                        {
                             let dir = match keycode {
                                 VirtualKeyCode::W | VirtualKeyCode::Up => Some(vec2(0, 1)),
                                 VirtualKeyCode::A | VirtualKeyCode::Left => Some(vec2(-1, 0)),
@@ -859,33 +2200,58 @@ impl<'a> App<'a> {
                             };
 
                             if let Some(dir) = dir {
-                                let agent = self.agent.as_mut().unwrap();
-                                // Borrowing rules require that self.undo_stack is obtained directly
-                                let undo_stack = self.undo_stacks[self.undo_stack_index]
-                                    .as_mut()
-                                    .expect("Tried to get undo stack while undoing/redoing");
-                                let prev_position = agent.position();
-                                let prev_direction = agent.direction();
-
-                                let result = agent.advance(&mut self.grid, dir);
-
-                                if agent.position() != prev_position
-                                    || agent.direction() != prev_direction
-                                {
-                                    undo_stack.push(UndoAction::AgentMove {
-                                        position: prev_position,
-                                        direction: prev_direction,
-                                    })
-                                }
+                                self.advance_agent(dir, modifiers.shift());
+                            }
 
-                                if let Some((_, xy, state)) = result {
-                                    undo_stack.push(UndoAction::GadgetChangeState {
-                                        position: xy,
-                                        state,
-                                    });
-                                }
+                            if *keycode == VirtualKeyCode::N {
+                                self.step_solution();
+                            }
 
-                                undo_stack.batch();
+                            if *keycode == VirtualKeyCode::E {
+                                self.explore();
+                            }
+
+                            if *keycode == VirtualKeyCode::Q {
+                                self.cycle_active_agent(if modifiers.shift() { -1 } else { 1 });
+                            }
+
+                            if let Some(choice) = self.traversal_choice.as_mut() {
+                                let number = match keycode {
+                                    VirtualKeyCode::Key1 => Some(0),
+                                    VirtualKeyCode::Key2 => Some(1),
+                                    VirtualKeyCode::Key3 => Some(2),
+                                    VirtualKeyCode::Key4 => Some(3),
+                                    VirtualKeyCode::Key5 => Some(4),
+                                    VirtualKeyCode::Key6 => Some(5),
+                                    VirtualKeyCode::Key7 => Some(6),
+                                    VirtualKeyCode::Key8 => Some(7),
+                                    VirtualKeyCode::Key9 => Some(8),
+                                    _ => None,
+                                };
+
+                                match keycode {
+                                    VirtualKeyCode::Tab => {
+                                        let index = (choice.index() + 1) % choice.targets().len();
+                                        choice.set_index(index);
+                                    }
+
+                                    VirtualKeyCode::Return | VirtualKeyCode::Space => {
+                                        self.confirm_traversal_choice();
+                                    }
+
+                                    VirtualKeyCode::Escape => {
+                                        self.traversal_choice = None;
+                                    }
+
+                                    _ => {
+                                        if let Some(number) = number {
+                                            if number < choice.targets().len() {
+                                                choice.set_index(number);
+                                                self.confirm_traversal_choice();
+                                            }
+                                        }
+                                    }
+                                }
                             }
                         }
                     }
@@ -896,21 +2262,39 @@ impl<'a> App<'a> {
     }
 }
 
-/// Attempts to save the grid as part of the URL's hash map, and returs whether it saved
-pub fn save_grid_in_url(grid: &Grid<Gadget>) -> bool {
-    let (base64, padding) = bit_serde::to_base64(grid)
+/// Attempts to save the grid as part of the URL's hash map, and returs whether it saved.
+/// The custom palette entries are saved after the grid,
+/// so whoever opens the URL can paint with them too.
+/// A replay, if there is one, is saved after those, so they can watch it.
+pub fn save_grid_in_url(grid: &Grid<Gadget>, custom: &[Gadget], replay: Option<&Replay>) -> bool {
+    let string = bit_serde::to_versioned_base64(grid)
         .map_err(|e| {
             elog!("Grid failed to save: {}", e);
             e
         })
-        .unwrap_or_else(|_e| (String::new(), 0));
+        .unwrap_or_else(|_e| String::new());
 
-    if base64.is_empty() {
+    if string.is_empty() {
         window().location().set_hash("").unwrap();
         return false;
     }
 
-    let string = format!("{}{}", base64, padding);
+    let palette = if custom.is_empty() {
+        None
+    } else {
+        bit_serde::to_versioned_base64(&GadgetLibrarySerde::new(custom))
+            .map_err(|e| elog!("Palette failed to save: {}", e))
+            .ok()
+    };
+
+    let replay = replay.and_then(|replay| {
+        bit_serde::to_versioned_base64(replay)
+            .map_err(|e| elog!("Replay failed to save: {}", e))
+            .ok()
+    });
+
+    let string = share::url_hash_from_segments(&string, palette.as_deref(), replay.as_deref());
+
     window().location().set_hash(&string).map_or_else(
         |e| {
             elog!("Grid failed to save: {:?}", e);
@@ -922,36 +2306,273 @@ pub fn save_grid_in_url(grid: &Grid<Gadget>) -> bool {
 
 pub fn load_grid_from_url() -> Option<Grid<Gadget>> {
     // Avoid panicking here
-    let mut string = window().location().hash().ok()?;
+    let string = window().location().hash().ok()?;
 
     if string.len() == 0 {
         return None;
     }
 
-    string = string.replace("#", "");
-
-    let padding = string
-        .pop()
-        .or_else(|| {
-            elog!("Failed to load grid: URL hash is empty");
-            None
-        })?
-        .to_string()
-        .parse()
+    share::grid_from_url(&string)
         .or_else(|e| {
             elog!("Failed to load grid: {}", e);
             Err(e)
         })
-        .ok()?;
+        .ok()
+}
+
+/// Loads the palette entries saved after the grid in the URL's hash, if there are any
+pub fn load_palette_from_url() -> Option<Vec<Gadget>> {
+    // Avoid panicking here
+    let string = window().location().hash().ok()?;
+
+    share::palette_from_url(&string)?
+        .or_else(|e| {
+            elog!("Failed to load palette: {}", e);
+            Err(e)
+        })
+        .ok()
+}
+
+/// Loads the replay saved after the palette in the URL's hash, if there is one
+pub fn load_replay_from_url() -> Option<Replay> {
+    // Avoid panicking here
+    let string = window().location().hash().ok()?;
 
-    bit_serde::from_base64(&string, padding)
+    share::replay_from_url(&string)?
         .or_else(|e| {
-            elog!("Failed to load grid: {}", e);
+            elog!("Failed to load replay: {}", e);
+            Err(e)
+        })
+        .ok()
+}
+
+/// Key of the gadget library in the browser's local storage
+const LIBRARY_STORAGE_KEY: &str = "gadget-up-2-library";
+
+/// What a file picked for import contains
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ImportKind {
+    Library,
+    Contraption,
+    Script,
+}
+
+thread_local! {
+    /// Contents of the file picked for import, waiting to be handled
+    static IMPORTED_FILE: RefCell<Option<(ImportKind, String)>> = RefCell::new(None);
+}
+
+/// Attempts to save the gadget library in local storage, and returns whether it saved
+pub fn save_library_in_storage(gadgets: &[Gadget]) -> bool {
+    let string = match library::library_to_ron(gadgets) {
+        Ok(string) => string,
+        Err(e) => {
+            elog!("Gadget library failed to save: {}", e);
+            return false;
+        }
+    };
+
+    let storage = match window().local_storage() {
+        Ok(Some(storage)) => storage,
+        _ => {
+            elog!("Gadget library failed to save: local storage is unavailable");
+            return false;
+        }
+    };
+
+    storage.set_item(LIBRARY_STORAGE_KEY, &string).map_or_else(
+        |e| {
+            elog!("Gadget library failed to save: {:?}", e);
+            false
+        },
+        |()| true,
+    )
+}
+
+pub fn load_library_from_storage() -> Option<Vec<Gadget>> {
+    // Avoid panicking here
+    let string = window()
+        .local_storage()
+        .ok()??
+        .get_item(LIBRARY_STORAGE_KEY)
+        .ok()??;
+
+    library::library_from_ron(&string)
+        .or_else(|e| {
+            elog!("Failed to load gadget library: {}", e);
             Err(e)
         })
         .ok()
 }
 
+/// Downloads text as a file
+fn download_text(string: &str, file_name: &str) -> Result<(), JsValue> {
+    download_data(string.as_bytes(), "text/plain;charset=utf-8", file_name)
+}
+
+/// Downloads data with a MIME type as a file
+fn download_data(data: &[u8], mime_type: &str, file_name: &str) -> Result<(), JsValue> {
+    use percent_encoding::{percent_encode, NON_ALPHANUMERIC};
+    use wasm_bindgen::JsCast;
+
+    let href = format!(
+        "data:{},{}",
+        mime_type,
+        percent_encode(data, NON_ALPHANUMERIC)
+    );
+
+    let anchor = window()
+        .document()
+        .ok_or("No document")?
+        .create_element("a")?
+        .dyn_into::<web_sys::HtmlAnchorElement>()?;
+
+    anchor.set_href(&href);
+    anchor.set_download(file_name);
+    anchor.click();
+    Ok(())
+}
+
+/// Attempts to download the gadget library as a RON file, and returns whether it did
+pub fn export_library(gadgets: &[Gadget]) -> bool {
+    let string = match library::library_to_ron(gadgets) {
+        Ok(string) => string,
+        Err(e) => {
+            elog!("Gadget library failed to export: {}", e);
+            return false;
+        }
+    };
+
+    download_text(&string, "gadget-library.ron").map_or_else(
+        |e| {
+            elog!("Gadget library failed to export: {:?}", e);
+            false
+        },
+        |()| true,
+    )
+}
+
+/// Attempts to download the contraption as a readable RON file, and returns whether it did
+pub fn export_contraption(grid: &Grid<Gadget>) -> bool {
+    let string = match document::grid_to_ron(grid) {
+        Ok(string) => string,
+        Err(e) => {
+            elog!("Contraption failed to export: {}", e);
+            return false;
+        }
+    };
+
+    download_text(&string, "contraption.ron").map_or_else(
+        |e| {
+            elog!("Contraption failed to export: {:?}", e);
+            false
+        },
+        |()| true,
+    )
+}
+
+/// Attempts to download the contraption as an ASCII drawing, and returns whether it did
+pub fn export_ascii(grid: &Grid<Gadget>) -> bool {
+    let string = match ascii::grid_to_ascii(grid) {
+        Ok(string) => string,
+        Err(e) => {
+            elog!("Contraption failed to export: {}", e);
+            return false;
+        }
+    };
+
+    download_text(&string, "contraption.txt").map_or_else(
+        |e| {
+            elog!("Contraption failed to export: {:?}", e);
+            false
+        },
+        |()| true,
+    )
+}
+
+/// Asks for a text file to import.
+/// The file is read in the background, and handled on a later update.
+fn import_file(kind: ImportKind) {
+    use wasm_bindgen::JsCast;
+
+    let result = (|| -> Result<(), JsValue> {
+        let input = window()
+            .document()
+            .ok_or("No document")?
+            .create_element("input")?
+            .dyn_into::<web_sys::HtmlInputElement>()?;
+
+        input.set_type("file");
+        input.set_accept(match kind {
+            ImportKind::Library => ".ron",
+            ImportKind::Contraption => ".ron,.txt",
+            ImportKind::Script => ".txt",
+        });
+
+        let input_ref = input.clone();
+        let on_change = Closure::wrap(Box::new(move || {
+            let file = match input_ref.files().and_then(|files| files.get(0)) {
+                Some(file) => file,
+                None => return,
+            };
+
+            let reader = match web_sys::FileReader::new() {
+                Ok(reader) => reader,
+                Err(e) => {
+                    elog!("Failed to import file: {:?}", e);
+                    return;
+                }
+            };
+
+            let reader_ref = reader.clone();
+            let on_load = Closure::wrap(Box::new(move || {
+                match reader_ref
+                    .result()
+                    .ok()
+                    .and_then(|result| result.as_string())
+                {
+                    Some(string) => {
+                        IMPORTED_FILE.with(|imported| *imported.borrow_mut() = Some((kind, string)))
+                    }
+                    None => elog!("Failed to import file: file is not text"),
+                }
+            }) as Box<dyn FnMut()>);
+
+            reader.set_onload(Some(on_load.as_ref().unchecked_ref()));
+            on_load.forget();
+
+            if let Err(e) = reader.read_as_text(&file) {
+                elog!("Failed to import file: {:?}", e);
+            }
+        }) as Box<dyn FnMut()>);
+
+        input.set_onchange(Some(on_change.as_ref().unchecked_ref()));
+        on_change.forget();
+
+        input.click();
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        elog!("Failed to import file: {:?}", e);
+    }
+}
+
+/// Asks for a RON library file to merge into the gadget selector
+pub fn import_library() {
+    import_file(ImportKind::Library);
+}
+
+/// Asks for a readable RON contraption file or an ASCII drawing to replace the contraption with
+pub fn import_contraption() {
+    import_file(ImportKind::Contraption);
+}
+
+/// Asks for a test script to run on the contraption
+pub fn import_script() {
+    import_file(ImportKind::Script);
+}
+
 // This is like the `main` function, except for JavaScript.
 #[wasm_bindgen(start)]
 pub fn main_js() -> Result<(), JsValue> {
@@ -1089,6 +2710,39 @@ fn window() -> web_sys::Window {
     web_sys::window().expect("No window!")
 }
 
+/// Gets what each input would do to an agent, without doing it,
+/// with a preview for each traversal the input could take.
+/// `back` is whether the inputs move the agent backward.
+fn input_previews(grid: &Grid<Gadget>, agent: &Agent, back: bool) -> Vec<(Vec2i, AdvancePreview)> {
+    solver::DIRECTIONS
+        .iter()
+        .flat_map(|&input| {
+            let num_choices = agent
+                .traversals(grid, input, back)
+                .map_or(1, |(_, sps)| sps.len().max(1));
+
+            (0..num_choices)
+                .map(move |choice| (input, agent.preview_advance(grid, input, back, choice)))
+        })
+        .collect()
+}
+
+/// Gets the gadget that an agent would go through with a step, and its minimal xy coords
+fn gadget_in_front(grid: &Grid<Gadget>, agent: &Agent, step: &Step) -> Option<(grid::XY, Gadget)> {
+    let (xy, _) = agent.traversals(grid, step.input, step.back)?;
+    Some((xy, grid.get(xy)?.0.clone()))
+}
+
+/// Describes the properties of a gadget for its tooltip in the gadget selector
+fn describe_properties(gadget: &Gadget) -> String {
+    analysis::Properties::of(gadget.def()).describe()
+}
+
+/// Milliseconds since the page loaded
+fn now() -> f64 {
+    window().performance().expect("No performance!").now()
+}
+
 #[allow(dead_code)]
 fn fake_panic() {
     panic!("This is fake")
@@ -1,230 +1,35 @@
-use super::lang::{self, Grl};
-use super::{Camera, ShaderType, TrianglesEx, TrianglesType, SHADERS, TRIANGLESES};
+use super::lang;
+use super::{Camera, GadgetEx, GadgetRenderInfo, ShaderType, TrianglesEx, SHADERS};
 use super::{Model, ModelType, Triangles, Vertex, MODELS};
-use crate::gadget::{Agent, Gadget, PP};
-use crate::grid::{WH, XY};
+use crate::gadget::{AdvancePreview, Agent, Gadget, PP};
+use crate::grid::{Grid, WH, XY};
 
 use crate::math::{Mat4, Vec2, Vec2i, Vector2Ex};
 use crate::shape::{Circle, Path, Shape};
+use crate::trail::{Trail, TrailStep};
 
-use cgmath::{vec2, vec3, vec4};
-use fnv::FnvHashMap;
+use cgmath::{vec2, vec3, vec4, Vector4};
+use itertools::Itertools;
 
 use golem::UniformValue;
 use golem::{Context, ShaderProgram};
 use golem::{ElementBuffer, GeometryMode, VertexBuffer};
 
 use ref_thread_local::RefThreadLocal;
-use std::cell::{Ref, RefCell};
+use std::cell::Ref;
 use std::rc::Rc;
 
-pub struct GadgetRenderInfo {
-    grl: Rc<Grl>,
-    grl_set: bool,
-    triangles: Triangles,
-    //paths: FnvHashMap<PP, Path>,
-    /// Cached model
-    model: RefCell<Option<Model>>,
+/// Drawing gadgets with the graphics backend, which `gadget_core` knows nothing about
+pub trait GadgetModelEx {
+    /// Gets the model of the triangles, which is made again if the gadget changed
+    fn model(&self, gl: &Context) -> Ref<'_, Model>;
 }
 
-impl GadgetRenderInfo {
-    pub const RECTANGLE_Z: f64 = -0.001;
-    pub const OUTLINE_Z: f64 = -0.002;
-    pub const PATH_Z: f64 = -0.003;
-    pub const PORT_Z: f64 = -0.004;
-
-    pub fn triangles(&self) -> &Triangles {
-        &self.triangles
-    }
-
-    /// Returns the model for this gadget, if it changed
-    pub fn model(&self, gl: &Context) -> Ref<Model> {
-        {
-            let mut model = self.model.borrow_mut();
-
-            if model.is_none() {
-                *model = Some(Model::new(
-                    gl,
-                    &SHADERS.borrow()[&ShaderType::Basic],
-                    &self.triangles,
-                ));
-            }
-        }
-        Ref::map(self.model.borrow(), |m| m.as_ref().unwrap())
-    }
-
-    pub(crate) fn new() -> Self {
-        Self {
-            triangles: Triangles::new(vec![], vec![]),
-            //paths: FnvHashMap::default(),
-            model: RefCell::new(None),
-            grl: Rc::new(Grl::default()),
-            grl_set: false,
-        }
-    }
-
-    fn has_outline(&self, gadget: &Gadget) -> bool {
-        gadget.def().num_states() > 1
-    }
-
-    /// Gets the path a robot takes to go from p0 to p1
-    //fn port_path(ports: PP, port_positions: &Vec<Vec2>) -> Path {
-    //    let positions: [Vec2; 2] = [port_positions[ports.0.id()], port_positions[ports.1.id()]];
-    //    let mut bezier = [vec2(0.0, 0.0), vec2(0.0, 0.0)];
-
-    //    let offset = 0.25;
-
-    //    for (pos, bez) in positions.iter().zip(bezier.iter_mut()) {
-    //        *bez = pos
-    //            + if pos.x.floor() == pos.x {
-    //                // on vertical edge
-    //                if pos.x == 0.0 {
-    //                    // on left edge
-    //                    vec2(offset, 0.0)
-    //                } else {
-    //                    // on right edge
-    //                    vec2(-offset, 0.0)
-    //                }
-    //            } else {
-    //                // on horizontal edge
-    //                if pos.y == 0.0 {
-    //                    // on bottom edge
-    //                    vec2(0.0, offset)
-    //                } else {
-    //                    // on top edge
-    //                    vec2(0.0, -offset)
-    //                }
-    //            }
-    //    }
-
-    //    // Same-port traversal; make it look like a loop
-    //    if bezier[0] == bezier[1] {
-    //        let dv = (bezier[0] - positions[0]).right_ccw();
-
-    //        bezier[0] += dv;
-    //        bezier[1] -= dv;
-    //    }
-
-    //    Path::from_bezier3(
-    //        [positions[0], bezier[0], bezier[1], positions[1]],
-    //        GadgetRenderInfo::PATH_Z,
-    //        0.05,
-    //    )
-    //}
-
-    /// Updates the rendering information so
-    /// that it is correct when rendering
-    pub(crate) fn update(&mut self, gadget: &Gadget) {
-        self.triangles.clear();
-        //self.paths.clear();
-        *self.model.borrow_mut() = None;
-
-        // Surrounding rectangle
-        self.triangles.append({
-            let mut triangles = (*TRIANGLESES.borrow()[&TrianglesType::GadgetRectangle]).clone();
-
-            for v in triangles.vertices_mut() {
-                v.position.x *= gadget.size().0 as f32;
-                v.position.y *= gadget.size().1 as f32;
-            }
-
-            triangles
-        });
-
-        // Port circles
-        let port_positions = gadget.port_positions();
-        for vec in port_positions.iter() {
-            self.triangles.append(
-                Circle::new(vec.x, vec.y, GadgetRenderInfo::PORT_Z, 0.05)
-                    .triangles(vec4(0.0, 0.0, 0.75, 1.0)),
-            );
-        }
-
-        // Outline
-        if self.has_outline(gadget) {
-            let path = Path::new(
-                vec![
-                    vec2(0.0, 0.0),
-                    vec2(0.0, gadget.size().1 as f64),
-                    vec2(gadget.size().0 as f64, gadget.size().1 as f64),
-                    vec2(gadget.size().0 as f64, 0.0),
-                ],
-                GadgetRenderInfo::OUTLINE_Z,
-                0.05,
-                true,
-            );
-
-            self.triangles
-                .append(path.triangles(vec4(0.0, 0.0, 0.0, 1.0)));
-        }
-
-        // Paths
-        if !self.grl_set {
-            self.grl_set = true;
-            self.grl = lang::get_grl(gadget.def());
-        }
-
-        let tris = self.grl.triangles(gadget);
-        self.triangles.append(tris);
-        //for ports in gadget.def().port_traversals_in_state(gadget.state()) {
-        //    let path = GadgetRenderInfo::port_path(ports, &port_positions);
-
-        //    self.paths.insert(ports, path);
-        //}
-
-        //for ((p0, p1), path) in &self.paths {
-        //    let directed = self.paths.get(&(*p1, *p0)).is_none();
-
-        //    // No redundant path drawing!
-        //    if p0 <= p1 || directed {
-        //        self.triangles
-        //            .append(path.triangles(vec4(0.0, 0.0, 0.0, 1.0)));
-        //    }
-
-        //    if directed {
-        //        let dir = path.end_direction();
-        //        let end: Vec2 = port_positions[p1.id()];
-
-        //        let v0: Vec2 = end + dir * -0.2 + dir.right_ccw() * -0.1;
-        //        let v2: Vec2 = end + dir * -0.2 + dir.right_ccw() * 0.1;
-
-        //        self.triangles.append(Triangles::new(
-        //            vec![
-        //                Vertex::new(
-        //                    vec3(v0.x as f32, v0.y as f32, GadgetRenderInfo::PATH_Z as f32),
-        //                    vec3(0.0, 0.0, 0.0),
-        //                    vec4(0.0, 0.0, 0.0, 1.0),
-        //                    [],
-        //                ),
-        //                Vertex::new(
-        //                    vec3(end.x as f32, end.y as f32, GadgetRenderInfo::PATH_Z as f32),
-        //                    vec3(0.0, 0.0, 0.0),
-        //                    vec4(0.0, 0.0, 0.0, 1.0),
-        //                    [],
-        //                ),
-        //                Vertex::new(
-        //                    vec3(v2.x as f32, v2.y as f32, GadgetRenderInfo::PATH_Z as f32),
-        //                    vec3(0.0, 0.0, 0.0),
-        //                    vec4(0.0, 0.0, 0.0, 1.0),
-        //                    [],
-        //                ),
-        //            ],
-        //            vec![0, 1, 2],
-        //        ));
-        //    }
-        //}
-    }
-}
-
-impl Clone for GadgetRenderInfo {
-    fn clone(&self) -> Self {
-        Self {
-            grl: Rc::clone(&self.grl),
-            grl_set: self.grl_set,
-            triangles: self.triangles.clone(),
-            //paths: self.paths.clone(),
-            model: RefCell::new(None),
-        }
+impl GadgetModelEx for GadgetRenderInfo {
+    fn model(&self, gl: &Context) -> Ref<'_, Model> {
+        self.cached_model(|triangles| {
+            Model::new(gl, &SHADERS.borrow()[&ShaderType::Basic], triangles)
+        })
     }
 }
 
@@ -382,18 +187,429 @@ impl SelectionRenderer {
     }
 }
 
-impl Agent {
-    pub fn render(&self, camera: &Camera) {
-        let dir = self.direction().cast::<f64>().unwrap();
+/// Renders highlights on ports in the contraption,
+/// such as the exits the agent can choose between
+pub struct PortHighlightRenderer {
+    gl: Rc<Context>,
+    /// Highlights the cached model was made from
+    ports: Vec<(Vec2, bool)>,
+    model: Option<Model>,
+}
 
-        let transform = Mat4::from_cols(
-            -dir.right_ccw().extend(0.0).extend(0.0),
-            dir.extend(0.0).extend(0.0),
-            vec4(0.0, 0.0, 1.0, 0.0),
-            (self.position()).extend(-0.1).extend(1.0),
-        );
-        MODELS.borrow()[&ModelType::Agent]
+impl PortHighlightRenderer {
+    pub const Z: f64 = -0.3;
+
+    pub fn new(gl: &Rc<Context>) -> Self {
+        Self {
+            gl: Rc::clone(gl),
+            ports: vec![],
+            model: None,
+        }
+    }
+
+    /// Renders highlights on port positions relative to `offset`.
+    /// Selected ports get a bigger highlight.
+    pub fn render(
+        &mut self,
+        ports: impl IntoIterator<Item = (Vec2, bool)>,
+        camera: &Camera,
+        offset: XY,
+        z: f64,
+    ) {
+        let ports = ports.into_iter().collect::<Vec<_>>();
+
+        if ports.is_empty() {
+            return;
+        }
+
+        if self.model.is_none() || ports != self.ports {
+            let mut triangles = Triangles::new(vec![], vec![]);
+
+            for (position, selected) in ports.iter() {
+                let (radius, color) = if *selected {
+                    (0.15, vec4(1.0, 0.5, 0.0, 1.0))
+                } else {
+                    (0.1, vec4(1.0, 0.8, 0.4, 1.0))
+                };
+
+                triangles.append(Circle::new(position.x, position.y, 0.0, radius).triangles(color));
+            }
+
+            self.model = Some(Model::new(
+                &self.gl,
+                &SHADERS.borrow()[&ShaderType::Basic],
+                &triangles,
+            ));
+            self.ports = ports;
+        }
+
+        self.model
+            .as_ref()
+            .unwrap()
             .prepare_render()
-            .render(transform, camera);
+            .render_position(vec3(offset.x as f64, offset.y as f64, z), camera);
+    }
+}
+
+/// Gets the path of a traversal between `ports` of the gadget with minimal xy coords `xy`,
+/// as the gadget draws it, if there is a gadget there
+fn traversal_path(grid: &Grid<Gadget>, xy: XY, ports: PP, thickness: f64) -> Option<Path> {
+    const RESOLUTION: usize = 16;
+
+    let (gadget, _, _) = grid.get(xy)?;
+    let port_positions = gadget.port_positions();
+    let xy = xy.cast::<f64>().unwrap();
+
+    let points = (0..=RESOLUTION)
+        .map(|i| {
+            let t = i as f64 / RESOLUTION as f64;
+            xy + lang::port_path_point(ports, t, &port_positions).0
+        })
+        .collect();
+
+    Some(Path::new(points, 0.0, thickness, false))
+}
+
+/// Renders the traversals agents have taken, on top of the gadgets they went through.
+/// Either the most recent ones are drawn, fading with age,
+/// or all of them are drawn, colored by how many times they were taken.
+pub struct TrailRenderer {
+    gl: Rc<Context>,
+    /// Steps and heatmap option the cached model was made from
+    steps: Vec<TrailStep>,
+    heatmap: bool,
+    model: Option<Model>,
+}
+
+impl TrailRenderer {
+    /// Above the gadgets being traversed, but below the agents
+    pub const Z: f64 = -0.07;
+    pub const THICKNESS: f64 = 0.08;
+    /// Number of the most recent traversals drawn when not drawing a heatmap
+    pub const FADE_LENGTH: usize = 32;
+
+    pub fn new(gl: &Rc<Context>) -> Self {
+        Self {
+            gl: Rc::clone(gl),
+            steps: vec![],
+            heatmap: false,
+            model: None,
+        }
+    }
+
+    /// Renders the trail through the gadgets in the grid,
+    /// as a heatmap if `heatmap` is true
+    pub fn render(&mut self, trail: &Trail, grid: &Grid<Gadget>, heatmap: bool, camera: &Camera) {
+        if trail.is_empty() {
+            return;
+        }
+
+        if self.model.is_none() || heatmap != self.heatmap || trail.steps() != &self.steps[..] {
+            let mut triangles = Triangles::new(vec![], vec![]);
+
+            if heatmap {
+                let max_count = trail.max_count();
+
+                for (step, count) in trail.counts() {
+                    // Yellow for the least used, red for the most
+                    let heat = if max_count > 1 {
+                        (count - 1) as f32 / (max_count - 1) as f32
+                    } else {
+                        1.0
+                    };
+
+                    if let Some(path) = traversal_path(grid, step.xy, step.ports, Self::THICKNESS) {
+                        triangles.append(path.triangles(vec4(0.9, 0.9 * (1.0 - heat), 0.1, 0.8)));
+                    }
+                }
+            } else {
+                let start = trail.len().saturating_sub(Self::FADE_LENGTH);
+
+                for (age, step) in trail.steps()[start..].iter().rev().enumerate() {
+                    let alpha = 1.0 - age as f32 / Self::FADE_LENGTH as f32;
+
+                    if let Some(path) = traversal_path(grid, step.xy, step.ports, Self::THICKNESS) {
+                        triangles.append(path.triangles(vec4(1.0, 0.5, 0.0, alpha)));
+                    }
+                }
+            }
+
+            self.model = Some(Model::new(
+                &self.gl,
+                &SHADERS.borrow()[&ShaderType::Basic],
+                &triangles,
+            ));
+            self.steps = trail.steps().to_vec();
+            self.heatmap = heatmap;
+        }
+
+        self.model
+            .as_ref()
+            .unwrap()
+            .prepare_render()
+            .render_position(vec3(0.0, 0.0, Self::Z), camera);
+    }
+}
+
+/// Renders what each input would do to an agent:
+/// an arrow next to it for each input, colored by what the input would do,
+/// and the path and destination of each traversal the input could take.
+/// Inputs that would do nothing get grey arrows.
+pub struct MoveHintRenderer {
+    gl: Rc<Context>,
+    /// Agent and previews the cached model was made from
+    agent: Option<Agent>,
+    previews: Vec<(Vec2i, AdvancePreview)>,
+    model: Option<Model>,
+}
+
+impl MoveHintRenderer {
+    /// Above the trail, but below the agents
+    pub const Z: f64 = -0.08;
+    const NOTHING_COLOR: [f32; 4] = [0.5, 0.5, 0.5, 0.4];
+    const TURN_AROUND_COLOR: [f32; 4] = [0.3, 0.5, 1.0, 0.8];
+    const TRAVERSE_COLOR: [f32; 4] = [0.2, 0.7, 0.2, 0.9];
+    /// For traversals that change the gadget's state
+    const CHANGE_COLOR: [f32; 4] = [1.0, 0.5, 0.0, 0.9];
+
+    pub fn new(gl: &Rc<Context>) -> Self {
+        Self {
+            gl: Rc::clone(gl),
+            agent: None,
+            previews: vec![],
+            model: None,
+        }
+    }
+
+    fn color(preview: &AdvancePreview) -> Vector4<f32> {
+        match preview {
+            AdvancePreview::Nothing => Self::NOTHING_COLOR,
+            AdvancePreview::TurnAround => Self::TURN_AROUND_COLOR,
+            AdvancePreview::Traverse { states, .. } if states.0 != states.1 => Self::CHANGE_COLOR,
+            AdvancePreview::Traverse { .. } => Self::TRAVERSE_COLOR,
+        }
+        .into()
+    }
+
+    /// Gets the triangles of an arrow next to the agent, pointing toward `input`
+    fn arrow(agent: &Agent, input: Vec2i, color: Vector4<f32>) -> Triangles {
+        let dir = input.cast::<f64>().unwrap();
+        let base = agent.position() + dir * 0.3;
+        let side = dir.right_ccw() * 0.08;
+
+        let vertex = |xy: Vec2| {
+            Vertex::new(
+                xy.extend(0.0).cast::<f32>().unwrap(),
+                vec3(0.0, 0.0, 0.0),
+                color,
+                [],
+            )
+        };
+
+        Triangles::new(
+            vec![
+                vertex(base + side),
+                vertex(base - side),
+                vertex(base + dir * 0.15),
+            ],
+            vec![0, 1, 2],
+        )
+    }
+
+    /// Renders what each input would do to the agent, given as (input, preview) pairs.
+    /// An input can have a preview for each traversal it could take.
+    pub fn render(
+        &mut self,
+        agent: &Agent,
+        previews: &[(Vec2i, AdvancePreview)],
+        grid: &Grid<Gadget>,
+        camera: &Camera,
+    ) {
+        if previews.is_empty() {
+            return;
+        }
+
+        if self.model.is_none()
+            || self.agent.as_ref() != Some(agent)
+            || previews != &self.previews[..]
+        {
+            let mut triangles = Triangles::new(vec![], vec![]);
+
+            // The first preview of each input decides the color of its arrow
+            for (input, preview) in previews.iter().dedup_by(|(i0, _), (i1, _)| i0 == i1) {
+                triangles.append(Self::arrow(agent, *input, Self::color(preview)));
+            }
+
+            for (_, preview) in previews {
+                if let AdvancePreview::Traverse {
+                    xy, ports, states, ..
+                } = preview
+                {
+                    let color = Self::color(preview);
+
+                    if let Some(path) = traversal_path(grid, *xy, *ports, 0.04) {
+                        triangles.append(path.triangles(vec4(color.x, color.y, color.z, 0.5)));
+                    }
+
+                    // Mark the gadget that would change state
+                    if states.0 != states.1 {
+                        if let Some((_, _, (w, h))) = grid.get(*xy) {
+                            let center =
+                                xy.cast::<f64>().unwrap() + vec2(*w as f64, *h as f64) * 0.5;
+                            triangles.append(
+                                Circle::new(center.x, center.y, 0.0, 0.08).triangles(color),
+                            );
+                        }
+                    }
+                }
+            }
+
+            self.model = Some(Model::new(
+                &self.gl,
+                &SHADERS.borrow()[&ShaderType::Basic],
+                &triangles,
+            ));
+            self.agent = Some(agent.clone());
+            self.previews = previews.to_vec();
+        }
+
+        self.model
+            .as_ref()
+            .unwrap()
+            .prepare_render()
+            .render_position(vec3(0.0, 0.0, Self::Z), camera);
+
+        // Faint agents where the traversals would take it
+        for (_, preview) in previews {
+            if let AdvancePreview::Traverse { agent, .. } = preview {
+                let color = Self::color(preview);
+                render_agent(
+                    agent.position(),
+                    agent.direction().cast::<f64>().unwrap(),
+                    camera,
+                    vec4(color.x, color.y, color.z, 0.35),
+                );
+            }
+        }
+    }
+}
+
+/// Drawing for agents, which `gadget_core` knows nothing about
+pub trait AgentEx {
+    /// Renders the agent in some color
+    fn render(&self, camera: &Camera, color: Vector4<f32>);
+}
+
+impl AgentEx for Agent {
+    fn render(&self, camera: &Camera, color: Vector4<f32>) {
+        render_agent(
+            self.position(),
+            self.direction().cast::<f64>().unwrap(),
+            camera,
+            color,
+        );
+    }
+}
+
+/// Renders an agent at some position, facing some direction, which need not be along an axis
+fn render_agent(position: Vec2, dir: Vec2, camera: &Camera, color: Vector4<f32>) {
+    let transform = Mat4::from_cols(
+        -dir.right_ccw().extend(0.0).extend(0.0),
+        dir.extend(0.0).extend(0.0),
+        vec4(0.0, 0.0, 1.0, 0.0),
+        position.extend(-0.1).extend(1.0),
+    );
+    let models = MODELS.borrow();
+    let model = models[&ModelType::Agent].prepare_render();
+    model.set_uniform("tint", UniformValue::Vector4(*color.as_ref()));
+    model.render(transform, camera);
+}
+
+/// An agent moving through a gadget along the path its traversal is drawn with.
+/// The gadget is shown in its previous state until the agent is halfway through.
+#[derive(Clone, Debug)]
+pub struct AgentAnimation {
+    /// Index of the moving agent
+    agent: usize,
+    /// The gadget being traversed, in its previous state
+    gadget: Gadget,
+    /// Minimal xy coords of the gadget
+    xy: XY,
+    /// Entry and exit ports
+    ports: PP,
+    /// Time the agent entered the gadget, in milliseconds
+    start_time: f64,
+    /// Time the agent takes to go through the gadget, in milliseconds
+    duration: f64,
+}
+
+impl AgentAnimation {
+    pub const DEFAULT_DURATION: f64 = 250.0;
+    pub const MAX_DURATION: f64 = 1000.0;
+    /// Above the gadget being traversed, but below the agents
+    const GADGET_Z: f64 = -0.05;
+
+    /// Starts animating agent `agent` going through `gadget`, with minimal xy coords `xy`,
+    /// from where it was (`from`) to where it is (`to`).
+    /// `gadget` should be in the state it was in before the agent went through.
+    /// Returns `None` if the duration is 0
+    /// or the agent didn't go from one of the gadget's ports to another.
+    pub fn new(
+        agent: usize,
+        from: &Agent,
+        to: &Agent,
+        gadget: Gadget,
+        xy: XY,
+        start_time: f64,
+        duration: f64,
+    ) -> Option<Self> {
+        if duration <= 0.0 {
+            return None;
+        }
+
+        let ports = (
+            gadget.port_at(from.double_xy() - xy * 2)?,
+            gadget.port_at(to.double_xy() - xy * 2)?,
+        );
+
+        Some(Self {
+            agent,
+            gadget,
+            xy,
+            ports,
+            start_time,
+            duration,
+        })
+    }
+
+    /// Gets the index of the moving agent
+    pub fn agent(&self) -> usize {
+        self.agent
+    }
+
+    /// Gets how far through the gadget the agent is at `time`, from 0 to 1
+    fn progress(&self, time: f64) -> f64 {
+        ((time - self.start_time) / self.duration).max(0.0).min(1.0)
+    }
+
+    pub fn is_finished(&self, time: f64) -> bool {
+        time >= self.start_time + self.duration
+    }
+
+    /// Renders the agent where it is at `time`, and the gadget if it hasn't changed state yet
+    pub fn render(&self, gl: &Rc<Context>, camera: &Camera, color: Vector4<f32>, time: f64) {
+        let t = self.progress(time);
+        let xy = self.xy.cast::<f64>().unwrap();
+
+        if t < 0.5 {
+            self.gadget
+                .renderer()
+                .model(gl)
+                .prepare_render()
+                .render_position(xy.extend(Self::GADGET_Z), camera);
+        }
+
+        let (position, dir) = lang::port_path_point(self.ports, t, &self.gadget.port_positions());
+        render_agent(xy + position, dir, camera, color);
     }
 }
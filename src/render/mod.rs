@@ -1,14 +1,16 @@
-mod camera;
 mod gadget;
-pub mod lang;
 mod model;
 mod shader;
+pub mod svg;
 mod texture;
 mod ui;
 
-pub use camera::Camera;
-pub use gadget::{GadgetRenderInfo, GadgetRenderer, GridItemRenderer, SelectionRenderer};
-pub use model::{Model, Triangles, TrianglesEx, Vertex, VertexEx};
+pub use gadget::{AgentAnimation, AgentEx, GadgetModelEx, GadgetRenderer, GridItemRenderer};
+pub use gadget::{MoveHintRenderer, PortHighlightRenderer, SelectionRenderer, TrailRenderer};
+pub use gadget_core::render::{gadget_rectangle, lang, raster, Camera};
+pub use gadget_core::render::{GadgetEx, GadgetRenderInfo, GadgetShape};
+pub use gadget_core::render::{Triangles, TrianglesEx, Vertex, VertexEx};
+pub use model::Model;
 pub use model::{ModelType, TrianglesType, MODELS, TRIANGLESES};
 pub use shader::{ShaderType, SHADERS};
 pub use texture::{TextureType, TEXTURES};
@@ -1,4 +1,4 @@
-use cgmath::{vec3, vec4, Vector3, Vector4};
+use cgmath::{vec3, vec4};
 use fnv::FnvHashMap;
 
 use golem::UniformValue;
@@ -7,154 +7,10 @@ use golem::{ElementBuffer, GeometryMode, VertexBuffer};
 use ref_thread_local::{ref_thread_local, RefThreadLocal};
 use std::rc::Rc;
 
-use super::{Camera, ShaderType, SHADERS};
+use super::{gadget_rectangle, Camera, ShaderType, Triangles, TrianglesEx, Vertex, SHADERS};
 use crate::math::{Mat4, Vec3};
 use crate::static_map::StaticMap;
 
-pub type Vertex = VertexEx<[f32; 0]>;
-
-/// Stores the information for a single vertex.
-#[derive(Clone, Debug)]
-pub struct VertexEx<T: AsRef<[f32]> + Copy> {
-    // Edit the num_floats function if fields are modified!
-    pub position: Vector3<f32>,
-    // Z coordinate is 0 for no texture and 1 for texture
-    pub tex_coord: Vector3<f32>,
-    pub color: Vector4<f32>,
-    pub extra: T,
-}
-
-impl<T: AsRef<[f32]> + Copy> VertexEx<T> {
-    pub fn new(
-        position: Vector3<f32>,
-        tex_coord: Vector3<f32>,
-        color: Vector4<f32>,
-        extra: T,
-    ) -> Self {
-        Self {
-            position,
-            tex_coord,
-            color,
-            extra,
-        }
-    }
-
-    /// Gets an iterator of attribute items,
-    /// putting position, then color, then extra items
-    pub fn iter<'a>(&'a self) -> impl Iterator<Item = f32> + 'a {
-        AsRef::<[f32; 3]>::as_ref(&self.position)
-            .iter()
-            .chain(AsRef::<[f32; 3]>::as_ref(&self.tex_coord).iter())
-            .chain(AsRef::<[f32; 4]>::as_ref(&self.color).iter())
-            .chain(self.extra.as_ref().iter())
-            .copied()
-    }
-
-    /// Gets the number of f32's this vertex takes
-    pub fn num_floats(&self) -> usize {
-        3 + 3 + 4 + self.extra.as_ref().len()
-    }
-}
-
-impl Vertex {
-    /// Gets a new vertex with extra attribute items added
-    pub fn with_extra<U: AsRef<[f32]> + Copy>(self, extra: U) -> VertexEx<U> {
-        VertexEx {
-            position: self.position,
-            tex_coord: self.tex_coord,
-            color: self.color,
-            extra,
-        }
-    }
-
-    /// Gets a new vertex with default extra attribute items added
-    pub fn with_default_extra<U: AsRef<[f32]> + Copy + Default>(self) -> VertexEx<U> {
-        self.with_extra(U::default())
-    }
-}
-
-pub type Triangles = TrianglesEx<[f32; 0]>;
-
-/// Stores the information for multiple triangles.
-#[derive(Clone, Debug, Default)]
-pub struct TrianglesEx<T: AsRef<[f32]> + Copy> {
-    vertices: Vec<VertexEx<T>>,
-    indexes: Vec<u32>,
-}
-
-impl<T: AsRef<[f32]> + Copy> TrianglesEx<T> {
-    pub fn new(vertices: Vec<VertexEx<T>>, indexes: Vec<u32>) -> Self {
-        Self { vertices, indexes }
-    }
-
-    /// Gets an iterator of attribute items for each vertex in order
-    pub fn iter_vertex_items<'a>(&'a self) -> impl Iterator<Item = f32> + 'a {
-        self.vertices.iter().flat_map(move |v| v.iter())
-    }
-
-    pub fn vertices(&self) -> &[VertexEx<T>] {
-        &self.vertices
-    }
-
-    pub fn vertices_mut(&mut self) -> &mut [VertexEx<T>] {
-        &mut self.vertices
-    }
-
-    pub fn indexes(&self) -> &[u32] {
-        &self.indexes
-    }
-
-    /// Takes ownership of the other set of triangles because
-    /// this will often be called with temporary triangle structures
-    pub fn append(&mut self, other: TrianglesEx<T>) {
-        let base_index = self.vertices.len() as u32;
-
-        let TrianglesEx { vertices, indexes } = other;
-        self.vertices.extend(vertices.into_iter());
-        self.indexes
-            .extend(indexes.into_iter().map(|i| i + base_index));
-    }
-
-    /// Clears the triangle list
-    pub fn clear(&mut self) {
-        self.vertices.clear();
-        self.indexes.clear();
-    }
-
-    ///// Appends the triangles to a list of vertex attributes and indexes,
-    ///// adding extra vertex attributes to each vertex after their position and color.
-    ///// Assumes that `extra` is the same length as the extra attribute items
-    ///// that have already been added to `vertices`.
-    pub fn append_to(&self, vertices: &mut Vec<f32>, indexes: &mut Vec<u32>) {
-        let base_index =
-            (vertices.len() / self.vertices.first().map_or(1, |v| v.num_floats())) as u32;
-
-        vertices.extend(self.iter_vertex_items());
-        indexes.extend(self.indexes.iter().map(|i| *i + base_index));
-    }
-}
-
-impl Triangles {
-    /// Converts this to a triangle list where `extra` has been
-    /// added to each vertex's attribute items
-    pub fn with_extra<U: AsRef<[f32]> + Copy>(self, extra: U) -> TrianglesEx<U> {
-        TrianglesEx {
-            vertices: self
-                .vertices
-                .into_iter()
-                .map(|v| v.with_extra(extra))
-                .collect(),
-            indexes: self.indexes,
-        }
-    }
-
-    /// Converts this to a triangle list where default extra items have been
-    /// added to each vertex's attribute items
-    pub fn with_default_extra<U: AsRef<[f32]> + Copy + Default>(self) -> TrianglesEx<U> {
-        self.with_extra(U::default())
-    }
-}
-
 /// A simple model.
 pub struct Model {
     program: Rc<ShaderProgram>,
@@ -271,6 +127,11 @@ impl<'a> RenderingModel<'a> {
     pub fn render_position(&self, position: Vec3, camera: &Camera) {
         self.render(Mat4::from_translation(position), camera);
     }
+
+    /// Sets a uniform other than the transform, such as the tint
+    pub fn set_uniform(&self, name: &str, value: UniformValue) {
+        self.0.program.set_uniform(name, value).unwrap();
+    }
 }
 
 pub struct InstancedRenderingModel<'a>(&'a Model, &'a [&'a str]);
@@ -299,6 +160,8 @@ impl<'a> Drop for InstancedRenderingModel<'a> {
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub enum TrianglesType {
     Agent,
+    /// The agent in shades of white, for tinting with the agent's color
+    AgentShades,
     GadgetRectangle,
     SelectionMark,
     Undo,
@@ -337,17 +200,22 @@ fn triangles_map(_: ()) -> TrianglesMap {
             )),
         ),
         (
-            TrianglesType::GadgetRectangle,
+            TrianglesType::AgentShades,
             Rc::new(Triangles::new(
                 vec![
-                    Vertex::new(vec3(0., 0., 0.), vec3(0., 0., 0.), vec4(0.6, 0.8, 1., 1.), []),
-                    Vertex::new(vec3(1., 0., 0.), vec3(0., 0., 0.), vec4(0.7, 0.9, 1., 1.), []),
-                    Vertex::new(vec3(1., 1., 0.), vec3(0., 0., 0.), vec4(0.9, 1.0, 1., 1.), []),
-                    Vertex::new(vec3(0., 1., 0.), vec3(0., 0., 0.), vec4(0.8, 1.0, 1., 1.), []),
+                    Vertex::new(vec3( 0.15, -0.15, 0.), vec3(0., 0., 0.), vec4(1., 1., 1., 1.), []),
+                    Vertex::new(vec3( 0.15,  0.,   0.), vec3(0., 0., 0.), vec4(0.75, 0.75, 0.75, 1.), []),
+                    Vertex::new(vec3( 0.,    0.15, 0.), vec3(0., 0., 0.), vec4(0.5, 0.5, 0.5, 1.), []),
+                    Vertex::new(vec3(-0.15,  0.,   0.), vec3(0., 0., 0.), vec4(0.75, 0.75, 0.75, 1.), []),
+                    Vertex::new(vec3(-0.15, -0.15, 0.), vec3(0., 0., 0.), vec4(1., 1., 1., 1.), []),
                 ],
-                vec![0, 1, 2, 2, 3, 0],
+                vec![0, 1, 2, 0, 2, 4, 2, 3, 4],
             )),
         ),
+        (
+            TrianglesType::GadgetRectangle,
+            Rc::new(gadget_rectangle()),
+        ),
         (
             TrianglesType::SelectionMark,
             Rc::new(include!("../../assets/models/selection_mark.tris")),
@@ -461,8 +329,8 @@ fn model_map(gl: &Context) -> ModelMap {
             ModelType::Agent,
             Rc::new(Model::new(
                 gl,
-                &SHADERS.borrow()[&ShaderType::Basic],
-                &TRIANGLESES.borrow()[&TrianglesType::Agent],
+                &SHADERS.borrow()[&ShaderType::Tint],
+                &TRIANGLESES.borrow()[&TrianglesType::AgentShades],
             )),
         ),
         (
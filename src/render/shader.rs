@@ -1,5 +1,6 @@
 use fnv::FnvHashMap;
 use golem::Dimension::{D2, D3, D4};
+use golem::NumberType;
 use golem::{Attribute, AttributeType, Uniform, UniformType};
 use golem::{Context, ShaderDescription, ShaderProgram};
 
@@ -13,6 +14,8 @@ use crate::static_map::StaticMap;
 pub enum ShaderType {
     /// Just position, texcoord, and color
     Basic,
+    /// Basic, with a tint (vec4) that multiplies the color
+    Tint,
     /// Add a offset (vec3)
     Offset,
     /// Add a scale (vec2) and an offset (vec3)
@@ -53,6 +56,31 @@ fn shader_map(gl: &Context) -> ShaderMap {
                 },
             ).unwrap())
         ),
+        (
+            ShaderType::Tint,
+            Rc::new(ShaderProgram::new(
+                gl,
+                ShaderDescription {
+                    vertex_input: &[
+                        Attribute::new("v_position", AttributeType::Vector(D3)),
+                        Attribute::new("v_tex_coord", AttributeType::Vector(D3)),
+                        Attribute::new("v_color", AttributeType::Vector(D4)),
+                    ],
+                    fragment_input: &[Attribute::new("f_color", AttributeType::Vector(D4))],
+                    uniforms: &[
+                        Uniform::new("transform", UniformType::Matrix(D4)),
+                        Uniform::new("tint", UniformType::Vector(NumberType::Float, D4)),
+                    ],
+                    vertex_shader: r#"void main() {
+                        f_color = v_color * tint;
+                        gl_Position = transform * vec4(v_position, 1.0);
+                    }"#,
+                    fragment_shader: r#"void main() {
+                        gl_FragColor = f_color;
+                    }"#,
+                },
+            ).unwrap())
+        ),
         (
             ShaderType::Offset,
             Rc::new(ShaderProgram::new(
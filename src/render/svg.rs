@@ -0,0 +1,352 @@
+//! Vector export of contraptions, for figures that stay sharp at any size
+
+use cgmath::{vec2, Vector4};
+use fnv::FnvHashSet;
+use ref_thread_local::RefThreadLocal;
+
+use std::fmt::Write;
+
+use super::{GadgetEx, GadgetShape, TrianglesType, TRIANGLESES};
+use crate::gadget::Gadget;
+use crate::grid::{WH, XY};
+use crate::math::Vec2;
+
+/// Number of SVG units per grid unit
+const SCALE: f64 = 100.0;
+
+/// Id of the gradient that fills gadget rectangles
+const RECTANGLE_GRADIENT: &str = "gadget-rectangle";
+
+/// Formats the RGB part of a color in hex
+fn hex(color: Vector4<f32>) -> String {
+    let channel = |c: f32| (c.max(0.0).min(1.0) * 255.0).round() as u8;
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        channel(color.x),
+        channel(color.y),
+        channel(color.z)
+    )
+}
+
+/// Formats a color as an SVG fill
+fn fill(color: Vector4<f32>) -> String {
+    let mut string = format!("fill=\"{}\"", hex(color));
+
+    if color.w < 1.0 {
+        write!(string, " fill-opacity=\"{}\"", number(color.w as f64)).unwrap();
+    }
+    string
+}
+
+/// Formats a number with at most 3 decimal places
+fn number(x: f64) -> String {
+    let string = format!("{:.3}", x);
+    let string = string.trim_end_matches('0').trim_end_matches('.');
+
+    if string == "-0" {
+        "0".to_string()
+    } else {
+        string.to_string()
+    }
+}
+
+/// Writes an SVG document, flipping grid coordinates so y points down
+struct SvgWriter {
+    /// Top left corner of the document, in grid coordinates
+    top_left: Vec2,
+    /// Position of the gadget being drawn
+    origin: Vec2,
+    /// Elements with their depths
+    elements: Vec<(f64, String)>,
+}
+
+impl SvgWriter {
+    fn point(&self, p: Vec2) -> String {
+        let p = p + self.origin;
+        format!(
+            "{},{}",
+            number((p.x - self.top_left.x) * SCALE),
+            number((self.top_left.y - p.y) * SCALE)
+        )
+    }
+
+    fn points(&self, points: impl IntoIterator<Item = Vec2>) -> String {
+        points
+            .into_iter()
+            .map(|p| self.point(p))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn add(&mut self, z: f64, element: String) {
+        self.elements.push((z, element));
+    }
+
+    fn rectangle(&mut self, z: f64, (w, h): WH) {
+        let corner = self.point(vec2(0.0, h as f64));
+        let mut coords = corner.split(',');
+
+        let element = format!(
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"url(#{})\"/>",
+            coords.next().unwrap(),
+            coords.next().unwrap(),
+            number(w as f64 * SCALE),
+            number(h as f64 * SCALE),
+            RECTANGLE_GRADIENT
+        );
+        self.add(z, element);
+    }
+
+    fn shape(&mut self, shape: &GadgetShape) {
+        let z = shape.z();
+
+        match shape {
+            GadgetShape::Rectangle { size } => self.rectangle(z, *size),
+
+            GadgetShape::Circle {
+                center,
+                radius,
+                color,
+            } => {
+                let point = self.point(center.truncate());
+                let mut coords = point.split(',');
+
+                let element = format!(
+                    "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" {}/>",
+                    coords.next().unwrap(),
+                    coords.next().unwrap(),
+                    number(radius * SCALE),
+                    fill(*color)
+                );
+                self.add(z, element);
+            }
+
+            GadgetShape::Polygon { points, color } => {
+                let element = format!(
+                    "<polygon points=\"{}\" {}/>",
+                    self.points(points.iter().map(|p| p.truncate())),
+                    fill(*color)
+                );
+                self.add(z, element);
+            }
+
+            GadgetShape::Path { path, color } => {
+                let d = path
+                    .outlines()
+                    .into_iter()
+                    .map(|outline| format!("M{}Z", self.points(outline)))
+                    .collect::<String>();
+
+                let element = format!("<path d=\"{}\" fill-rule=\"evenodd\" {}/>", d, fill(*color));
+                self.add(z, element);
+            }
+
+            // SVG has no colors per vertex, so each triangle gets its average color
+            GadgetShape::Triangles(triangles) => {
+                for indexes in triangles.indexes().chunks(3) {
+                    let vertices = indexes
+                        .iter()
+                        .map(|i| &triangles.vertices()[*i as usize])
+                        .collect::<Vec<_>>();
+                    let n = vertices.len() as f32;
+
+                    let color = vertices.iter().map(|v| v.color).sum::<Vector4<f32>>() / n;
+                    let z = vertices.iter().map(|v| v.position.z as f64).sum::<f64>() / n as f64;
+
+                    let element = format!(
+                        "<polygon points=\"{}\" {}/>",
+                        self.points(
+                            vertices
+                                .iter()
+                                .map(|v| v.position.truncate().cast::<f64>().unwrap())
+                        ),
+                        fill(color)
+                    );
+                    self.add(z, element);
+                }
+            }
+        }
+    }
+
+    /// Finishes the document, which is (w, h) grid units big
+    fn finish(mut self, (w, h): WH) -> String {
+        // Back to front, like the depth test on screen
+        self.elements
+            .sort_by(|(z0, _), (z1, _)| z1.partial_cmp(z0).expect("Depth is NaN"));
+
+        let rectangle = TRIANGLESES.borrow()[&TrianglesType::GadgetRectangle].clone();
+        let colors = rectangle
+            .vertices()
+            .iter()
+            .map(|v| v.color)
+            .collect::<Vec<_>>();
+
+        let mut svg = String::new();
+        writeln!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">",
+            number(w as f64 * SCALE),
+            number(h as f64 * SCALE)
+        )
+        .unwrap();
+
+        // The rectangle is shaded from its bottom left corner to its top right corner
+        writeln!(
+            svg,
+            "<defs><linearGradient id=\"{}\" x1=\"0\" y1=\"1\" x2=\"1\" y2=\"0\">\
+             <stop offset=\"0\" stop-color=\"{}\"/><stop offset=\"1\" stop-color=\"{}\"/>\
+             </linearGradient></defs>",
+            RECTANGLE_GRADIENT,
+            hex(colors[0]),
+            hex(colors[2])
+        )
+        .unwrap();
+
+        for (_, element) in self.elements {
+            writeln!(svg, "{}", element).unwrap();
+        }
+
+        svg += "</svg>\n";
+        svg
+    }
+}
+
+/// Draws gadgets as an SVG document, with the same shapes as on screen.
+/// Empty cells between the gadgets are drawn like on screen too.
+pub fn gadgets_to_svg<'a>(gadgets: impl IntoIterator<Item = &'a (Gadget, XY, WH)>) -> String {
+    let gadgets = gadgets.into_iter().collect::<Vec<_>>();
+
+    let min_x = gadgets.iter().map(|(_, xy, _)| xy.x).min().unwrap_or(0);
+    let min_y = gadgets.iter().map(|(_, xy, _)| xy.y).min().unwrap_or(0);
+    let max_x = gadgets
+        .iter()
+        .map(|(_, xy, (w, _))| xy.x + *w as isize)
+        .max()
+        .unwrap_or(0);
+    let max_y = gadgets
+        .iter()
+        .map(|(_, xy, (_, h))| xy.y + *h as isize)
+        .max()
+        .unwrap_or(0);
+
+    let mut writer = SvgWriter {
+        top_left: vec2(min_x as f64, max_y as f64),
+        origin: vec2(0.0, 0.0),
+        elements: vec![],
+    };
+
+    let mut covered = FnvHashSet::default();
+
+    for (gadget, xy, (w, h)) in &gadgets {
+        writer.origin = xy.cast::<f64>().unwrap();
+
+        for shape in gadget.renderer().gadget_shapes(gadget) {
+            writer.shape(&shape);
+        }
+
+        for x in 0..*w as isize {
+            for y in 0..*h as isize {
+                covered.insert(xy + vec2(x, y));
+            }
+        }
+    }
+
+    for x in min_x..max_x {
+        for y in min_y..max_y {
+            if !covered.contains(&vec2(x, y)) {
+                writer.origin = vec2(x as f64, y as f64);
+                writer.rectangle(super::GadgetRenderInfo::RECTANGLE_Z, (1, 1));
+            }
+        }
+    }
+
+    writer.finish(((max_x - min_x) as usize, (max_y - min_y) as usize))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gadget::{GadgetDef, State};
+    use crate::grid::Grid;
+    use crate::preset_gadgets::preset_gadgets;
+    use crate::spsp_multi;
+
+    use std::rc::Rc;
+
+    fn init() -> Vec<Gadget> {
+        TRIANGLESES.borrow_mut().init(());
+        preset_gadgets()
+    }
+
+    #[test]
+    fn test_number() {
+        assert_eq!(number(1.0), "1");
+        assert_eq!(number(-0.0001), "0");
+        assert_eq!(number(12.34567), "12.346");
+        assert_eq!(number(100.0), "100");
+    }
+
+    #[test]
+    fn test_fill() {
+        assert_eq!(fill(Vector4::new(1.0, 0.0, 0.5, 1.0)), "fill=\"#ff0080\"");
+        assert_eq!(
+            fill(Vector4::new(0.0, 0.0, 0.0, 0.5)),
+            "fill=\"#000000\" fill-opacity=\"0.5\""
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        init();
+        let svg = gadgets_to_svg(&[]);
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"0\""));
+        assert!(svg.ends_with("</svg>\n"));
+    }
+
+    #[test]
+    fn test_gadgets() {
+        let presets = init();
+        let toggle = presets
+            .iter()
+            .find(|gadget| gadget.name() == "Toggle")
+            .unwrap()
+            .clone();
+
+        let mut grid = Grid::new();
+        grid.insert(toggle.clone(), vec2(0, 0), (1, 1));
+        grid.insert(toggle, vec2(2, 1), (1, 1));
+
+        let svg = gadgets_to_svg(grid.iter());
+        assert!(svg.contains("width=\"300\" height=\"200\""));
+
+        // 2 gadgets and 4 empty cells
+        assert_eq!(svg.matches("<rect ").count(), 6);
+        assert!(svg.contains("<rect x=\"0\" y=\"100\" width=\"100\" height=\"100\""));
+
+        // Port circles, on the bottom and top of each gadget
+        assert_eq!(svg.matches("<circle ").count(), 4);
+        assert!(svg.contains("<circle cx=\"50\" cy=\"200\" r=\"5\" fill=\"#0000bf\"/>"));
+        assert!(svg.contains("<circle cx=\"250\" cy=\"0\" r=\"5\" fill=\"#0000bf\"/>"));
+
+        // Rectangles are behind everything else, and port circles are in front of paths
+        let last_rect = svg.rfind("<rect ").unwrap();
+        let first_path = svg.find("<path ").unwrap();
+        let first_circle = svg.find("<circle ").unwrap();
+        assert!(last_rect < first_path);
+        assert!(svg.rfind("<path ").unwrap() < first_circle);
+    }
+
+    #[test]
+    fn test_triangles_match_shapes() {
+        init();
+        let def = GadgetDef::from_traversals(2, 2, spsp_multi![((0, 0), (1, 1)), ((1, 1), (0, 0))]);
+        let gadget = Gadget::new(&Rc::new(def), (2, 1), vec![0, 3], State(1));
+
+        let renderer = gadget.renderer();
+        let mut num_vertices = 0;
+        for shape in renderer.gadget_shapes(&gadget) {
+            num_vertices += shape.triangles().vertices().len();
+        }
+        assert_eq!(renderer.triangles().vertices().len(), num_vertices);
+    }
+}
@@ -2,16 +2,24 @@ use cgmath::vec2;
 use conrod_core::color;
 
 use conrod_core::render::PrimitiveWalker;
+use conrod_core::widget::button::Flat;
 use conrod_core::widget::text::Text;
+use conrod_core::widget::Button as TextButton;
 use conrod_core::widget::Canvas;
-use conrod_core::widget::{bordered_rectangle, BorderedRectangle, List};
+use conrod_core::widget::{bordered_rectangle, BorderedRectangle, List, Matrix, Slider, Toggle};
 use conrod_core::widget_ids;
-use conrod_core::{Borderable, Color, Colorable, Positionable, Sizeable, Theme, Widget};
+use conrod_core::{Borderable, Color, Colorable, Labelable, Positionable, Sizeable, Theme, Widget};
 use conrod_core::{Ui, UiCell};
 use ref_thread_local::RefThreadLocal;
 
-use crate::gadget::Agent;
+use crate::def_editor::DefEditor;
+use crate::gadget::{Gadget, Port, State, SP};
+use crate::grid::XY;
+use crate::math::Vec2i;
+use crate::replay::Replay;
+use crate::solver::Step;
 
+use crate::render::AgentAnimation;
 use crate::render::TrianglesType;
 use crate::render::TRIANGLESES;
 use crate::widget::button;
@@ -21,8 +29,16 @@ use crate::App;
 
 widget_ids! {
     pub struct WidgetIds {
-        contraption_screen, menu, menu_list, gadget_select, agent, version,
+        contraption_screen, menu, menu_list, gadget_select, agent, version, contraption_report,
         canvas, header, body, left_sidebar,
+        explore_panel, explore_button, explore_report, explore_prev, explore_next,
+        explore_index, explore_dead_ends, script_button, script_report,
+        playback_slider, playback_start, playback_prev, playback_toggle, playback_next,
+        playback_status, playback_watch, animation_duration, trail_clear, trail_heatmap,
+        region_panel, region_status, region_add_rep, region_clear_reps, region_compose,
+        region_defs, region_report,
+        def_panel, def_help, def_row_texts[], def_row_decs[], def_row_incs[], def_matrix,
+        def_preview, def_add, def_reset, def_export, def_import, def_report,
     }
 }
 
@@ -47,6 +63,10 @@ pub enum Mode {
     Zoom,
     GadgetMove,
     GadgetPaste,
+    /// Marking external ports and representatives of a region to check
+    RegionEdit,
+    /// Building a gadget def by hand
+    DefEdit,
 }
 
 /// Action to be done with the left mouse button
@@ -57,6 +77,131 @@ pub enum LeftMouseAction {
     Zoom,
 }
 
+/// A choice between multiple traversals the agent can take in play mode
+pub struct TraversalChoice {
+    /// Input that gave the choice
+    input: Vec2i,
+    /// Whether the input moves the agent backward
+    back: bool,
+    /// Minimal xy coords of the gadget being traversed
+    xy: XY,
+    /// Possible (resulting state, exit port) pairs
+    targets: Vec<SP>,
+    /// Index of the highlighted target
+    index: usize,
+    /// The gadget as it would be after taking the highlighted target
+    preview: Gadget,
+}
+
+impl TraversalChoice {
+    pub fn new(input: Vec2i, back: bool, xy: XY, targets: Vec<SP>, gadget: &Gadget) -> Self {
+        let mut res = Self {
+            input,
+            back,
+            xy,
+            targets,
+            index: 0,
+            preview: gadget.clone(),
+        };
+        res.set_index(0);
+        res
+    }
+
+    pub fn xy(&self) -> XY {
+        self.xy
+    }
+
+    pub fn targets(&self) -> &[SP] {
+        &self.targets
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Gets the highlighted target
+    pub fn target(&self) -> SP {
+        self.targets[self.index]
+    }
+
+    /// Highlights the target at some index
+    pub fn set_index(&mut self, index: usize) {
+        self.index = index;
+        self.preview.set_state(self.targets[index].0);
+    }
+
+    pub fn preview(&self) -> &Gadget {
+        &self.preview
+    }
+
+    /// Gets the step that takes the highlighted target
+    pub fn step(&self) -> Step {
+        Step::new(self.input, self.back, self.index)
+    }
+}
+
+/// Watching a replay in play mode
+pub struct Playback {
+    replay: Replay,
+    /// Number of moves of the replay that have been made
+    position: usize,
+    /// Whether moves are being made as time passes
+    playing: bool,
+    /// Time of the last move made as time passed, in milliseconds
+    last_move_time: f64,
+}
+
+impl Playback {
+    /// Time between moves while playing, in milliseconds
+    pub const MOVE_INTERVAL: f64 = 400.0;
+
+    pub fn new(replay: Replay) -> Self {
+        Self {
+            replay,
+            position: 0,
+            playing: false,
+            last_move_time: 0.0,
+        }
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    pub fn into_replay(self) -> Replay {
+        self.replay
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: usize) {
+        self.position = position.min(self.replay.len());
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Starts or stops making moves as time passes, starting from `time`
+    pub fn set_playing(&mut self, playing: bool, time: f64) {
+        self.playing = playing;
+        self.last_move_time = time;
+    }
+
+    /// Gets whether it is time to make the next move while playing,
+    /// and starts waiting for the one after it if so
+    pub fn is_move_due(&mut self, time: f64) -> bool {
+        if self.playing && time - self.last_move_time >= Self::MOVE_INTERVAL {
+            self.last_move_time = time;
+            true
+        } else {
+            false
+        }
+    }
+}
+
 impl<'a> App<'a> {
     pub fn set_mode(&mut self, mode: Mode) {
         if mode != self.mode {
@@ -75,6 +220,8 @@ impl<'a> App<'a> {
             }
 
             // clear some fields
+            self.contraption_report.clear();
+
             if mode != Mode::TilePaint {
                 self.gadget_selection = None;
                 self.gadget_tile = None;
@@ -86,6 +233,9 @@ impl<'a> App<'a> {
                 self.undo_stack_mut().clear();
 
                 self.left_mouse_action = LeftMouseAction::Pan;
+                // Record the moves from the start, so they can be shared
+                self.restart_recording();
+                self.trail.clear();
             }
 
             // Play time's over! Move the entire play history to the main stack as a single batch
@@ -107,13 +257,29 @@ impl<'a> App<'a> {
             }
 
             if mode != Mode::AgentPlace && mode != Mode::Play {
-                self.agent = None;
+                self.agents.clear();
+                self.active_agent = 0;
+            }
+
+            if mode != Mode::Play {
+                self.stop_animation();
+                self.traversal_choice = None;
+                self.solve_target = None;
+                self.solution.clear();
+                self.exploration = None;
+                self.recording = None;
+                self.recording_redo.clear();
+                self.playback = None;
             }
 
             if mode != Mode::Select && mode != Mode::Pan && mode != Mode::Zoom {
                 self.selection.clear();
             }
 
+            if mode != Mode::RegionEdit {
+                self.region = None;
+            }
+
             if self.mode == Mode::GadgetPaste {
                 // Just in case a cut was performed without a paste
                 self.undo_stack_mut().batch();
@@ -155,17 +321,17 @@ impl<'a> App<'a> {
                 }
 
                 screen::Event::AgentPlace(xy) => {
-                    if self.agent.is_some() {
+                    if self.agent().is_some() {
                         self.set_mode(Mode::Play);
                     }
 
-                    if let Some(agent) = &mut self.agent {
+                    if let Some(agent) = self.agent_mut() {
                         agent.set_position(xy);
                     }
                 }
 
                 screen::Event::AgentHover(xy) => {
-                    if let Some(agent) = &mut self.agent {
+                    if let Some(agent) = self.agent_mut() {
                         agent.set_position(xy);
                     }
                 }
@@ -233,6 +399,22 @@ impl<'a> App<'a> {
                     self.set_mode(Mode::Select);
                 }
 
+                screen::Event::PlayClick(xy) => {
+                    self.click_play(xy);
+                }
+
+                screen::Event::PlayTarget(xy) => {
+                    self.solve_to(xy);
+                }
+
+                screen::Event::RegionPortToggle(xy) => {
+                    self.toggle_region_port(xy);
+                }
+
+                screen::Event::RegionCycleState(xy) => {
+                    self.cycle_region_gadget_state(xy);
+                }
+
                 screen::Event::GadgetPaste(xy) => {
                     for (t, xy, _) in self.paste.clone().translate(xy) {
                         self.add_gadget_to_grid(t, xy);
@@ -272,7 +454,7 @@ impl<'a> App<'a> {
             .wh_of(self.ids.header)
             .set(self.ids.menu, &mut ui);
 
-        let (mut items, _) = List::flow_right(19)
+        let (mut items, _) = List::flow_right(21)
             .middle_of(self.ids.menu)
             .wh_of(self.ids.menu)
             .set(self.ids.menu_list, &mut ui);
@@ -318,8 +500,7 @@ impl<'a> App<'a> {
             .tooltip_text("Place agent (H)"),
             &mut ui,
         ) {
-            self.set_mode(Mode::AgentPlace);
-            self.agent = Some(Agent::new(vec2(0.5, 0.0), vec2(0, 1)));
+            self.place_agent();
         }
 
         for _ in items.next(&ui).unwrap().set(
@@ -502,7 +683,45 @@ impl<'a> App<'a> {
             .tooltip_text("Save (Ctrl + S)"),
             &mut ui,
         ) {
-            crate::save_grid_in_url(&self.grid);
+            self.save();
+        }
+
+        for _ in items.next(&ui).unwrap().set(
+            as_menu_button(
+                Button::triangles(Triangles3d::new(
+                    (*TRIANGLESES.borrow()[&TrianglesType::Save])
+                        .clone()
+                        .with_default_extra(),
+                    vec2(0.0, 0.0),
+                    2.0,
+                    -2.0,
+                )),
+                self,
+                &mut ui,
+            )
+            .tooltip_text("Export as text (Ctrl + Shift + S)"),
+            &mut ui,
+        ) {
+            crate::export_contraption(&self.grid);
+        }
+
+        for _ in items.next(&ui).unwrap().set(
+            as_menu_button(
+                Button::triangles(Triangles3d::new(
+                    (*TRIANGLESES.borrow()[&TrianglesType::Save])
+                        .clone()
+                        .with_default_extra(),
+                    vec2(0.0, 0.0),
+                    -2.0,
+                    -2.0,
+                )),
+                self,
+                &mut ui,
+            )
+            .tooltip_text("Import from text (Ctrl + Shift + O)"),
+            &mut ui,
+        ) {
+            crate::import_contraption();
         }
 
         for _ in items.next(&ui).unwrap().set(
@@ -700,14 +919,23 @@ impl<'a> App<'a> {
         }
 
         // Gadget selector
-        if self.mode != Mode::Play {
-            let selection = SelectionGrid::new(4, &self.gadget_select, self.gadget_selection)
-                .color(Color::Rgba(0.8, 0.9, 0.8, 1.0))
-                .border_color(color::BLACK)
-                .outer_padding(5.0)
-                .middle_of(self.ids.left_sidebar)
-                .padded_wh_of(self.ids.left_sidebar, 10.0)
-                .set(self.ids.gadget_select, &mut ui);
+        if self.mode == Mode::RegionEdit {
+            self.update_region_panel(&mut ui);
+        } else if self.mode == Mode::DefEdit {
+            self.update_def_edit_panel(&mut ui);
+        } else if self.mode != Mode::Play {
+            let selection = SelectionGrid::new(
+                4,
+                &self.gadget_select,
+                &self.gadget_select_properties,
+                self.gadget_selection,
+            )
+            .color(Color::Rgba(0.8, 0.9, 0.8, 1.0))
+            .border_color(color::BLACK)
+            .outer_padding(5.0)
+            .middle_of(self.ids.left_sidebar)
+            .padded_wh_of(self.ids.left_sidebar, 10.0)
+            .set(self.ids.gadget_select, &mut ui);
 
             if let Some(selection) = selection {
                 self.set_mode(Mode::TilePaint);
@@ -716,6 +944,8 @@ impl<'a> App<'a> {
                 let gadget = self.gadget_select[selection].clone();
                 self.gadget_tile = Some(gadget);
             }
+        } else {
+            self.update_explore_panel(&mut ui);
         }
 
         // Version number
@@ -723,6 +953,598 @@ impl<'a> App<'a> {
             .font_size(12)
             .bottom_left_with_margin_on(self.ids.gadget_select, 3.0)
             .set(self.ids.version, &mut ui);
+
+        if !self.contraption_report.is_empty() {
+            Text::new(&self.contraption_report)
+                .font_size(12)
+                .color(Color::Rgba(0.8, 0.0, 0.0, 1.0))
+                .w(500.0)
+                .bottom_right_with_margin_on(self.ids.body, 10.0)
+                .set(self.ids.contraption_report, &mut ui);
+        }
+    }
+
+    /// Sets the widgets of the play mode panel that reports on reachable configurations
+    fn update_explore_panel(&mut self, ui: &mut UiCell) {
+        sidebar_panel()
+            .middle_of(self.ids.left_sidebar)
+            .padded_wh_of(self.ids.left_sidebar, 10.0)
+            .set(self.ids.explore_panel, ui);
+
+        for _ in text_button()
+            .label("Explore reachable configurations (E)")
+            .mid_top_with_margin_on(self.ids.explore_panel, 5.0)
+            .padded_w_of(self.ids.explore_panel, 5.0)
+            .h(25.0)
+            .set(self.ids.explore_button, ui)
+        {
+            self.explore();
+        }
+
+        for _ in text_button()
+            .label("Run test script")
+            .mid_bottom_with_margin_on(self.ids.explore_panel, 5.0)
+            .padded_w_of(self.ids.explore_panel, 5.0)
+            .h(25.0)
+            .set(self.ids.script_button, ui)
+        {
+            crate::import_script();
+        }
+
+        let top = self.update_playback_controls(ui);
+
+        if let Some(duration) = Slider::new(
+            self.animation_duration as f32,
+            0.0,
+            AgentAnimation::MAX_DURATION as f32,
+        )
+        .label(&format!(
+            "Move animation: {} ms",
+            self.animation_duration.round()
+        ))
+        .label_font_size(12)
+        .color(Color::Rgba(0.9, 0.9, 0.9, 1.0))
+        .border(1.0)
+        .border_color(color::BLACK)
+        .up_from(top, 5.0)
+        .align_left_of(self.ids.script_button)
+        .padded_w_of(self.ids.explore_panel, 5.0)
+        .h(20.0)
+        .set(self.ids.animation_duration, ui)
+        {
+            // Snap to 10 ms
+            self.animation_duration = (duration as f64 / 10.0).round() * 10.0;
+        }
+
+        for _ in text_button()
+            .label("Clear trail")
+            .up_from(self.ids.animation_duration, 5.0)
+            .align_left_of(self.ids.script_button)
+            .w_h(100.0, 25.0)
+            .set(self.ids.trail_clear, ui)
+        {
+            self.trail.clear();
+        }
+
+        for heatmap in Toggle::new(self.trail_heatmap)
+            .label("Trail heatmap")
+            .label_font_size(12)
+            .color(if self.trail_heatmap {
+                Color::Rgba(0.4, 0.8, 0.4, 1.0)
+            } else {
+                Color::Rgba(0.9, 0.9, 0.9, 1.0)
+            })
+            .border(1.0)
+            .border_color(color::BLACK)
+            .right_from(self.ids.trail_clear, 5.0)
+            .w_h(100.0, 25.0)
+            .set(self.ids.trail_heatmap, ui)
+        {
+            self.trail_heatmap = heatmap;
+        }
+
+        if !self.script_report.is_empty() {
+            Text::new(&self.script_report)
+                .font_size(12)
+                .up_from(self.ids.trail_clear, 5.0)
+                .align_left_of(self.ids.script_button)
+                .padded_w_of(self.ids.explore_panel, 5.0)
+                .set(self.ids.script_report, ui);
+        }
+
+        let exploration = match &self.exploration {
+            Some(exploration) => exploration,
+            None => return,
+        };
+
+        let mut report = format!(
+            "Configurations: {}{}\nDead ends: {}\nUnvisited states: {}",
+            exploration.configurations().len(),
+            if exploration.is_complete() {
+                ""
+            } else {
+                " (gave up)"
+            },
+            exploration.dead_ends().len(),
+            exploration.unvisited_states().len(),
+        );
+        for (xy, state) in exploration.unvisited_states().iter().take(5) {
+            report += &format!("\n    Gadget at ({}, {}): state {}", xy.x, xy.y, state.id());
+        }
+        if exploration.unvisited_states().len() > 5 {
+            report += "\n    ...";
+        }
+
+        Text::new(&report)
+            .font_size(12)
+            .down_from(self.ids.explore_button, 5.0)
+            .align_left_of(self.ids.explore_button)
+            .set(self.ids.explore_report, ui);
+
+        let num_configs = exploration.configurations().len();
+        let mut jump = None;
+
+        for _ in text_button()
+            .label("<")
+            .down_from(self.ids.explore_report, 5.0)
+            .align_left_of(self.ids.explore_button)
+            .w_h(25.0, 25.0)
+            .set(self.ids.explore_prev, ui)
+        {
+            jump = Some((self.exploration_index + num_configs - 1) % num_configs);
+        }
+
+        for _ in text_button()
+            .label(">")
+            .right_from(self.ids.explore_prev, 5.0)
+            .w_h(25.0, 25.0)
+            .set(self.ids.explore_next, ui)
+        {
+            jump = Some((self.exploration_index + 1) % num_configs);
+        }
+
+        Text::new(&format!(
+            "Configuration {} of {}",
+            self.exploration_index + 1,
+            num_configs
+        ))
+        .font_size(12)
+        .right_from(self.ids.explore_next, 5.0)
+        .set(self.ids.explore_index, ui);
+
+        let (mut items, _) = List::flow_down(exploration.dead_ends().len())
+            .item_size(22.0)
+            .scrollbar_on_top()
+            .down_from(self.ids.explore_prev, 5.0)
+            .align_left_of(self.ids.explore_button)
+            .padded_w_of(self.ids.explore_panel, 5.0)
+            .h(200.0)
+            .set(self.ids.explore_dead_ends, ui);
+
+        while let Some(item) = items.next(ui) {
+            let index = exploration.dead_ends()[item.i];
+            let position = exploration.configurations()[index].agent.position();
+            let label = format!(
+                "Dead end {}: agent at ({}, {})",
+                index + 1,
+                position.x,
+                position.y
+            );
+
+            for _ in item.set(text_button().label(&label), ui) {
+                jump = Some(index);
+            }
+        }
+
+        if let Some(index) = jump {
+            self.jump_to_configuration(index);
+        }
+    }
+
+    /// Sets the widgets for watching a replay, or for watching the recorded moves,
+    /// above the script button. Returns the id of the topmost widget.
+    fn update_playback_controls(&mut self, ui: &mut UiCell) -> conrod_core::widget::Id {
+        let (position, len, playing) = match &self.playback {
+            Some(playback) => (
+                playback.position(),
+                playback.replay().len(),
+                playback.is_playing(),
+            ),
+            None => {
+                let num_moves = self.recording.as_ref().map_or(0, |r| r.len());
+
+                for _ in text_button()
+                    .label("Watch recorded moves")
+                    .up_from(self.ids.script_button, 5.0)
+                    .align_left_of(self.ids.script_button)
+                    .w_h(150.0, 25.0)
+                    .set(self.ids.playback_watch, ui)
+                {
+                    if let Some(recording) = self.recording.clone().filter(|r| !r.is_empty()) {
+                        self.watch_replay(recording);
+                    }
+                }
+
+                Text::new(&format!("Recorded {} moves", num_moves))
+                    .font_size(12)
+                    .right_from(self.ids.playback_watch, 5.0)
+                    .set(self.ids.playback_status, ui);
+
+                return self.ids.playback_watch;
+            }
+        };
+
+        for _ in text_button()
+            .label("|<")
+            .up_from(self.ids.script_button, 5.0)
+            .align_left_of(self.ids.script_button)
+            .w_h(25.0, 25.0)
+            .set(self.ids.playback_start, ui)
+        {
+            self.step_playback(-(position as isize));
+        }
+
+        for _ in text_button()
+            .label("<")
+            .right_from(self.ids.playback_start, 5.0)
+            .w_h(25.0, 25.0)
+            .set(self.ids.playback_prev, ui)
+        {
+            self.step_playback(-1);
+        }
+
+        for _ in text_button()
+            .label(if playing { "Pause" } else { "Play" })
+            .right_from(self.ids.playback_prev, 5.0)
+            .w_h(50.0, 25.0)
+            .set(self.ids.playback_toggle, ui)
+        {
+            self.toggle_playback();
+        }
+
+        for _ in text_button()
+            .label(">")
+            .right_from(self.ids.playback_toggle, 5.0)
+            .w_h(25.0, 25.0)
+            .set(self.ids.playback_next, ui)
+        {
+            self.step_playback(1);
+        }
+
+        Text::new(&format!("Move {} of {}", position, len))
+            .font_size(12)
+            .right_from(self.ids.playback_next, 5.0)
+            .set(self.ids.playback_status, ui);
+
+        if let Some(value) = Slider::new(position as f32, 0.0, len.max(1) as f32)
+            .color(Color::Rgba(0.9, 0.9, 0.9, 1.0))
+            .border(1.0)
+            .border_color(color::BLACK)
+            .up_from(self.ids.playback_start, 5.0)
+            .align_left_of(self.ids.script_button)
+            .padded_w_of(self.ids.explore_panel, 5.0)
+            .h(15.0)
+            .set(self.ids.playback_slider, ui)
+        {
+            let offset = value.round() as isize - position as isize;
+            if offset != 0 {
+                self.step_playback(offset);
+            }
+        }
+
+        self.ids.playback_slider
+    }
+
+    /// Sets the widgets of the panel for checking whether a region simulates a gadget
+    fn update_region_panel(&mut self, ui: &mut UiCell) {
+        let region = match &self.region {
+            Some(region) => region,
+            None => return,
+        };
+
+        sidebar_panel()
+            .middle_of(self.ids.left_sidebar)
+            .padded_wh_of(self.ids.left_sidebar, 10.0)
+            .set(self.ids.region_panel, ui);
+
+        let mut status = String::from(
+            "Click boundary edges to mark external ports\n\
+             Right click gadgets to cycle their states\n\
+             Escape to stop\n",
+        );
+        for (i, port) in region.ports().iter().enumerate() {
+            status += &format!(
+                "\nPort {}: ({}, {})",
+                i,
+                port.double_xy.x as f64 * 0.5,
+                port.double_xy.y as f64 * 0.5
+            );
+        }
+        status += &format!("\nRepresentatives: {}", region.representatives().len());
+
+        Text::new(&status)
+            .font_size(12)
+            .top_left_with_margin_on(self.ids.region_panel, 5.0)
+            .set(self.ids.region_status, ui);
+
+        for _ in text_button()
+            .label("Add current states as representative (M)")
+            .down_from(self.ids.region_status, 5.0)
+            .padded_w_of(self.ids.region_panel, 5.0)
+            .h(25.0)
+            .set(self.ids.region_add_rep, ui)
+        {
+            self.add_region_representative();
+        }
+
+        for _ in text_button()
+            .label("Clear representatives")
+            .down_from(self.ids.region_add_rep, 5.0)
+            .padded_w_of(self.ids.region_panel, 5.0)
+            .h(25.0)
+            .set(self.ids.region_clear_reps, ui)
+        {
+            self.clear_region_representatives();
+        }
+
+        for _ in text_button()
+            .label("Compose into new gadget (B)")
+            .down_from(self.ids.region_clear_reps, 5.0)
+            .padded_w_of(self.ids.region_panel, 5.0)
+            .h(25.0)
+            .set(self.ids.region_compose, ui)
+        {
+            self.compose_region();
+        }
+
+        let (mut items, _) = List::flow_down(self.gadget_select.len())
+            .item_size(22.0)
+            .scrollbar_on_top()
+            .down_from(self.ids.region_compose, 5.0)
+            .padded_w_of(self.ids.region_panel, 5.0)
+            .h(200.0)
+            .set(self.ids.region_defs, ui);
+
+        let mut check = None;
+
+        while let Some(item) = items.next(ui) {
+            let label = format!("Check against {}", self.gadget_select[item.i].name());
+
+            for _ in item.set(text_button().label(&label), ui) {
+                check = Some(item.i);
+            }
+        }
+
+        if let Some(index) = check {
+            self.check_region(index);
+        }
+
+        Text::new(&self.region_report)
+            .font_size(12)
+            .down_from(self.ids.region_defs, 5.0)
+            .padded_w_of(self.ids.region_panel, 5.0)
+            .set(self.ids.region_report, ui);
+    }
+
+    /// Sets the widgets of the panel for building a gadget def by hand
+    fn update_def_edit_panel(&mut self, ui: &mut UiCell) {
+        sidebar_panel()
+            .middle_of(self.ids.left_sidebar)
+            .padded_wh_of(self.ids.left_sidebar, 10.0)
+            .set(self.ids.def_panel, ui);
+
+        Text::new(
+            "Click a cell to toggle the traversal\n\
+             from the row's port to the column's port\n\
+             Escape to stop",
+        )
+        .font_size(12)
+        .top_left_with_margin_on(self.ids.def_panel, 5.0)
+        .set(self.ids.def_help, ui);
+
+        let editor = &self.def_editor;
+        let (from, to) = editor.shown_states();
+        let port = editor.selected_port();
+        let (w, h) = editor.size();
+
+        let rows = [
+            format!("States: {}", editor.num_states()),
+            format!("Ports: {}", editor.num_ports()),
+            format!("Width: {}", w),
+            format!("Height: {}", h),
+            format!("Selected port: {}", port.id()),
+            format!(
+                "Port {} position: {}",
+                port.id(),
+                editor.port_map().get(port.id()).copied().unwrap_or(0)
+            ),
+            format!("From state: {}", from.id()),
+            format!("To state: {}", to.id()),
+        ];
+
+        let mut id_gen = ui.widget_id_generator();
+        self.ids.def_row_texts.resize(rows.len(), &mut id_gen);
+        self.ids.def_row_decs.resize(rows.len(), &mut id_gen);
+        self.ids.def_row_incs.resize(rows.len(), &mut id_gen);
+
+        let mut change = None;
+        let mut above = self.ids.def_help;
+
+        for (i, row) in rows.iter().enumerate() {
+            let text_id = self.ids.def_row_texts[i];
+            let dec_id = self.ids.def_row_decs[i];
+            let inc_id = self.ids.def_row_incs[i];
+
+            Text::new(row)
+                .font_size(12)
+                .down_from(above, 8.0)
+                .align_left_of(self.ids.def_help)
+                .set(text_id, ui);
+
+            for _ in text_button()
+                .label("+")
+                .w_h(25.0, 20.0)
+                .mid_right_with_margin_on(self.ids.def_panel, 5.0)
+                .align_middle_y_of(text_id)
+                .set(inc_id, ui)
+            {
+                change = Some((i, 1));
+            }
+
+            for _ in text_button()
+                .label("-")
+                .w_h(25.0, 20.0)
+                .left_from(inc_id, 5.0)
+                .set(dec_id, ui)
+            {
+                change = Some((i, -1));
+            }
+
+            above = text_id;
+        }
+
+        if let Some((row, delta)) = change {
+            let editor = &mut self.def_editor;
+            let add = |n: usize| (n as isize + delta).max(0) as usize;
+            let wrap = |n: usize, len: usize| (n + len).wrapping_add(delta as usize) % len.max(1);
+
+            match row {
+                0 => editor.set_num_states(add(editor.num_states())),
+                1 => {
+                    editor.set_num_ports(add(editor.num_ports()));
+                }
+                2 => {
+                    editor.set_size((add(w), h));
+                }
+                3 => {
+                    editor.set_size((w, add(h)));
+                }
+                4 => editor.select_port(Port(wrap(port.id(), editor.num_ports()))),
+                5 => {
+                    if port.id() < editor.num_ports() {
+                        editor.move_port(port, delta);
+                    }
+                }
+                6 => editor.set_shown_states((State(wrap(from.id(), editor.num_states())), to)),
+                _ => editor.set_shown_states((from, State(wrap(to.id(), editor.num_states())))),
+            }
+
+            self.def_editor_report.clear();
+        }
+
+        let editor = &self.def_editor;
+        let (from, to) = editor.shown_states();
+        let num_ports = editor.num_ports();
+
+        let mut toggled = vec![];
+
+        if num_ports > 0 {
+            let mut elements = Matrix::new(num_ports, num_ports)
+                .down_from(above, 10.0)
+                .align_left_of(self.ids.def_help)
+                .w_h(25.0 * num_ports as f64, 25.0 * num_ports as f64)
+                .set(self.ids.def_matrix, ui);
+
+            while let Some(element) = elements.next(ui) {
+                let traversal = ((from, Port(element.row)), (to, Port(element.col)));
+                let value = editor.has_traversal(traversal);
+                let label = format!("{}:{}", element.row, element.col);
+
+                let toggle = Toggle::new(value)
+                    .label(&label)
+                    .label_font_size(10)
+                    .color(if value {
+                        Color::Rgba(0.4, 0.8, 0.4, 1.0)
+                    } else {
+                        Color::Rgba(0.9, 0.9, 0.9, 1.0)
+                    })
+                    .border(1.0)
+                    .border_color(color::BLACK);
+
+                for _ in element.set(toggle, ui) {
+                    toggled.push(traversal);
+                }
+            }
+        }
+
+        // Preview of the gadget in the from state
+        let preview = editor.build().map(|mut gadget| {
+            gadget.set_state(from);
+            gadget
+        });
+        let below_matrix = if num_ports > 0 {
+            self.ids.def_matrix
+        } else {
+            above
+        };
+
+        if let Ok(gadget) = &preview {
+            Button::triangles(Triangles3d::from_gadget(gadget))
+                .padding(5.0)
+                .w_h(100.0, 100.0)
+                .down_from(below_matrix, 10.0)
+                .align_left_of(self.ids.def_help)
+                .set(self.ids.def_preview, ui);
+        }
+
+        for traversal in toggled {
+            self.def_editor.toggle_traversal(traversal);
+            self.def_editor_report.clear();
+        }
+
+        for _ in text_button()
+            .label("Add to gadgets")
+            .down_from(
+                if preview.is_ok() {
+                    self.ids.def_preview
+                } else {
+                    below_matrix
+                },
+                10.0,
+            )
+            .padded_w_of(self.ids.def_panel, 5.0)
+            .h(25.0)
+            .set(self.ids.def_add, ui)
+        {
+            self.add_edited_def();
+        }
+
+        for _ in text_button()
+            .label("Reset")
+            .down_from(self.ids.def_add, 5.0)
+            .padded_w_of(self.ids.def_panel, 5.0)
+            .h(25.0)
+            .set(self.ids.def_reset, ui)
+        {
+            self.def_editor = DefEditor::new();
+            self.def_editor_report.clear();
+        }
+
+        for _ in text_button()
+            .label("Export gadget library (Ctrl + E)")
+            .down_from(self.ids.def_reset, 15.0)
+            .padded_w_of(self.ids.def_panel, 5.0)
+            .h(25.0)
+            .set(self.ids.def_export, ui)
+        {
+            crate::export_library(&self.custom_gadgets());
+        }
+
+        for _ in text_button()
+            .label("Import gadget library (Ctrl + O)")
+            .down_from(self.ids.def_export, 5.0)
+            .padded_w_of(self.ids.def_panel, 5.0)
+            .h(25.0)
+            .set(self.ids.def_import, ui)
+        {
+            crate::import_library();
+        }
+
+        Text::new(&self.def_editor_report)
+            .font_size(12)
+            .down_from(self.ids.def_import, 5.0)
+            .padded_w_of(self.ids.def_panel, 5.0)
+            .set(self.ids.def_report, ui);
     }
 
     pub fn render_ui(&mut self, ui: &mut Ui, width: f64, height: f64) {
@@ -736,3 +1558,21 @@ impl<'a> App<'a> {
         self.ui_renderer.draw_end();
     }
 }
+
+/// A button with a text label, for use in panels
+fn text_button<'a>() -> TextButton<'a, Flat> {
+    TextButton::new()
+        .color(Color::Rgba(0.9, 0.9, 0.9, 1.0))
+        .border(1.0)
+        .border_color(color::BLACK)
+        .label_font_size(12)
+}
+
+/// The background of a panel in the left sidebar
+fn sidebar_panel() -> BorderedRectangle {
+    BorderedRectangle::new([1.0, 1.0]).with_style(bordered_rectangle::Style {
+        color: Some(Color::Rgba(0.8, 0.9, 0.8, 1.0)),
+        border: None,
+        border_color: Some(color::BLACK),
+    })
+}
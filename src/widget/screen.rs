@@ -157,6 +157,23 @@ impl<'a> ContraptionScreen<'a> {
         state.pressed ^= state.prev_input;
     }
 
+    /// Snaps a position to the center of the nearest edge of the grid
+    fn snap_to_edge(position: Vec2) -> Vec2 {
+        let Vec2 { mut x, y } = position;
+        x -= 0.5;
+
+        let mut xx = x - y;
+        let mut yy = x + y;
+
+        xx = xx.round();
+        yy = yy.round();
+
+        let x = (xx + yy) * 0.5 + 0.5;
+        let y = (-xx + yy) * 0.5;
+
+        vec2(x, y)
+    }
+
     fn update_paint_tile(self, args: widget::UpdateArgs<Self>) -> <Self as Widget>::Event {
         let id = args.id;
         let state = args.state;
@@ -212,22 +229,12 @@ impl<'a> ContraptionScreen<'a> {
                 if mouse.is_over() {
                     let (_w, _h) = rect.w_h();
 
-                    let Vec2 { mut x, y } = state.position;
-                    x -= 0.5;
-
-                    let mut xx = x - y;
-                    let mut yy = x + y;
-
-                    xx = xx.round();
-                    yy = yy.round();
-
-                    let x = (xx + yy) * 0.5 + 0.5;
-                    let y = (-xx + yy) * 0.5;
+                    let position = Self::snap_to_edge(state.position);
 
                     if state.pressed.is_left() {
-                        events.push(Event::AgentPlace(vec2(x, y)));
+                        events.push(Event::AgentPlace(position));
                     } else {
-                        events.push(Event::AgentHover(vec2(x, y)));
+                        events.push(Event::AgentHover(position));
                     }
                 }
             }
@@ -236,6 +243,55 @@ impl<'a> ContraptionScreen<'a> {
         events
     }
 
+    fn update_play(self, args: widget::UpdateArgs<Self>) -> <Self as Widget>::Event {
+        let id = args.id;
+        let state = args.state;
+        let ui = args.ui;
+
+        let Self { camera: _, .. } = self;
+
+        let mut events = vec![];
+
+        if let Some(mouse) = ui.widget_input(id).mouse() {
+            if mouse.is_over() && state.pressed.is_left() {
+                events.push(Event::PlayClick(state.position));
+            }
+
+            if mouse.is_over() && state.pressed.is_right() {
+                events.push(Event::PlayTarget(Self::snap_to_edge(state.position)));
+            }
+        }
+
+        events
+    }
+
+    fn update_region_edit(self, args: widget::UpdateArgs<Self>) -> <Self as Widget>::Event {
+        let id = args.id;
+        let state = args.state;
+        let ui = args.ui;
+
+        let Self { camera: _, .. } = self;
+
+        let mut events = vec![];
+
+        if let Some(mouse) = ui.widget_input(id).mouse() {
+            if mouse.is_over() {
+                if state.pressed.is_left() {
+                    events.push(Event::RegionPortToggle(Self::snap_to_edge(state.position)));
+                }
+
+                if state.pressed.is_right() {
+                    let x = state.position[0].floor() as isize;
+                    let y = state.position[1].floor() as isize;
+
+                    events.push(Event::RegionCycleState(vec2(x, y)));
+                }
+            }
+        }
+
+        events
+    }
+
     fn update_select(self, args: widget::UpdateArgs<Self>) -> <Self as Widget>::Event {
         let id = args.id;
         let state = args.state;
@@ -358,6 +414,10 @@ pub enum Event {
     AgentPlace(Vec2),
     /// Mouse moved over (X, Y) in agent place mode
     AgentHover(Vec2),
+    /// Clicked at (X, Y) in play mode
+    PlayClick(Vec2),
+    /// Chose the edge centered at (X, Y) as the target to solve for in play mode
+    PlayTarget(Vec2),
     /// Screen panned by a difference of (X, Y)
     Pan(Vec2),
     /// Screen zoomed at (X, Y) by some amount
@@ -368,6 +428,10 @@ pub enum Event {
     Select(Rect, SelectFunc),
     /// Finished moving gadgets
     GadgetMoveFinish,
+    /// Toggled whether the edge centered at (X, Y) is an external port of the region
+    RegionPortToggle(Vec2),
+    /// Cycled the state of the region gadget at (X, Y)
+    RegionCycleState(XY),
     /// Pasted copied selection
     GadgetPaste(XY),
     /// Communicates the position of the mouse in the grid
@@ -456,9 +520,11 @@ impl<'a> Widget for ContraptionScreen<'a> {
         vec.append(&mut match self.mode {
             Mode::TilePaint => self.update_paint_tile(args),
             Mode::AgentPlace => self.update_place_agent(args),
+            Mode::Play => self.update_play(args),
             Mode::Select => self.update_select(args),
             Mode::GadgetMove => self.update_gadget_move(args),
             Mode::GadgetPaste => self.update_gadget_paste(args),
+            Mode::RegionEdit => self.update_region_edit(args),
             _ => vec![],
         });
 
@@ -23,6 +23,8 @@ pub struct SelectionGrid<'a> {
     style: Style,
     size: WH,
     gadgets: &'a [Gadget],
+    /// Property descriptions of the gadgets, shown in their tooltips
+    properties: &'a [String],
     /// Index of selected gadget
     selected: Option<usize>,
 }
@@ -41,12 +43,18 @@ pub struct Style {
 }
 
 impl<'a> SelectionGrid<'a> {
-    pub fn new(width: usize, gadgets: &'a [Gadget], selected: Option<usize>) -> Self {
+    pub fn new(
+        width: usize,
+        gadgets: &'a [Gadget],
+        properties: &'a [String],
+        selected: Option<usize>,
+    ) -> Self {
         Self {
             common: widget::CommonBuilder::default(),
             style: Style::default(),
             size: (width, (gadgets.len() + width - 1) / width),
             gadgets,
+            properties,
             selected,
         }
     }
@@ -105,6 +113,7 @@ impl<'a> Widget for SelectionGrid<'a> {
         let Self {
             size: (size_w, size_h),
             gadgets,
+            properties,
             ..
         } = self;
 
@@ -145,9 +154,11 @@ impl<'a> Widget for SelectionGrid<'a> {
                 //    event = Some(name.to_owned());
                 //}
 
+                let tooltip = format!("{}\n{}", gadget.name(), properties[i]);
+
                 let button = Button::triangles(Triangles3d::from_gadget(gadget))
                     .padding(5.0)
-                    .tooltip_text(gadget.name());
+                    .tooltip_text(&tooltip);
 
                 for _ in element.set(button, ui) {
                     event = Some(i);
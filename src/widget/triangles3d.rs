@@ -6,7 +6,7 @@ use conrod_derive::WidgetCommon;
 use crate::gadget::Gadget;
 
 use crate::math::Vec2;
-use crate::render::{TrianglesEx, UiRenderer};
+use crate::render::{GadgetEx, TrianglesEx, UiRenderer};
 
 /// Triangles, but in 3D
 #[derive(Debug, WidgetCommon)]